@@ -0,0 +1,80 @@
+use std::sync::Arc;
+
+use serde_derive::Serialize;
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+use crate::errors::AuditLogError;
+use crate::util::now;
+
+pub const DEFAULT_AUDIT_LOG_FILE: &str = "./threematrix_audit.log";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditAction {
+    Bind,
+    Unbind,
+}
+
+/// One line of the audit log
+#[derive(Debug, Serialize)]
+pub struct AuditEntry<'a> {
+    /// Unix timestamp in seconds
+    pub timestamp: u64,
+    pub action: AuditAction,
+    /// Matrix user id or Threema id of the user who triggered the action
+    pub actor: &'a str,
+    pub room_id: &'a str,
+    /// Readable Threema group id
+    pub group_id: &'a str,
+}
+
+/// Append-only log of binding changes, written as JSON lines
+#[derive(Clone)]
+pub struct AuditLog {
+    path: String,
+    lock: Arc<Mutex<()>>,
+}
+
+impl AuditLog {
+    pub fn new(path: &str) -> AuditLog {
+        return AuditLog {
+            path: path.to_owned(),
+            lock: Arc::new(Mutex::new(())),
+        };
+    }
+
+    pub async fn record(
+        &self,
+        action: AuditAction,
+        actor: &str,
+        room_id: &str,
+        group_id: &str,
+    ) -> Result<(), AuditLogError> {
+        let entry = AuditEntry {
+            timestamp: now(),
+            action,
+            actor,
+            room_id,
+            group_id,
+        };
+        let mut line =
+            serde_json::to_string(&entry).map_err(|e| AuditLogError::SerializationError(e))?;
+        line.push('\n');
+
+        let _guard = self.lock.lock().await;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .map_err(|e| AuditLogError::IoError(e))?;
+        file.write_all(line.as_bytes())
+            .await
+            .map_err(|e| AuditLogError::IoError(e))?;
+        // Tokio writes in the background, so the entry is only complete after the flush
+        file.flush().await.map_err(|e| AuditLogError::IoError(e))?;
+        return Ok(());
+    }
+}
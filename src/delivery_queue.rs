@@ -0,0 +1,265 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{debug, error, warn};
+use matrix_sdk::ruma::events::room::message::MessageType;
+use matrix_sdk::ruma::{OwnedEventId, OwnedRoomId};
+use serde_derive::{Deserialize, Serialize};
+use threema_gateway::IncomingMessage;
+use tokio::sync::{Mutex, Notify};
+use tokio::time::sleep;
+
+use crate::errors::{DeliveryError, StoreError};
+
+pub const THREEMA_TO_MATRIX_TREE_NAME: &str = "jobs_threema_to_matrix";
+pub const MATRIX_TO_THREEMA_TREE_NAME: &str = "jobs_matrix_to_threema";
+/// Number of attempts to deliver a job, before it is given up
+pub const MAX_DELIVERY_ATTEMPTS: u32 = 5;
+pub const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(2);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Result of a successful delivery attempt
+#[derive(Debug, PartialEq)]
+pub enum DeliveryOutcome {
+    Delivered,
+    /// The job is delivered later and has to be completed then with [`DeliveryQueue::complete`]
+    Deferred,
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum DeliveryJob {
+    /// Threema message as received from the gateway. It is only decrypted by the worker, so that
+    /// the gateway gets its response without waiting for key lookups or blob downloads.
+    ThreemaToMatrix(ReceivedThreemaMessage),
+    /// Message from a Matrix room, which is sent to the bound Threema group
+    MatrixToThreema {
+        room_id: OwnedRoomId,
+        /// Event of the message, so that delivery receipts can be shown in Matrix
+        #[serde(default)]
+        event_id: Option<OwnedEventId>,
+        group_id: Vec<u8>,
+        group_creator: Option<String>,
+        message: MatrixMessage,
+    },
+    /// Answer to a sync request for a Threema group, which we created
+    GroupSync { receiver: String, group_id: Vec<u8> },
+}
+
+/// Message from a Matrix room. Attachments are only downloaded by the worker.
+#[derive(Serialize, Deserialize)]
+pub enum MatrixMessage {
+    /// Text in Threema markup
+    Text(String),
+    /// Event content of an image, file, audio or video, which refers to the media on the
+    /// homeserver
+    Attachment {
+        content: MessageType,
+        sender_name: String,
+    },
+}
+
+/// Storable copy of an [`IncomingMessage`]
+#[derive(Serialize, Deserialize)]
+pub struct ReceivedThreemaMessage {
+    pub from: String,
+    pub to: String,
+    pub message_id: String,
+    pub date: usize,
+    #[serde(with = "hex_bytes")]
+    pub nonce: Vec<u8>,
+    #[serde(with = "hex_bytes")]
+    pub box_data: Vec<u8>,
+    pub nickname: Option<String>,
+}
+
+impl From<&IncomingMessage> for ReceivedThreemaMessage {
+    fn from(message: &IncomingMessage) -> ReceivedThreemaMessage {
+        return ReceivedThreemaMessage {
+            from: message.from.clone(),
+            to: message.to.clone(),
+            message_id: message.message_id.clone(),
+            date: message.date,
+            nonce: message.nonce.clone(),
+            box_data: message.box_data.clone(),
+            nickname: message.nickname.clone(),
+        };
+    }
+}
+
+impl From<ReceivedThreemaMessage> for IncomingMessage {
+    fn from(message: ReceivedThreemaMessage) -> IncomingMessage {
+        return IncomingMessage {
+            from: message.from,
+            to: message.to,
+            message_id: message.message_id,
+            date: message.date,
+            nonce: message.nonce,
+            box_data: message.box_data,
+            nickname: message.nickname,
+        };
+    }
+}
+
+/// Stores bytes as hex string instead of a JSON array of numbers
+mod hex_bytes {
+    use data_encoding::HEXLOWER;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        return serializer.serialize_str(&HEXLOWER.encode(bytes));
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        return HEXLOWER
+            .decode(hex.as_bytes())
+            .map_err(|e| D::Error::custom(e));
+    }
+}
+
+/// Durable FIFO queue of delivery jobs. Jobs stay in the on-disk store until they are completed,
+/// so that jobs, which were not delivered before a shutdown or crash, are retried after a restart.
+#[derive(Clone)]
+pub struct DeliveryQueue {
+    db: sled::Db,
+    tree: sled::Tree,
+    job_ids: Arc<Mutex<VecDeque<u64>>>,
+    notify: Arc<Notify>,
+    retry_delay: Duration,
+}
+
+impl DeliveryQueue {
+    pub fn open(db: &sled::Db, tree_name: &str) -> Result<DeliveryQueue, StoreError> {
+        let tree = db
+            .open_tree(tree_name)
+            .map_err(|e| StoreError::DbError(e))?;
+
+        // Keys are big endian, so the stored jobs are iterated in insertion order
+        let mut job_ids = VecDeque::new();
+        for key in tree.iter().keys() {
+            let key = key.map_err(|e| StoreError::DbError(e))?;
+            match <[u8; 8]>::try_from(key.as_ref()) {
+                Ok(key) => job_ids.push_back(u64::from_be_bytes(key)),
+                Err(_) => error!("Store: Ignoring job with invalid id in {}", tree_name),
+            }
+        }
+        debug!("Store: Loaded {} jobs from {}", job_ids.len(), tree_name);
+
+        return Ok(DeliveryQueue {
+            db: db.clone(),
+            tree,
+            job_ids: Arc::new(Mutex::new(job_ids)),
+            notify: Arc::new(Notify::new()),
+            retry_delay: DEFAULT_RETRY_DELAY,
+        });
+    }
+
+    /// Sets the delay before the first retry of a job, it doubles with every further attempt
+    pub fn set_retry_delay(&mut self, retry_delay: Duration) {
+        self.retry_delay = retry_delay;
+    }
+
+    /// Stores a job. It is written to disk with the next periodic flush of the store, use
+    /// [`DeliveryQueue::flush`], if it has to be on disk right away.
+    pub async fn push(&self, job: &DeliveryJob) -> Result<(), StoreError> {
+        let serialized = serde_json::to_vec(job).map_err(|e| StoreError::SerializationError(e))?;
+        let job_id = self.db.generate_id().map_err(|e| StoreError::DbError(e))?;
+        self.tree
+            .insert(job_id.to_be_bytes(), serialized)
+            .map_err(|e| StoreError::DbError(e))?;
+
+        self.job_ids.lock().await.push_back(job_id);
+        self.notify.notify_one();
+        return Ok(());
+    }
+
+    /// Writes the stored jobs to disk
+    pub async fn flush(&self) -> Result<(), StoreError> {
+        self.tree
+            .flush_async()
+            .await
+            .map_err(|e| StoreError::DbError(e))?;
+        return Ok(());
+    }
+
+    /// Waits for the next job. It has to be marked as completed with [`DeliveryQueue::complete`].
+    pub async fn next(&self) -> (u64, DeliveryJob) {
+        loop {
+            let job_id = self.job_ids.lock().await.pop_front();
+            match job_id {
+                Some(job_id) => match self.tree.get(job_id.to_be_bytes()) {
+                    Ok(Some(job)) => match serde_json::from_slice(&job) {
+                        Ok(job) => return (job_id, job),
+                        Err(e) => {
+                            error!("Store: Dropping job {}, which can't be read: {}", job_id, e);
+                            if let Err(e) = self.complete(job_id) {
+                                error!("Store: Could not remove job {}: {}", job_id, e);
+                            }
+                        }
+                    },
+                    Ok(None) => {}
+                    Err(e) => error!("Store: Could not load job {}: {}", job_id, e),
+                },
+                None => self.notify.notified().await,
+            }
+        }
+    }
+
+    /// Tries to deliver a job, until it was delivered, failed permanently or ran out of attempts,
+    /// and completes it afterwards. Temporary failures are retried with exponential backoff.
+    /// `deliver` is told, whether it is the last attempt, e.g. to report the failure only once.
+    /// Deferred jobs are kept, so that they are retried after a restart, if they were not completed.
+    pub async fn deliver<F, Fut>(&self, job_id: u64, mut deliver: F) -> Result<(), DeliveryError>
+    where
+        F: FnMut(bool) -> Fut,
+        Fut: Future<Output = Result<DeliveryOutcome, DeliveryError>>,
+    {
+        let mut attempt = 1;
+        let result = loop {
+            match deliver(attempt == MAX_DELIVERY_ATTEMPTS).await {
+                Err(DeliveryError::Temporary(e)) if attempt < MAX_DELIVERY_ATTEMPTS => {
+                    let delay = self
+                        .retry_delay
+                        .saturating_mul(2u32.saturating_pow(attempt - 1))
+                        .min(MAX_RETRY_DELAY);
+                    warn!(
+                        "Store: Delivery of job {} failed, retrying in {}s: {}",
+                        job_id,
+                        delay.as_secs(),
+                        e
+                    );
+                    sleep(delay).await;
+                    attempt += 1;
+                }
+                Ok(DeliveryOutcome::Deferred) => {
+                    debug!("Store: Delivery of job {} was deferred", job_id);
+                    return Ok(());
+                }
+                Ok(DeliveryOutcome::Delivered) => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+        if let Err(e) = self.complete(job_id) {
+            error!("Store: Could not remove job {}: {}", job_id, e);
+        }
+        return result;
+    }
+
+    pub fn complete(&self, job_id: u64) -> Result<(), StoreError> {
+        self.tree
+            .remove(job_id.to_be_bytes())
+            .map_err(|e| StoreError::DbError(e))?;
+        return Ok(());
+    }
+
+    pub async fn len(&self) -> usize {
+        return self.job_ids.lock().await.len();
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        return self.job_ids.lock().await.is_empty();
+    }
+}
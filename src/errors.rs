@@ -1,7 +1,11 @@
+use matrix_sdk::ruma::IdParseError;
+use matrix_sdk::ClientBuildError;
 use std::num::ParseIntError;
 use std::string::FromUtf8Error;
 use thiserror::Error;
-use threema_gateway::errors::{ApiError, CryptoError};
+use threema_gateway::errors::{ApiError, CryptoError, FileMessageBuilderError};
+
+use crate::threema::types::{GroupSendReport, MessageType};
 
 #[derive(Debug, Error)]
 pub enum SendGroupMessageError {
@@ -9,6 +13,14 @@ pub enum SendGroupMessageError {
     GroupNotInCache,
     #[error("{0}")]
     ApiError(ApiError),
+    #[error("Invalid file: {0}")]
+    InvalidFile(FileMessageBuilderError),
+    #[error("Too many messages are waiting for the group members to become known")]
+    PendingQueueFull,
+    #[error("{0}")]
+    PartialDelivery(GroupSendReport),
+    #[error("Could not serialize message: {0}")]
+    SerializationError(serde_json::Error),
 }
 
 #[derive(Debug, Error)]
@@ -19,11 +31,22 @@ pub enum ProcessIncomingMessageError {
     ApiError(ApiError),
     #[error("{0}")]
     Utf8ConvertError(FromUtf8Error),
-    #[error("Unknown Message Type")]
-    UnknownMessageTypeError,
+    #[error("Unknown message type {0:#04x}")]
+    UnknownMessageType(u8),
+    #[error("Message type {0:?} is not supported")]
+    UnsupportedMessageType(MessageType),
+    #[error("Message payload is too short")]
+    InvalidPayloadLength,
+    #[error("Unknown delivery receipt status {0:#02x}")]
+    UnknownDeliveryReceiptStatus(u8),
+    #[error("Invalid file message: {0}")]
+    InvalidFileMessage(String),
+    #[error("{0}")]
+    StoreError(StoreError),
+    #[error("{0} is not the creator of the group")]
+    NotGroupCreator(String),
 }
 
-
 #[derive(Debug, Error)]
 pub enum StringifyGroupIdError {
     #[error("Group Id is empty")]
@@ -37,3 +60,69 @@ pub enum ParseGroupIdError {
     #[error("Group Id chars should be between 0 and 255 : {0}")]
     EncodingError(ParseIntError),
 }
+
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error("Store error: {0}")]
+    DbError(sled::Error),
+    #[error("Could not (de)serialize stored value: {0}")]
+    SerializationError(serde_json::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum AppserviceError {
+    #[error("{0}")]
+    MatrixError(matrix_sdk::Error),
+    #[error("{0}")]
+    ClientBuildError(ClientBuildError),
+    #[error("Invalid Matrix user id: {0}")]
+    InvalidUserId(IdParseError),
+    #[error("Puppet could not join the Matrix room")]
+    PuppetNotJoined,
+}
+
+#[derive(Debug, Error)]
+pub enum SessionError {
+    #[error("{0}")]
+    MatrixError(Box<matrix_sdk::Error>),
+    #[error("{0}")]
+    IoError(std::io::Error),
+    #[error("{0}")]
+    SerializationError(serde_json::Error),
+    #[error("Invalid Matrix user id: {0}")]
+    InvalidUserId(IdParseError),
+    #[error("Neither password nor access token of the Matrix user is configured")]
+    MissingCredentials,
+}
+
+#[derive(Debug, Error)]
+pub enum AuditLogError {
+    #[error("Could not write audit log: {0}")]
+    IoError(std::io::Error),
+    #[error("{0}")]
+    SerializationError(serde_json::Error),
+}
+
+/// Failed attempt to deliver a queued job
+#[derive(Debug, Error)]
+pub enum DeliveryError {
+    /// The job is tried again later, e.g. because a server could not be reached
+    #[error("{0}")]
+    Temporary(String),
+    /// Trying again would not help or would deliver the message twice
+    #[error("{0}")]
+    Permanent(String),
+}
+
+impl DeliveryError {
+    /// Network and server errors of the gateway are temporary
+    pub fn from_api_error(e: &ApiError) -> DeliveryError {
+        return match e {
+            ApiError::ServerError
+            | ApiError::RequestError(_)
+            | ApiError::IoError(_)
+            | ApiError::Other(_) => DeliveryError::Temporary(e.to_string()),
+            _ => DeliveryError::Permanent(e.to_string()),
+        };
+    }
+}
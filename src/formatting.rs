@@ -0,0 +1,503 @@
+use matrix_sdk::ruma::events::room::message::RoomMessageEventContent;
+
+use crate::reply::{format_threema_quote, ThreemaQuote};
+
+/// Markers of Threema's text markup and the HTML tags they stand for
+const MARKUP: [(char, &str); 3] = [('*', "strong"), ('_', "em"), ('~', "del")];
+const CODE_BLOCK_FENCE: &str = "```";
+const URL_SCHEMES: [&str; 2] = ["https://", "http://"];
+/// Characters, which end a sentence rather than a link
+const URL_TRAILING_PUNCTUATION: [char; 8] = ['.', ',', ':', ';', '!', '?', ')', '\''];
+/// Threema limits nicknames to 32 bytes, but other gateways may not
+const NICKNAME_MAX_CHARS: usize = 32;
+
+/// Builds the Matrix message of a Threema text, whose markup is converted to HTML. A quote, which
+/// could not be turned into a Matrix reply, is shown as block quote in front of the text. All
+/// Threema content is escaped, so that it can't inject markup.
+pub fn threema_text_to_matrix_content(
+    sender_name: Option<&str>,
+    text: &str,
+    quoted_text: Option<&str>,
+) -> RoomMessageEventContent {
+    let (body, html_body) = match sender_name {
+        Some(sender_name) => (
+            format!("{}: {}", sender_name, text),
+            format!(
+                "<strong>{}</strong>: {}",
+                escape_html(sender_name),
+                threema_to_html(text)
+            ),
+        ),
+        None => (text.to_owned(), threema_to_html(text)),
+    };
+    match quoted_text {
+        Some(quoted_text) => {
+            return RoomMessageEventContent::text_html(
+                format_threema_quote(&ThreemaQuote::Text(quoted_text.to_owned()), &body),
+                format!(
+                    "<blockquote>{}</blockquote>{}",
+                    threema_to_html(quoted_text),
+                    html_body
+                ),
+            );
+        }
+        None => return RoomMessageEventContent::text_html(body, html_body),
+    }
+}
+
+/// Builds the Threema caption of a Matrix attachment. The body is only added, if it is a caption
+/// rather than the file name, which Matrix clients usually put there.
+pub fn matrix_attachment_caption(sender_name: &str, body: &str, file_name: Option<&str>) -> String {
+    let body = body.trim();
+    if body.is_empty() || file_name.is_none_or(|file_name| file_name == body) {
+        return format!("*{}*", sender_name);
+    }
+    return format!("*{}*: {}", sender_name, body);
+}
+
+/// Removes line breaks, control and invisible characters (e.g. right-to-left overrides) from the
+/// nickname of a Threema user, so that it can't be used to fake further lines or senders. Returns
+/// `None`, if nothing is left.
+pub fn sanitize_nickname(nickname: &str) -> Option<String> {
+    let words: Vec<String> = nickname
+        .split(|c: char| c.is_whitespace() || c.is_control())
+        .map(|word| word.chars().filter(|c| !is_invisible(*c)).collect())
+        .filter(|word: &String| !word.is_empty())
+        .collect();
+    let sanitized: String = words.join(" ").chars().take(NICKNAME_MAX_CHARS).collect();
+    let sanitized = sanitized.trim_end();
+    if sanitized.is_empty() {
+        return None;
+    }
+    return Some(sanitized.to_owned());
+}
+
+/// Format characters, which change the direction or appearance of the surrounding text
+fn is_invisible(c: char) -> bool {
+    return matches!(
+        c,
+        '\u{00ad}'
+            | '\u{034f}'
+            | '\u{061c}'
+            | '\u{115f}'
+            | '\u{1160}'
+            | '\u{180e}'
+            | '\u{200b}'..='\u{200f}'
+            | '\u{202a}'..='\u{202e}'
+            | '\u{2060}'..='\u{206f}'
+            | '\u{3164}'
+            | '\u{feff}'
+            | '\u{ffa0}'
+            | '\u{fff9}'..='\u{fffb}'
+    );
+}
+
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    return escaped;
+}
+
+/// Converts a Threema text with *bold*, _italic_ and ~strikethrough~ markup, code and links into
+/// Matrix HTML. Everything else is escaped.
+pub fn threema_to_html(text: &str) -> String {
+    let mut html = String::new();
+    let mut rest = text;
+    while let Some((before, code, after)) = split_delimited(rest, CODE_BLOCK_FENCE) {
+        html.push_str(&text_to_html(before.strip_suffix('\n').unwrap_or(before)));
+        let code = code.strip_prefix('\n').unwrap_or(code);
+        let code = code.strip_suffix('\n').unwrap_or(code);
+        html.push_str(&format!("<pre><code>{}</code></pre>", escape_html(code)));
+        rest = after.strip_prefix('\n').unwrap_or(after);
+    }
+    html.push_str(&text_to_html(rest));
+    return html;
+}
+
+/// Splits the text at the first pair of delimiters, which enclose something. Returns the text
+/// before, between and after them.
+fn split_delimited<'a>(text: &'a str, delimiter: &str) -> Option<(&'a str, &'a str, &'a str)> {
+    let start = text.find(delimiter)?;
+    let content_start = start + delimiter.len();
+    let content_length = text[content_start..].find(delimiter)?;
+    if content_length == 0 {
+        return None;
+    }
+    let content_end = content_start + content_length;
+    return Some((
+        &text[..start],
+        &text[content_start..content_end],
+        &text[content_end + delimiter.len()..],
+    ));
+}
+
+/// Converts text without code blocks. Markup can't span several lines.
+fn text_to_html(text: &str) -> String {
+    let lines: Vec<String> = text.split('\n').map(|line| line_to_html(line)).collect();
+    return lines.join("<br>");
+}
+
+fn line_to_html(line: &str) -> String {
+    let mut html = String::new();
+    let mut rest = line;
+    while let Some((before, code, after)) = split_delimited(rest, "`") {
+        html.push_str(&links_to_html(before));
+        html.push_str(&format!("<code>{}</code>", escape_html(code)));
+        rest = after;
+    }
+    html.push_str(&links_to_html(rest));
+    return html;
+}
+
+fn links_to_html(text: &str) -> String {
+    let mut html = String::new();
+    let mut rest = text;
+    while let Some(start) = find_url(rest) {
+        html.push_str(&markup_to_html(&rest[..start]));
+        let url_and_rest = &rest[start..];
+        let end = url_and_rest
+            .find(char::is_whitespace)
+            .unwrap_or(url_and_rest.len());
+        let url_length = url_and_rest[..end]
+            .trim_end_matches(URL_TRAILING_PUNCTUATION)
+            .len();
+        let url = escape_html(&url_and_rest[..url_length]);
+        html.push_str(&format!("<a href=\"{}\">{}</a>", url, url));
+        rest = &url_and_rest[url_length..];
+    }
+    html.push_str(&markup_to_html(rest));
+    return html;
+}
+
+/// Returns the start of the first link, which begins a word
+fn find_url(text: &str) -> Option<usize> {
+    return text
+        .char_indices()
+        .filter(|(index, _)| {
+            text[..*index]
+                .chars()
+                .last()
+                .map(|previous| !previous.is_alphanumeric())
+                .unwrap_or(true)
+        })
+        .map(|(index, _)| index)
+        .find(|index| {
+            URL_SCHEMES.iter().any(|scheme| {
+                text[*index..].starts_with(scheme) && text.len() > index + scheme.len()
+            })
+        });
+}
+
+fn markup_to_html(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    return markup_chars_to_html(&chars);
+}
+
+/// A marker opens, if it starts a word, and closes, if it ends one. So snake_case or 2*3 stay as
+/// they are.
+fn markup_chars_to_html(chars: &[char]) -> String {
+    let mut html = String::new();
+    let mut index = 0;
+    while index < chars.len() {
+        let c = chars[index];
+        let tag = MARKUP
+            .iter()
+            .find(|(marker, _)| *marker == c)
+            .map(|(_, tag)| *tag);
+        let opens = index == 0 || !chars[index - 1].is_alphanumeric();
+        let followed_by_text = chars
+            .get(index + 1)
+            .map(|next| !next.is_whitespace())
+            .unwrap_or(false);
+
+        if let (Some(tag), true, true) = (tag, opens, followed_by_text) {
+            let closing = (index + 2..chars.len()).find(|&end| {
+                chars[end] == c
+                    && !chars[end - 1].is_whitespace()
+                    && chars
+                        .get(end + 1)
+                        .map(|next| !next.is_alphanumeric())
+                        .unwrap_or(true)
+            });
+            if let Some(closing) = closing {
+                html.push_str(&format!(
+                    "<{}>{}</{}>",
+                    tag,
+                    markup_chars_to_html(&chars[index + 1..closing]),
+                    tag
+                ));
+                index = closing + 1;
+                continue;
+            }
+        }
+        html.push_str(&escape_html(&c.to_string()));
+        index += 1;
+    }
+    return html;
+}
+
+enum HtmlToken<'a> {
+    Start { name: String, tag: &'a str },
+    End { name: String },
+    Text(&'a str),
+}
+
+fn tokenize_html(html: &str) -> Vec<HtmlToken<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = html;
+    while !rest.is_empty() {
+        let tag_start = match rest.find('<') {
+            Some(tag_start) => tag_start,
+            None => {
+                tokens.push(HtmlToken::Text(rest));
+                break;
+            }
+        };
+        if tag_start > 0 {
+            tokens.push(HtmlToken::Text(&rest[..tag_start]));
+        }
+        let tag_end = match rest[tag_start..].find('>') {
+            Some(tag_end) => tag_start + tag_end,
+            None => {
+                tokens.push(HtmlToken::Text(&rest[tag_start..]));
+                break;
+            }
+        };
+
+        let tag = &rest[tag_start + 1..tag_end];
+        let (closing, tag_content) = match tag.strip_prefix('/') {
+            Some(tag_content) => (true, tag_content),
+            None => (false, tag),
+        };
+        let name: String = tag_content
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric() || *c == '-')
+            .collect::<String>()
+            .to_ascii_lowercase();
+        // A tag starts with a letter, otherwise "<" is just text
+        if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            tokens.push(HtmlToken::Text(&rest[tag_start..tag_start + 1]));
+            rest = &rest[tag_start + 1..];
+            continue;
+        }
+        if closing {
+            tokens.push(HtmlToken::End { name });
+        } else {
+            tokens.push(HtmlToken::Start { name, tag });
+        }
+        rest = &rest[tag_end + 1..];
+    }
+    return tokens;
+}
+
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let value_start = tag.find(&format!("{}=", name))? + name.len() + 1;
+    let value = &tag[value_start..];
+    let value = match value.chars().next()? {
+        quote @ ('"' | '\'') => value[1..].split(quote).next()?,
+        _ => value
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()?,
+    };
+    return Some(unescape_html(value));
+}
+
+pub fn unescape_html(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest
+            .find(';')
+            .map(|end| &rest[1..end])
+            .filter(|entity| entity.len() <= 10);
+        let decoded = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => match entity.strip_prefix('#') {
+                Some(number) => match number.strip_prefix(['x', 'X']) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => number.parse::<u32>().ok(),
+                }
+                .and_then(char::from_u32),
+                None => None,
+            },
+        });
+        match (entity, decoded) {
+            (Some(entity), Some(decoded)) => {
+                unescaped.push(decoded);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    return unescaped;
+}
+
+fn ensure_line_break(text: &mut String) {
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+}
+
+/// Converts the formatted body of a Matrix message into Threema markup. Unsupported tags are
+/// dropped, but their text is kept. The reply fallback is removed.
+pub fn html_to_threema(html: &str) -> String {
+    let mut text = String::new();
+    // Start of the text of the open links and block quotes
+    let mut links: Vec<(usize, Option<String>)> = Vec::new();
+    let mut quotes: Vec<usize> = Vec::new();
+    let mut lists: Vec<Option<u32>> = Vec::new();
+    let mut skipped_depth = 0;
+    let mut pre_depth = 0;
+
+    for token in tokenize_html(html) {
+        if skipped_depth > 0 {
+            match token {
+                HtmlToken::Start { name, .. } if name == "mx-reply" => skipped_depth += 1,
+                HtmlToken::End { name } if name == "mx-reply" => skipped_depth -= 1,
+                _ => {}
+            }
+            continue;
+        }
+
+        match token {
+            HtmlToken::Text(content) => {
+                let content = unescape_html(content);
+                if pre_depth > 0 {
+                    text.push_str(&content);
+                } else {
+                    // Line breaks in HTML are only formatting
+                    text.push_str(&content.replace('\n', ""));
+                }
+            }
+            HtmlToken::Start { name, tag } => match name.as_str() {
+                "mx-reply" => skipped_depth = 1,
+                "strong" | "b" => text.push('*'),
+                "em" | "i" => text.push('_'),
+                "del" | "s" | "strike" => text.push('~'),
+                "code" if pre_depth == 0 => text.push('`'),
+                "br" => text.push('\n'),
+                "pre" => {
+                    ensure_line_break(&mut text);
+                    text.push_str(CODE_BLOCK_FENCE);
+                    text.push('\n');
+                    pre_depth += 1;
+                }
+                "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                    ensure_line_break(&mut text);
+                    text.push('*');
+                }
+                "p" | "div" => ensure_line_break(&mut text),
+                "blockquote" => {
+                    ensure_line_break(&mut text);
+                    quotes.push(text.len());
+                }
+                "ul" => {
+                    ensure_line_break(&mut text);
+                    lists.push(None);
+                }
+                "ol" => {
+                    ensure_line_break(&mut text);
+                    let start = attribute(tag, "start")
+                        .and_then(|start| start.parse().ok())
+                        .unwrap_or(1);
+                    lists.push(Some(start));
+                }
+                "li" => {
+                    ensure_line_break(&mut text);
+                    match lists.last_mut() {
+                        Some(Some(number)) => {
+                            text.push_str(&format!("{}. ", number));
+                            *number += 1;
+                        }
+                        _ => text.push_str("- "),
+                    }
+                }
+                "a" => links.push((text.len(), attribute(tag, "href"))),
+                "img" => {
+                    if let Some(alt) = attribute(tag, "alt") {
+                        text.push_str(&alt);
+                    }
+                }
+                _ => {}
+            },
+            HtmlToken::End { name } => match name.as_str() {
+                "strong" | "b" => text.push('*'),
+                "em" | "i" => text.push('_'),
+                "del" | "s" | "strike" => text.push('~'),
+                "code" if pre_depth == 0 => text.push('`'),
+                "pre" if pre_depth > 0 => {
+                    pre_depth -= 1;
+                    ensure_line_break(&mut text);
+                    text.push_str(CODE_BLOCK_FENCE);
+                    text.push('\n');
+                }
+                "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                    text.push('*');
+                    text.push('\n');
+                }
+                "p" => {
+                    ensure_line_break(&mut text);
+                    text.push('\n');
+                }
+                "div" | "li" => ensure_line_break(&mut text),
+                "ul" | "ol" => {
+                    lists.pop();
+                    ensure_line_break(&mut text);
+                }
+                "blockquote" => {
+                    if let Some(start) = quotes.pop() {
+                        let quoted: Vec<String> = text[start..]
+                            .trim()
+                            .lines()
+                            .map(|line| format!("> {}", line))
+                            .collect();
+                        text.truncate(start);
+                        text.push_str(&quoted.join("\n"));
+                        // Threema clients expect an empty line after a quote
+                        text.push_str("\n\n");
+                    }
+                }
+                "a" => {
+                    if let Some((start, Some(href))) = links.pop() {
+                        let link_text = text[start..].to_owned();
+                        // Matrix permalinks are kept as text, e.g. for mentions
+                        if link_text != href && !href.starts_with("https://matrix.to/") {
+                            text.push_str(&format!(" ({})", href));
+                        }
+                    }
+                }
+                _ => {}
+            },
+        }
+    }
+
+    // Block elements may leave more empty lines than needed
+    let mut collapsed = String::with_capacity(text.len());
+    for c in text.trim().chars() {
+        if c == '\n' && collapsed.ends_with("\n\n") {
+            continue;
+        }
+        collapsed.push(c);
+    }
+    return collapsed;
+}
@@ -1,38 +1,100 @@
+#![allow(clippy::needless_return, clippy::redundant_closure)]
+
+use std::collections::HashMap;
 use std::env::var;
 use std::fs::read_to_string;
+use std::io::Cursor;
 
-use actix_web::{http::header::ContentType, web, HttpResponse, Responder};
+use actix_web::http::header::{self, ContentType};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
 use log::{debug, error, info, warn};
+use matrix_sdk::attachment::{
+    AttachmentConfig, AttachmentInfo, BaseAudioInfo, BaseFileInfo, BaseImageInfo,
+    BaseThumbnailInfo, BaseVideoInfo, Thumbnail,
+};
 use matrix_sdk::event_handler::Ctx;
-use matrix_sdk::room::{Joined, Room};
+use matrix_sdk::media::{MediaFormat, MediaRequest};
+use matrix_sdk::room::{Joined, MessagesOptions, Room};
+use matrix_sdk::ruma::api::appservice::event::push_events;
+use matrix_sdk::ruma::events::reaction::{ReactionEventContent, Relation};
+use matrix_sdk::ruma::events::receipt::ReceiptEventContent;
 use matrix_sdk::ruma::events::room::message::{
-    MessageType, RoomMessageEventContent, TextMessageEventContent,
+    FormattedBody, InReplyTo, MessageFormat, MessageType, NoticeMessageEventContent,
+    Relation as MessageRelation, RoomMessageEventContent, TextMessageEventContent,
 };
-use matrix_sdk::ruma::events::OriginalSyncMessageLikeEvent;
-use matrix_sdk::ruma::TransactionId;
-use matrix_sdk::Client;
+use matrix_sdk::ruma::events::room::{MediaSource, ThumbnailInfo};
+use matrix_sdk::ruma::events::{
+    AnyMessageLikeEvent, AnyRoomEvent, MessageLikeEvent, OriginalSyncMessageLikeEvent,
+    SyncEphemeralRoomEvent,
+};
+use matrix_sdk::ruma::receipt::ReceiptType;
+use matrix_sdk::ruma::serde::Raw;
+use matrix_sdk::ruma::{OwnedEventId, RoomId, TransactionId, UInt};
+use matrix_sdk::{Client, RoomMember};
+use mime::Mime;
 use serde_derive::{Deserialize, Serialize};
 use threema_gateway::IncomingMessage;
 use tokio::sync::Mutex;
 
-use threema::types::Message;
+use threema::types::{
+    DeliveryReceiptMessage, DeliveryReceiptStatus, Message, MessageBase, OutgoingGroupMessage,
+    ThreemaFile, ThreemaThumbnail,
+};
 
+use crate::audit_log::{AuditAction, AuditLog};
+use crate::delivery_queue::{
+    DeliveryJob, DeliveryOutcome, DeliveryQueue, MatrixMessage, ReceivedThreemaMessage,
+};
+use crate::errors::{
+    AppserviceError, DeliveryError, ProcessIncomingMessageError, SendGroupMessageError,
+};
+use crate::formatting::{
+    html_to_threema, matrix_attachment_caption, threema_text_to_matrix_content,
+};
+use crate::matrix::appservice::Appservice;
+use crate::matrix::binding_index::BindingIndex;
+use crate::matrix::commands::{MatrixCommand, HELP_TEXT};
+use crate::matrix::pending_bindings::{PendingBindings, PENDING_BINDING_TTL};
+use crate::matrix::transaction_log::TransactionLog;
 use crate::matrix::util::{
     get_threematrix_room_state, set_threematrix_room_state, ThreematrixStateEventContent,
 };
+use crate::message_map::{BridgeDirection, BridgedMessage, MessageMap};
+use crate::reply::{
+    format_threema_quote, matches_quoted_text, parse_threema_quote, strip_matrix_reply_fallback,
+    ThreemaQuote,
+};
 use crate::threema::util::{
-    convert_group_id_from_readable_string, convert_group_id_to_readable_string,
+    convert_group_id_from_readable_string, convert_group_id_to_readable_string, is_valid_threema_id,
 };
 use crate::threema::ThreemaClient;
 
+pub mod audit_log;
+pub mod delivery_queue;
 pub mod errors;
+pub mod formatting;
 pub mod matrix;
+pub mod message_map;
+pub mod reply;
 pub mod threema;
 pub mod util;
 
+/// Reactions, which show delivery receipts of Threema users in Matrix
+const READ_REACTION_KEY: &str = "👀";
+const AGREED_REACTION_KEY: &str = "👍";
+const DISAGREED_REACTION_KEY: &str = "👎";
+
 pub struct AppState {
     pub threema_client: ThreemaClient,
     pub matrix_client: Mutex<Client>,
+    pub appservice: Option<Appservice>,
+    pub pending_bindings: PendingBindings,
+    pub audit_log: AuditLog,
+    pub binding_index: BindingIndex,
+    pub threema_to_matrix_queue: DeliveryQueue,
+    pub matrix_to_threema_queue: DeliveryQueue,
+    pub message_map: MessageMap,
+    pub transaction_log: TransactionLog,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -42,13 +104,36 @@ pub struct ThreemaConfig {
     pub gateway_own_id: String,
     pub port: Option<u16>,
     pub host: Option<String>,
+    pub pending_message_ttl: Option<u64>,
+    pub pending_message_limit: Option<usize>,
+    pub send_concurrency: Option<usize>,
+    pub pubkey_cache_ttl: Option<u64>,
+    pub send_read_receipts: Option<bool>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct MatrixConfig {
     pub homeserver_url: String,
     pub user: String,
-    pub password: String,
+    pub password: Option<String>,
+    pub access_token: Option<String>,
+    pub device_id: Option<String>,
+    pub session_file: Option<String>,
+    pub store_path: Option<String>,
+    pub store_passphrase: Option<String>,
+    pub appservice: Option<AppserviceConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AppserviceConfig {
+    pub id: String,
+    pub url: String,
+    pub as_token: String,
+    pub hs_token: String,
+    pub server_name: String,
+    pub sender_localpart: String,
+    pub puppet_prefix: Option<String>,
+    pub registration_file: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -56,11 +141,24 @@ pub struct LoggerConfig {
     pub level: String,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StoreConfig {
+    pub path: Option<String>,
+    pub message_retention: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AuditLogConfig {
+    pub path: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ThreematrixConfig {
     pub threema: ThreemaConfig,
     pub matrix: MatrixConfig,
     pub logger: Option<LoggerConfig>,
+    pub store: Option<StoreConfig>,
+    pub audit_log: Option<AuditLogConfig>,
 }
 
 impl ThreematrixConfig {
@@ -76,195 +174,1139 @@ impl ThreematrixConfig {
             config_from_file.threema.host = Some(host_from_env)
         };
         if let Ok(port_from_env) = port_from_env {
-            config_from_file.threema.port =
-                Some(port_from_env.parse::<u16>().expect("Invalid Port in environment"))
+            config_from_file.threema.port = Some(
+                port_from_env
+                    .parse::<u16>()
+                    .expect("Invalid Port in environment"),
+            )
         };
         return config_from_file;
     }
 }
 
+/// Queues the message from the Threema gateway for delivery to Matrix. It is decrypted by the
+/// worker, so that the gateway gets its response without waiting for Threema or Matrix.
 pub async fn threema_incoming_message_handler(
     incoming_message: web::Form<IncomingMessage>,
     app_state: web::Data<AppState>,
 ) -> impl Responder {
+    let job = DeliveryJob::ThreemaToMatrix(ReceivedThreemaMessage::from(&*incoming_message));
+    let queue = &app_state.threema_to_matrix_queue;
+    // The gateway does not deliver the message again after a successful response, so it has to
+    // be on disk already
+    if let Err(e) = async {
+        queue.push(&job).await?;
+        queue.flush().await
+    }
+    .await
+    {
+        // The gateway delivers the message again, if the response is not successful
+        error!("Threema: Could not queue incoming message: {}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    HttpResponse::Ok()
+        .content_type(ContentType::plaintext())
+        .body(())
+}
+
+/// Decrypts the queued Threema messages and delivers them to Matrix. Jobs are processed one after
+/// another, so that messages keep their order.
+pub async fn run_threema_to_matrix_worker(app_state: web::Data<AppState>) {
+    let queue = &app_state.threema_to_matrix_queue;
+    loop {
+        let (job_id, job) = queue.next().await;
+        let incoming_message = match job {
+            DeliveryJob::ThreemaToMatrix(received_message) => {
+                IncomingMessage::from(received_message)
+            }
+            DeliveryJob::MatrixToThreema { .. } | DeliveryJob::GroupSync { .. } => {
+                error!("Store: Ignoring outgoing message in Threema to Matrix queue");
+                if let Err(e) = queue.complete(job_id) {
+                    error!("Store: Could not remove job {}: {}", job_id, e);
+                }
+                continue;
+            }
+        };
+        if let Err(e) = queue
+            .deliver(job_id, |is_last_attempt| {
+                let delivery =
+                    deliver_threema_message(&incoming_message, &app_state, is_last_attempt);
+                async { delivery.await.map(|_| DeliveryOutcome::Delivered) }
+            })
+            .await
+        {
+            error!("Threema: Incoming Message Error: {}", e);
+        }
+    }
+}
+
+async fn deliver_threema_message(
+    incoming_message: &IncomingMessage,
+    app_state: &AppState,
+    is_last_attempt: bool,
+) -> Result<(), DeliveryError> {
+    match app_state
+        .threema_client
+        .process_incoming_msg(incoming_message)
+        .await
+    {
+        Ok(message) => return forward_threema_message(message, app_state, is_last_attempt).await,
+        // Messages, which can't be shown in Matrix, are dropped
+        Err(ProcessIncomingMessageError::UnsupportedMessageType(message_type)) => {
+            info!(
+                "Threema: Ignoring unsupported {:?} message from {}",
+                message_type, incoming_message.from
+            );
+            return Ok(());
+        }
+        Err(ProcessIncomingMessageError::UnknownMessageType(message_type)) => {
+            info!(
+                "Threema: Ignoring message of unknown type {:#04x} from {}",
+                message_type, incoming_message.from
+            );
+            return Ok(());
+        }
+        Err(ProcessIncomingMessageError::ApiError(e)) => {
+            return Err(DeliveryError::from_api_error(&e))
+        }
+        Err(e) => return Err(DeliveryError::Permanent(e.to_string())),
+    }
+}
+
+/// Delivers the queued Matrix messages to Threema. Jobs are processed one after another, so that
+/// messages keep their order.
+pub async fn run_matrix_to_threema_worker(app_state: web::Data<AppState>) {
+    let queue = &app_state.matrix_to_threema_queue;
+    loop {
+        let (job_id, job) = queue.next().await;
+        if let Err(e) = queue
+            .deliver(job_id, |is_last_attempt| {
+                deliver_matrix_message(job_id, &job, &app_state, is_last_attempt)
+            })
+            .await
+        {
+            error!("Threema: Couldn't send message from Matrix: {}", e);
+        }
+        for job_id in app_state.threema_client.take_expired_pending_jobs().await {
+            if let Err(e) = queue.complete(job_id) {
+                error!("Store: Could not remove job {}: {}", job_id, e);
+            }
+        }
+    }
+}
+
+async fn deliver_matrix_message(
+    job_id: u64,
+    job: &DeliveryJob,
+    app_state: &AppState,
+    is_last_attempt: bool,
+) -> Result<DeliveryOutcome, DeliveryError> {
+    let (room_id, event_id, group_id, group_creator, message) = match job {
+        DeliveryJob::MatrixToThreema {
+            room_id,
+            event_id,
+            group_id,
+            group_creator,
+            message,
+        } => (room_id, event_id, group_id, group_creator, message),
+        DeliveryJob::GroupSync { receiver, group_id } => {
+            return app_state
+                .threema_client
+                .send_group_sync(group_id, receiver)
+                .await
+                .map(|_| DeliveryOutcome::Delivered)
+                .map_err(|e| DeliveryError::from_api_error(&e));
+        }
+        DeliveryJob::ThreemaToMatrix(_) => {
+            return Err(DeliveryError::Permanent(
+                "Threema message in Matrix to Threema queue".to_owned(),
+            ));
+        }
+    };
+    let matrix_client = app_state.matrix_client.lock().await.clone();
+
+    let message = match message {
+        MatrixMessage::Text(text) => OutgoingGroupMessage::Text(text.clone()),
+        MatrixMessage::Attachment {
+            content,
+            sender_name,
+        } => match download_matrix_attachment(&matrix_client, content, sender_name).await {
+            Ok(file) => OutgoingGroupMessage::File(file),
+            Err(e) => {
+                if is_last_attempt || matches!(e, DeliveryError::Permanent(_)) {
+                    match matrix_client.get_joined_room(room_id) {
+                        Some(room) => {
+                            let err_txt =
+                                format!("Couldn't send attachment to Threema group: {}", e);
+                            send_error_message_to_matrix_room(&room, err_txt, true).await;
+                        }
+                        None => error!("Matrix: Couldn't fetch attachment in {}: {}", room_id, e),
+                    }
+                }
+                return Err(e);
+            }
+        },
+    };
+
+    let result = app_state
+        .threema_client
+        .send_or_queue_group_msg(message, group_id, group_creator.as_deref(), job_id)
+        .await;
+
+    let report = match &result {
+        Ok(report) => report.as_ref(),
+        Err(SendGroupMessageError::PartialDelivery(report)) => Some(report),
+        Err(_) => None,
+    };
+    if let (Some(report), Some(event_id)) = (report, event_id) {
+        for (receiver, message_id) in &report.sent {
+            let message = BridgedMessage::new(
+                BridgeDirection::MatrixToThreema,
+                receiver,
+                message_id,
+                group_id,
+                room_id.clone(),
+                event_id.clone(),
+            );
+            if let Err(e) = app_state.message_map.insert(&message) {
+                error!("Store: Could not map message {}: {}", message_id, e);
+            }
+        }
+    }
+
+    let e = match result {
+        Ok(Some(_)) => return Ok(DeliveryOutcome::Delivered),
+        // The job is completed, when the queued message is sent after the group sync
+        Ok(None) => return Ok(DeliveryOutcome::Deferred),
+        Err(e) => e,
+    };
+    let delivery_error = match &e {
+        SendGroupMessageError::ApiError(api_error) => DeliveryError::from_api_error(api_error),
+        // Nobody got the message yet, so it can be sent again
+        SendGroupMessageError::PartialDelivery(report) if report.sent.is_empty() => {
+            DeliveryError::Temporary(e.to_string())
+        }
+        _ => DeliveryError::Permanent(e.to_string()),
+    };
+    if is_last_attempt || matches!(delivery_error, DeliveryError::Permanent(_)) {
+        match matrix_client.get_joined_room(room_id) {
+            Some(room) => {
+                let err_txt = format!("Couldn't send message to Threema group: {}", e);
+                send_error_message_to_matrix_room(&room, err_txt, true).await;
+            }
+            None => error!("Threema: Couldn't send message from {}: {}", room_id, e),
+        }
+    }
+    return Err(delivery_error);
+}
+
+async fn forward_threema_message(
+    message: Message,
+    app_state: &AppState,
+    is_last_attempt: bool,
+) -> Result<(), DeliveryError> {
     let threema_client = &app_state.threema_client;
-    let decrypted_message = threema_client.process_incoming_msg(&incoming_message).await;
-
-    match decrypted_message {
-        Ok(message) => match message {
-            Message::GroupTextMessage(group_text_msg) => {
-                let matrix_client = app_state.matrix_client.lock().await;
-
-                if group_text_msg.text.starts_with("!threematrix") {
-                    let split_text: Vec<&str> = group_text_msg.text.split(" ").collect();
-                    match split_text.get(1).map(|str| *str) {
-                        Some("bind") => {
-                            let rooms = matrix_client.joined_rooms();
-                            let matrix_room_id = split_text.get(2);
-
-                            if let Some(matrix_room_id) = matrix_room_id {
-                                if let Some(room) =
-                                    rooms.iter().find(|r| r.room_id() == matrix_room_id)
-                                {
-                                    if let Ok(r) = convert_group_id_to_readable_string(
+
+    match message {
+        Message::GroupTextMessage(group_text_msg) => {
+            let matrix_client = app_state.matrix_client.lock().await;
+
+            if group_text_msg.text.starts_with("!threematrix") {
+                let split_text: Vec<&str> = group_text_msg.text.split(" ").collect();
+                match split_text.get(1).copied() {
+                    Some("bind") => {
+                        let rooms = matrix_client.joined_rooms();
+                        let matrix_room_id = split_text.get(2);
+
+                        if let Some(matrix_room_id) = matrix_room_id {
+                            if let Some(room) = rooms.iter().find(|r| r.room_id() == matrix_room_id)
+                            {
+                                let code = match app_state
+                                    .pending_bindings
+                                    .create(
+                                        room.room_id(),
                                         &group_text_msg.group_id,
-                                    ) {
-                                        let content: ThreematrixStateEventContent =
-                                            ThreematrixStateEventContent {
-                                                threematrix_threema_group_id: r,
-                                            };
-
-                                        if let Err(e) =
-                                            set_threematrix_room_state(content, room).await
-                                        {
-                                            let err_text =
-                                                format!("Could not set Matrix room state: {}", e);
-                                            send_error_message_to_threema_group(
-                                                threema_client,
-                                                err_text,
-                                                group_text_msg.group_id.as_slice(),
-                                                false,
-                                            )
-                                            .await;
-                                        } else {
-                                            let succ_text = format!("Group has been successfully bound to Matrix room: {}", matrix_room_id);
-                                            if let Err(e) = threema_client
-                                                .send_group_msg_by_group_id(
-                                                    succ_text.as_str(),
-                                                    group_text_msg.group_id.as_slice(),
-                                                )
-                                                .await
-                                            {
-                                                error!("Threema: Could not send bind text: {}", e)
-                                            }
-                                        };
-                                    } else {
-                                        error!("Threema: Group Id not valid!");
+                                        &group_text_msg.group_creator,
+                                    )
+                                    .await
+                                {
+                                    Some(code) => code,
+                                    None => {
+                                        let err_text = format!("Another binding of Matrix room {} is still waiting for confirmation. Please try again later!", matrix_room_id);
+                                        send_error_message_to_threema_group(
+                                            threema_client,
+                                            err_text,
+                                            &group_text_msg.group_creator,
+                                            group_text_msg.group_id.as_slice(),
+                                            false,
+                                        )
+                                        .await;
+                                        return Ok(());
                                     }
-                                } else {
-                                    let err_text = format!("Matrix room not found. Maybe the bot is not invited or the room id has wrong format!");
-                                    send_error_message_to_threema_group(
-                                        threema_client,
-                                        err_text,
+                                };
+                                info!(
+                                    "Threema: Binding of Matrix room {} requested",
+                                    room.room_id()
+                                );
+
+                                let notice = RoomMessageEventContent::notice_plain(format!(
+                                    "A Threema group wants to bind this room. A moderator can confirm it within {} minutes with \"!threematrix confirm <code>\". The code has been sent to the Threema group.",
+                                    PENDING_BINDING_TTL.as_secs() / 60
+                                ));
+                                if let Err(e) = room.send(notice, None).await {
+                                    error!("Matrix: Could not send bind notice: {}", e);
+                                }
+
+                                let code_text = format!(
+                                    "To bind this group to Matrix room {}, a moderator of the room has to send \"!threematrix confirm {}\" in the room within {} minutes.",
+                                    matrix_room_id,
+                                    code,
+                                    PENDING_BINDING_TTL.as_secs() / 60
+                                );
+                                if let Err(e) = threema_client
+                                    .send_group_msg_by_group_id(
+                                        code_text.as_str(),
+                                        &group_text_msg.group_creator,
                                         group_text_msg.group_id.as_slice(),
-                                        false,
                                     )
-                                    .await;
+                                    .await
+                                {
+                                    error!("Threema: Could not send bind code: {}", e)
                                 }
                             } else {
-                                let err_text = format!("Missing Matrix room id!");
+                                let err_text = "Matrix room not found. Maybe the bot is not invited or the room id has wrong format!".to_owned();
                                 send_error_message_to_threema_group(
                                     threema_client,
                                     err_text,
+                                    &group_text_msg.group_creator,
                                     group_text_msg.group_id.as_slice(),
                                     false,
                                 )
                                 .await;
                             }
-                        }
-                        Some("help") => {
-                            let help_txt = r#"To bind this Threema Group to a Matrix Room, please use the command "!threematrix bind !abc123:homeserver.org".
-You can find the required room id in your Matrix client. Attention: This is NOT a "human readable" room alias, but an "internal" room id, which consists of random characters."#;
-                            if let Err(e) = threema_client
-                                .send_group_msg_by_group_id(
-                                    help_txt,
-                                    group_text_msg.group_id.as_slice(),
-                                )
-                                .await
-                            {
-                                error!("Threema: Could not send help text: {}", e)
-                            }
-                        }
-                        _ => {
-                            let err_text = format!(
-                                "Command not found! Use *!threematrix help* for more information"
-                            );
+                        } else {
+                            let err_text = "Missing Matrix room id!".to_owned();
                             send_error_message_to_threema_group(
                                 threema_client,
                                 err_text,
+                                &group_text_msg.group_creator,
                                 group_text_msg.group_id.as_slice(),
                                 false,
                             )
                             .await;
                         }
                     }
-                } else {
-                    let sender_name = group_text_msg
-                        .base
-                        .push_from_name
-                        .unwrap_or("UNKNOWN".to_owned());
-                    let content = RoomMessageEventContent::text_html(
-                        format!("{}: {}", sender_name, group_text_msg.text.as_str()),
-                        format!(
-                            "<strong>{}</strong>: {}",
-                            sender_name,
-                            group_text_msg.text.as_str()
-                        ),
-                    );
-                    for room in matrix_client.joined_rooms() {
-                        match get_threematrix_room_state(&room).await {
-                            Ok(None) => debug!(
-                                "Matrix: Room {:?} does not have proper room state",
-                                &room.display_name().await.unwrap_or(
-                                    matrix_sdk::DisplayName::Named("UNKNOWN".to_owned())
+                    Some("unbind") => {
+                        let rooms = get_bound_matrix_rooms(
+                            &matrix_client,
+                            &app_state.binding_index,
+                            &group_text_msg.group_id,
+                        )
+                        .await;
+                        if rooms.is_empty() {
+                            let err_text = "This group is not bound to a Matrix room!".to_owned();
+                            send_error_message_to_threema_group(
+                                threema_client,
+                                err_text,
+                                &group_text_msg.group_creator,
+                                group_text_msg.group_id.as_slice(),
+                                false,
+                            )
+                            .await;
+                        }
+                        for room in rooms {
+                            if let Err(e) = unbind_room(
+                                &room,
+                                &group_text_msg.base.from_identity,
+                                Some(&group_text_msg.group_creator),
+                                &group_text_msg.group_id,
+                                threema_client,
+                                &app_state.audit_log,
+                                &app_state.binding_index,
+                            )
+                            .await
+                            {
+                                let err_text = format!("Could not unbind Matrix room: {}", e);
+                                send_error_message_to_threema_group(
+                                    threema_client,
+                                    err_text,
+                                    &group_text_msg.group_creator,
+                                    group_text_msg.group_id.as_slice(),
+                                    true,
                                 )
-                            ),
-                            Ok(Some(state)) => {
-                                if let Ok(group_id) =
-                                    convert_group_id_to_readable_string(&group_text_msg.group_id)
-                                {
-                                    if state.threematrix_threema_group_id == group_id {
-                                        let txn_id = TransactionId::new();
-                                        if let Err(e) =
-                                            room.send(content.clone(), Some(&txn_id)).await
-                                        {
-                                            let err_txt = format!(
-                                                "Could not send message to Matrix room: {}",
-                                                e
-                                            );
-                                            send_error_message_to_threema_group(
-                                                threema_client,
-                                                err_txt,
-                                                group_text_msg.group_id.as_slice(),
-                                                true,
-                                            )
-                                            .await;
-                                        }
-                                    }
-                                }
+                                .await;
                             }
-                            Err(e) => warn!("Matrix: Could not retrieve room state: {}", e),
                         }
                     }
+                    Some("help") => {
+                        let help_txt = r#"To bind this Threema Group to a Matrix Room, please use the command "!threematrix bind !abc123:homeserver.org".
+You can find the required room id in your Matrix client. Attention: This is NOT a "human readable" room alias, but an "internal" room id, which consists of random characters.
+Afterwards a moderator of the Matrix room has to confirm the binding by sending "!threematrix confirm <code>" with the code, which the bridge sends to this group.
+To remove the binding again, use the command "!threematrix unbind"."#;
+                        if let Err(e) = threema_client
+                            .send_group_msg_by_group_id(
+                                help_txt,
+                                &group_text_msg.group_creator,
+                                group_text_msg.group_id.as_slice(),
+                            )
+                            .await
+                        {
+                            error!("Threema: Could not send help text: {}", e)
+                        }
+                    }
+                    _ => {
+                        let err_text =
+                            "Command not found! Use *!threematrix help* for more information"
+                                .to_owned();
+                        send_error_message_to_threema_group(
+                            threema_client,
+                            err_text,
+                            &group_text_msg.group_creator,
+                            group_text_msg.group_id.as_slice(),
+                            false,
+                        )
+                        .await;
+                    }
                 }
+            } else {
+                let quote = parse_threema_quote(&group_text_msg.text);
+                let sync_token = matrix_client.sync_token().await;
+                let mut forwarded = false;
+                let mut errors = Vec::new();
+                for room in get_bound_matrix_rooms(
+                    &matrix_client,
+                    &app_state.binding_index,
+                    &group_text_msg.group_id,
+                )
+                .await
+                {
+                    let replied_event_id = match &quote {
+                        Some((quote, _)) => {
+                            find_quoted_event(&room, quote, &app_state.message_map, &sync_token)
+                                .await
+                        }
+                        None => None,
+                    };
+                    let (room, sender_name) =
+                        match get_sending_room(&app_state.appservice, room, &group_text_msg.base)
+                            .await
+                        {
+                            Ok(sending_room) => sending_room,
+                            Err(e) => {
+                                let err_txt =
+                                    format!("Could not send message to Matrix room: {}", e);
+                                errors.push(err_txt);
+                                continue;
+                            }
+                        };
+                    let content = match (&quote, replied_event_id) {
+                        (Some((_, reply)), Some(replied_event_id)) => {
+                            let mut content =
+                                threema_text_to_matrix_content(sender_name.as_deref(), reply, None);
+                            content.relates_to = Some(MessageRelation::Reply {
+                                in_reply_to: InReplyTo::new(replied_event_id),
+                            });
+                            content
+                        }
+                        // The message id is useless without the quoted message
+                        (Some((ThreemaQuote::MessageId(_), reply)), None) => {
+                            threema_text_to_matrix_content(sender_name.as_deref(), reply, None)
+                        }
+                        (Some((ThreemaQuote::Text(quoted), reply)), None) => {
+                            threema_text_to_matrix_content(
+                                sender_name.as_deref(),
+                                reply,
+                                Some(quoted),
+                            )
+                        }
+                        (None, _) => threema_text_to_matrix_content(
+                            sender_name.as_deref(),
+                            &group_text_msg.text,
+                            None,
+                        ),
+                    };
+                    let txn_id = TransactionId::new();
+                    match room.send(content, Some(&txn_id)).await {
+                        Ok(response) => {
+                            map_forwarded_message(
+                                &app_state.message_map,
+                                &group_text_msg.base,
+                                &group_text_msg.group_id,
+                                room.room_id(),
+                                response.event_id,
+                            );
+                            forwarded = true;
+                        }
+                        Err(e) => {
+                            let err_txt = format!("Could not send message to Matrix room: {}", e);
+                            errors.push(err_txt);
+                        }
+                    }
+                }
+                if forwarded {
+                    send_received_receipt(threema_client, &group_text_msg.base).await;
+                }
+                return report_forwarding_errors(
+                    threema_client,
+                    &group_text_msg.group_creator,
+                    &group_text_msg.group_id,
+                    errors,
+                    forwarded,
+                    is_last_attempt,
+                )
+                .await;
             }
-            Message::GroupCreateMessage(group_create_msg) => {
-                info!(
-                    "Got group create message with members: {:?}",
-                    group_create_msg.members
-                );
+        }
+        Message::GroupImageMessage(group_image_msg) => {
+            let matrix_client = app_state.matrix_client.lock().await;
+            let file = ThreemaFile {
+                data: group_image_msg.image,
+                media_type: mime::IMAGE_JPEG.to_string(),
+                file_name: Some("image.jpg".to_owned()),
+                caption: None,
+                thumbnail: None,
+            };
+
+            let mut forwarded = false;
+            let mut errors = Vec::new();
+            for room in get_bound_matrix_rooms(
+                &matrix_client,
+                &app_state.binding_index,
+                &group_image_msg.group_id,
+            )
+            .await
+            {
+                let room_id = room.room_id().to_owned();
+                let result = match get_sending_room(
+                    &app_state.appservice,
+                    room,
+                    &group_image_msg.base,
+                )
+                .await
+                {
+                    Ok((room, sender_name)) => {
+                        send_threema_file_to_matrix_room(&room, sender_name.as_deref(), &file)
+                            .await
+                            .map_err(|e| e.to_string())
+                    }
+                    Err(e) => Err(e.to_string()),
+                };
+                match result {
+                    Ok(event_id) => {
+                        map_forwarded_message(
+                            &app_state.message_map,
+                            &group_image_msg.base,
+                            &group_image_msg.group_id,
+                            &room_id,
+                            event_id,
+                        );
+                        forwarded = true;
+                    }
+                    Err(e) => {
+                        let err_txt = format!("Could not send image to Matrix room: {}", e);
+                        errors.push(err_txt);
+                    }
+                }
+            }
+            if forwarded {
+                send_received_receipt(threema_client, &group_image_msg.base).await;
             }
-            Message::GroupRenameMessage(group_rename_msg) => {
-                info!(
-                    "Got group rename message for: {:?}",
-                    group_rename_msg.group_name
+            return report_forwarding_errors(
+                threema_client,
+                &group_image_msg.group_creator,
+                &group_image_msg.group_id,
+                errors,
+                forwarded,
+                is_last_attempt,
+            )
+            .await;
+        }
+        Message::GroupFileMessage(group_file_msg) => {
+            let matrix_client = app_state.matrix_client.lock().await;
+
+            let mut forwarded = false;
+            let mut errors = Vec::new();
+            for room in get_bound_matrix_rooms(
+                &matrix_client,
+                &app_state.binding_index,
+                &group_file_msg.group_id,
+            )
+            .await
+            {
+                let room_id = room.room_id().to_owned();
+                let result =
+                    match get_sending_room(&app_state.appservice, room, &group_file_msg.base).await
+                    {
+                        Ok((room, sender_name)) => send_threema_file_to_matrix_room(
+                            &room,
+                            sender_name.as_deref(),
+                            &group_file_msg.file,
+                        )
+                        .await
+                        .map_err(|e| e.to_string()),
+                        Err(e) => Err(e.to_string()),
+                    };
+                match result {
+                    Ok(event_id) => {
+                        map_forwarded_message(
+                            &app_state.message_map,
+                            &group_file_msg.base,
+                            &group_file_msg.group_id,
+                            &room_id,
+                            event_id,
+                        );
+                        forwarded = true;
+                    }
+                    Err(e) => {
+                        let err_txt = format!("Could not send file to Matrix room: {}", e);
+                        errors.push(err_txt);
+                    }
+                }
+            }
+            if forwarded {
+                send_received_receipt(threema_client, &group_file_msg.base).await;
+            }
+            return report_forwarding_errors(
+                threema_client,
+                &group_file_msg.group_creator,
+                &group_file_msg.group_id,
+                errors,
+                forwarded,
+                is_last_attempt,
+            )
+            .await;
+        }
+        Message::GroupCreateMessage(group_create_msg) => {
+            info!(
+                "Got group create message with members: {:?}",
+                group_create_msg.members
+            );
+            let job_ids = threema_client
+                .send_pending_group_msgs(
+                    &group_create_msg.base.from_identity,
+                    &group_create_msg.group_id,
+                )
+                .await;
+            for job_id in job_ids {
+                if let Err(e) = app_state.matrix_to_threema_queue.complete(job_id) {
+                    error!("Store: Could not remove job {}: {}", job_id, e);
+                }
+            }
+        }
+        Message::DeliveryReceiptMessage(receipt) => {
+            forward_delivery_receipt(receipt, app_state).await;
+        }
+        Message::GroupRenameMessage(group_rename_msg) => {
+            info!(
+                "Got group rename message for: {:?}",
+                group_rename_msg.group_name
+            );
+        }
+        Message::GroupRequestSyncMessage(sync_request) => {
+            let requester = &sync_request.base.from_identity;
+            if threema_client
+                .accept_group_sync_request(requester, &sync_request.group_id)
+                .await
+            {
+                // Answered by the worker for outgoing messages, so that a requester can't hold up
+                // the delivery of incoming messages
+                let job = DeliveryJob::GroupSync {
+                    receiver: requester.clone(),
+                    group_id: sync_request.group_id,
+                };
+                if let Err(e) = app_state.matrix_to_threema_queue.push(&job).await {
+                    error!("Store: Could not queue group sync for {}: {}", requester, e);
+                }
+            }
+        }
+        Message::GroupLeaveMessage(group_leave_msg) => {
+            let base = &group_leave_msg.base;
+            // Leaves of unknown groups or of senders, who already left, are not shown again
+            if !group_leave_msg.member_removed {
+                debug!(
+                    "Threema: Ignoring group leave of {}, who isn't a known member",
+                    base.from_identity
                 );
+                return Ok(());
+            }
+            let matrix_client = app_state.matrix_client.lock().await;
+            info!("Threema: {} left a group", base.from_identity);
+
+            for room in get_bound_matrix_rooms(
+                &matrix_client,
+                &app_state.binding_index,
+                &group_leave_msg.group_id,
+            )
+            .await
+            {
+                match &app_state.appservice {
+                    Some(appservice) => {
+                        if let Err(e) = appservice
+                            .leave_puppet_room(
+                                &base.from_identity,
+                                base.push_from_name.as_deref(),
+                                &room,
+                            )
+                            .await
+                        {
+                            error!(
+                                "Matrix: Puppet of {} could not leave room {}: {}",
+                                base.from_identity,
+                                room.room_id(),
+                                e
+                            );
+                        }
+                    }
+                    None => {
+                        let sender_name = base
+                            .push_from_name
+                            .clone()
+                            .unwrap_or(base.from_identity.clone());
+                        send_notice_to_matrix_room(
+                            &room,
+                            &format!("{} has left the Threema group", sender_name),
+                        )
+                        .await;
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+    return Ok(());
+}
+
+/// Reports the rooms, which a Threema message could not be forwarded to, to the Threema group.
+/// If it could not be forwarded to any room, it is tried again instead, until the last attempt.
+async fn report_forwarding_errors(
+    threema_client: &ThreemaClient,
+    group_creator: &str,
+    group_id: &[u8],
+    errors: Vec<String>,
+    forwarded: bool,
+    is_last_attempt: bool,
+) -> Result<(), DeliveryError> {
+    if !forwarded && !errors.is_empty() && !is_last_attempt {
+        return Err(DeliveryError::Temporary(errors.join(", ")));
+    }
+    for err_txt in errors {
+        send_error_message_to_threema_group(threema_client, err_txt, group_creator, group_id, true)
+            .await;
+    }
+    return Ok(());
+}
+
+#[derive(Deserialize)]
+pub struct AppserviceTransaction {
+    events: Vec<Raw<AnyRoomEvent>>,
+}
+
+/// Receives the events, which the homeserver pushes to the appservice, and passes them to the
+/// registered Matrix event handlers
+pub async fn matrix_appservice_transaction_handler(
+    request: HttpRequest,
+    txn_id: web::Path<String>,
+    transaction: web::Json<AppserviceTransaction>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let appservice = match &app_state.appservice {
+        Some(appservice) => appservice,
+        None => return HttpResponse::NotFound().finish(),
+    };
+
+    // The homeserver authenticates with the hs_token, either as query parameter or header
+    let query_token = web::Query::<HashMap<String, String>>::from_query(request.query_string())
+        .ok()
+        .and_then(|query| query.get("access_token").cloned());
+    let header_token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.to_owned());
+    match query_token.or(header_token) {
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({"errcode": "M_UNAUTHORIZED"}));
+        }
+        Some(token) if !appservice.is_valid_hs_token(&token) => {
+            return HttpResponse::Forbidden().json(serde_json::json!({"errcode": "M_FORBIDDEN"}));
+        }
+        Some(_) => {}
+    }
+
+    // The homeserver sends a transaction again, if it did not get the response
+    match app_state.transaction_log.contains(&txn_id) {
+        Ok(true) => {
+            debug!("Matrix: Ignoring repeated transaction {}", txn_id);
+            return HttpResponse::Ok().json(serde_json::json!({}));
+        }
+        Ok(false) => {}
+        Err(e) => {
+            error!("Store: Could not look up transaction {}: {}", txn_id, e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+
+    debug!(
+        "Matrix: Incoming transaction {} with {} events",
+        txn_id,
+        transaction.events.len()
+    );
+    let matrix_client = app_state.matrix_client.lock().await.clone();
+    let incoming_transaction = push_events::v1::IncomingRequest::new(
+        txn_id.as_str().into(),
+        transaction.into_inner().events,
+    );
+    if let Err(e) = matrix_client
+        .receive_transaction(incoming_transaction)
+        .await
+    {
+        error!("Matrix: Could not process transaction {}: {}", txn_id, e);
+        return HttpResponse::InternalServerError().finish();
+    }
+    if let Err(e) = app_state.transaction_log.insert(&txn_id) {
+        error!("Store: Could not store transaction {}: {}", txn_id, e);
+    }
+
+    return HttpResponse::Ok().json(serde_json::json!({}));
+}
+
+/// Uploads a Threema file to the Matrix media repo and posts it as m.image, m.video, m.audio or
+/// m.file event (depending on the MIME type), followed by its caption. The sender name is
+/// prepended, unless the file is posted by a puppet. Returns the id of the file event.
+async fn send_threema_file_to_matrix_room(
+    room: &Joined,
+    sender_name: Option<&str>,
+    file: &ThreemaFile,
+) -> Result<OwnedEventId, matrix_sdk::Error> {
+    let media_type = file
+        .media_type
+        .parse::<Mime>()
+        .unwrap_or(mime::APPLICATION_OCTET_STREAM);
+    let file_name = file.file_name.as_deref().unwrap_or("file");
+    let body = match sender_name {
+        Some(sender_name) => format!("{}: {}", sender_name, file_name),
+        None => file_name.to_owned(),
+    };
+    let size = UInt::new(file.data.len() as u64);
+    let info = match media_type.type_() {
+        mime::IMAGE => AttachmentInfo::Image(BaseImageInfo {
+            height: None,
+            width: None,
+            size,
+            blurhash: None,
+        }),
+        mime::VIDEO => AttachmentInfo::Video(BaseVideoInfo {
+            duration: None,
+            height: None,
+            width: None,
+            size,
+            blurhash: None,
+        }),
+        mime::AUDIO => AttachmentInfo::Audio(BaseAudioInfo {
+            duration: None,
+            size,
+        }),
+        _ => AttachmentInfo::File(BaseFileInfo { size }),
+    };
+
+    let response = if let Some(thumbnail) = &file.thumbnail {
+        let thumbnail_type = thumbnail
+            .media_type
+            .parse::<Mime>()
+            .unwrap_or(mime::IMAGE_JPEG);
+        let mut thumbnail_reader = Cursor::new(&thumbnail.data);
+        let config = AttachmentConfig::with_thumbnail(Thumbnail {
+            reader: &mut thumbnail_reader,
+            content_type: &thumbnail_type,
+            info: Some(BaseThumbnailInfo {
+                height: None,
+                width: None,
+                size: UInt::new(thumbnail.data.len() as u64),
+            }),
+        })
+        .info(info);
+        room.send_attachment(&body, &media_type, &mut Cursor::new(&file.data), config)
+            .await?
+    } else {
+        let config = AttachmentConfig::new().info(info);
+        room.send_attachment(&body, &media_type, &mut Cursor::new(&file.data), config)
+            .await?
+    };
+
+    if let Some(caption) = &file.caption {
+        let content = threema_text_to_matrix_content(sender_name, caption, None);
+        let txn_id = TransactionId::new();
+        room.send(content, Some(&txn_id)).await?;
+    }
+    return Ok(response.event_id);
+}
+
+/// Number of recent room messages, which are searched for the text of a Threema quote
+const QUOTE_SEARCH_LIMIT: u32 = 50;
+
+/// Returns the Matrix event, which a Threema quote refers to. Quoted message ids are looked up in
+/// the message map, quoted texts are searched in the recent messages of the room.
+async fn find_quoted_event(
+    room: &Joined,
+    quote: &ThreemaQuote,
+    message_map: &MessageMap,
+    sync_token: &Option<String>,
+) -> Option<OwnedEventId> {
+    match quote {
+        ThreemaQuote::MessageId(message_id) => match message_map.get_by_threema_id(message_id) {
+            Ok(messages) => {
+                return messages
+                    .into_iter()
+                    .find(|message| message.room_id == room.room_id())
+                    .map(|message| message.event_id);
+            }
+            Err(e) => {
+                error!("Store: Could not look up message {}: {}", message_id, e);
+                return None;
             }
-            _ => {}
         },
-        Err(err) => {
-            error!("Threema: Incoming Message Error: {}", err);
+        ThreemaQuote::Text(quoted_text) => {
+            // Without sync (e.g. in appservice mode) the start of the timeline is unknown
+            let from = sync_token.clone().or_else(|| room.last_prev_batch())?;
+            let mut options = MessagesOptions::backward(&from);
+            options.limit = UInt::from(QUOTE_SEARCH_LIMIT);
+            let messages = match room.messages(options).await {
+                Ok(messages) => messages,
+                Err(e) => {
+                    warn!("Matrix: Could not search quoted message: {}", e);
+                    return None;
+                }
+            };
+            return messages.chunk.iter().find_map(|room_event| {
+                match room_event.event.deserialize() {
+                    Ok(AnyRoomEvent::MessageLike(AnyMessageLikeEvent::RoomMessage(
+                        MessageLikeEvent::Original(event),
+                    ))) => match &event.content.msgtype {
+                        MessageType::Text(TextMessageEventContent { body, .. })
+                        | MessageType::Notice(NoticeMessageEventContent { body, .. })
+                            if matches_quoted_text(body, quoted_text) =>
+                        {
+                            Some(event.event_id)
+                        }
+                        _ => None,
+                    },
+                    _ => None,
+                }
+            });
         }
     }
+}
 
-    HttpResponse::Ok()
-        .content_type(ContentType::plaintext())
-        .body(())
+/// Converts a Matrix text into a Threema text. The formatted body is preferred, because its HTML
+/// can be converted into Threema markup. A reply is converted into the Threema quote format:
+/// replies to bridged Threema messages refer to the original message id, other replies quote the
+/// text of the fallback.
+fn matrix_text_to_threema_text(
+    sender_name: &str,
+    body: &str,
+    formatted: Option<&FormattedBody>,
+    relates_to: Option<&MessageRelation>,
+    message_map: &MessageMap,
+) -> String {
+    let in_reply_to = match relates_to {
+        Some(MessageRelation::Reply { in_reply_to }) => Some(in_reply_to),
+        _ => None,
+    };
+    let (quoted_text, reply) = match in_reply_to {
+        Some(_) => strip_matrix_reply_fallback(body),
+        None => (None, body.to_owned()),
+    };
+    let reply = match formatted {
+        // The reply fallback of the formatted body is removed as well
+        Some(formatted) if formatted.format == MessageFormat::Html => {
+            html_to_threema(&formatted.body)
+        }
+        _ => reply,
+    };
+    let text = format!("*{}*: {}", sender_name, reply);
+    let in_reply_to = match in_reply_to {
+        Some(in_reply_to) => in_reply_to,
+        None => return text,
+    };
+
+    let threema_message = match message_map.get_by_event_id(&in_reply_to.event_id) {
+        Ok(messages) => messages
+            .into_iter()
+            .find(|message| message.direction == BridgeDirection::ThreemaToMatrix),
+        Err(e) => {
+            error!(
+                "Store: Could not look up event {}: {}",
+                in_reply_to.event_id, e
+            );
+            None
+        }
+    };
+    let quote = match (threema_message, quoted_text) {
+        (Some(threema_message), _) => ThreemaQuote::MessageId(threema_message.threema_message_id),
+        (None, Some(quoted_text)) => ThreemaQuote::Text(quoted_text),
+        (None, None) => return text,
+    };
+    return format_threema_quote(&quote, &text);
+}
+
+/// Remembers the Threema message of a forwarded event, so that e.g. a Matrix read receipt can be
+/// sent back to the sender
+fn map_forwarded_message(
+    message_map: &MessageMap,
+    base: &MessageBase,
+    group_id: &[u8],
+    room_id: &RoomId,
+    event_id: OwnedEventId,
+) {
+    let message = BridgedMessage::new(
+        BridgeDirection::ThreemaToMatrix,
+        &base.from_identity,
+        &base.message_id,
+        group_id,
+        room_id.to_owned(),
+        event_id,
+    );
+    if let Err(e) = message_map.insert(&message) {
+        error!("Store: Could not map event {}: {}", message.event_id, e);
+    }
+}
+
+/// Tells the Threema sender, that the message has been posted to Matrix
+async fn send_received_receipt(threema_client: &ThreemaClient, base: &MessageBase) {
+    if let Err(e) = threema_client
+        .send_delivery_receipt(
+            &base.from_identity,
+            DeliveryReceiptStatus::Received,
+            std::slice::from_ref(&base.message_id),
+        )
+        .await
+    {
+        warn!("Threema: Could not send delivery receipt: {}", e);
+    }
+}
+
+/// Shows Matrix users, that a Threema user has read their message or reacted to it. Puppets send
+/// read receipts, the bot can only react.
+async fn forward_delivery_receipt(receipt: DeliveryReceiptMessage, app_state: &AppState) {
+    let reaction_key = match receipt.status {
+        DeliveryReceiptStatus::Received => {
+            debug!(
+                "Threema: {} received {:?}",
+                receipt.base.from_identity, receipt.message_ids
+            );
+            return;
+        }
+        DeliveryReceiptStatus::Read => READ_REACTION_KEY,
+        DeliveryReceiptStatus::Agreed => AGREED_REACTION_KEY,
+        DeliveryReceiptStatus::Disagreed => DISAGREED_REACTION_KEY,
+    };
+
+    let matrix_client = app_state.matrix_client.lock().await.clone();
+    for message_id in &receipt.message_ids {
+        let matrix_event = match app_state.message_map.get_by_threema_id(message_id) {
+            Ok(messages) => messages
+                .into_iter()
+                .find(|message| message.direction == BridgeDirection::MatrixToThreema),
+            Err(e) => {
+                error!("Store: Could not look up message {}: {}", message_id, e);
+                continue;
+            }
+        };
+        let matrix_event = match matrix_event {
+            Some(matrix_event) => matrix_event,
+            None => {
+                debug!("Threema: Receipt for unknown message {}", message_id);
+                continue;
+            }
+        };
+        let room = match matrix_client.get_joined_room(&matrix_event.room_id) {
+            Some(room) => room,
+            None => continue,
+        };
+        let room = match get_sending_room(&app_state.appservice, room, &receipt.base).await {
+            Ok((room, _)) => room,
+            Err(e) => {
+                warn!("Matrix: Could not forward delivery receipt: {}", e);
+                continue;
+            }
+        };
+
+        let result =
+            if receipt.status == DeliveryReceiptStatus::Read && app_state.appservice.is_some() {
+                room.read_receipt(&matrix_event.event_id).await
+            } else {
+                match app_state.message_map.add_reaction(
+                    &matrix_event.event_id,
+                    room.own_user_id(),
+                    reaction_key,
+                ) {
+                    Ok(true) => {
+                        let content = ReactionEventContent::new(Relation::new(
+                            matrix_event.event_id.clone(),
+                            reaction_key.to_owned(),
+                        ));
+                        room.send(content, None).await.map(|_| ())
+                    }
+                    Ok(false) => Ok(()),
+                    Err(e) => {
+                        error!("Store: Could not store reaction: {}", e);
+                        Ok(())
+                    }
+                }
+            };
+        if let Err(e) = result {
+            warn!("Matrix: Could not forward delivery receipt: {}", e);
+        }
+    }
+}
+
+/// Returns the room, from which a Threema message is posted, and the sender name to prepend.
+/// In appservice mode the puppet of the Threema sender posts the message without prefix.
+async fn get_sending_room(
+    appservice: &Option<Appservice>,
+    room: Joined,
+    base: &MessageBase,
+) -> Result<(Joined, Option<String>), AppserviceError> {
+    match appservice {
+        Some(appservice) => {
+            let puppet_room = appservice
+                .get_puppet_room(&base.from_identity, base.push_from_name.as_deref(), &room)
+                .await?;
+            return Ok((puppet_room, None));
+        }
+        None => {
+            let sender_name = base.push_from_name.clone().unwrap_or("UNKNOWN".to_owned());
+            return Ok((room, Some(sender_name)));
+        }
+    }
+}
+
+async fn get_bound_matrix_rooms(
+    matrix_client: &Client,
+    binding_index: &BindingIndex,
+    group_id: &[u8],
+) -> Vec<Joined> {
+    return binding_index
+        .get_rooms(group_id)
+        .await
+        .iter()
+        .filter_map(|room_id| {
+            let room = matrix_client.get_joined_room(room_id);
+            if room.is_none() {
+                debug!("Matrix: Bound room {} is not joined", room_id);
+            }
+            room
+        })
+        .collect();
 }
 
 async fn send_error_message_to_threema_group(
     threema_client: &ThreemaClient,
     err_text: String,
+    group_creator: &str,
     group_id: &[u8],
     log_level_error: bool,
 ) {
@@ -274,7 +1316,7 @@ async fn send_error_message_to_threema_group(
         warn!("Threema: {}", err_text);
     }
     if let Err(e) = threema_client
-        .send_group_msg_by_group_id(err_text.as_str(), group_id)
+        .send_group_msg_by_group_id(err_text.as_str(), group_creator, group_id)
         .await
     {
         error!(
@@ -284,73 +1326,146 @@ async fn send_error_message_to_threema_group(
     }
 }
 
+/// Stores, which the Matrix message handler needs. They are bundled, because an event handler
+/// can only take a limited number of contexts.
+#[derive(Clone)]
+pub struct MatrixHandlerStores {
+    pub audit_log: AuditLog,
+    pub binding_index: BindingIndex,
+    pub delivery_queue: DeliveryQueue,
+    pub message_map: MessageMap,
+}
+
 pub async fn matrix_incoming_message_handler(
     event: OriginalSyncMessageLikeEvent<RoomMessageEventContent>,
     room: Room,
     threema_client: Ctx<ThreemaClient>,
+    appservice: Ctx<Option<Appservice>>,
+    pending_bindings: Ctx<PendingBindings>,
+    stores: Ctx<MatrixHandlerStores>,
     matrix_client: Client,
 ) -> () {
     match room {
         Room::Joined(room) => {
-            if let OriginalSyncMessageLikeEvent {
+            let OriginalSyncMessageLikeEvent {
                 content:
                     RoomMessageEventContent {
-                        msgtype: MessageType::Text(TextMessageEventContent { body: msg_body, .. }),
+                        msgtype,
+                        relates_to,
                         ..
                     },
                 sender,
+                event_id,
                 ..
-            } = event
-            {
-                debug!("Matrix: Incoming message: {}", msg_body);
-
-                let sender_member = room.get_member(&sender).await;
-                match sender_member {
-                    Ok(Some(sender_member)) => {
-                        let sender_name = sender_member
-                            .display_name()
-                            .unwrap_or_else(|| sender_member.user_id().as_str());
-
-                        // Filter out messages coming from our own bridge user
-                        if sender != matrix_client.user_id().await.unwrap() {
-                            match get_threematrix_room_state(&room).await {
-                                Ok(None) => {
-                                    let err_txt = format!("Room {} does not have proper room state. Have you bound the room to a Threema group?",
-                                                          &room.display_name().await.unwrap_or(matrix_sdk::DisplayName::Named("UNKNOWN".to_owned())));
-                                    send_error_message_to_matrix_room(&room, err_txt, false).await;
-                                }
-                                Ok(Some(threematrix_state)) => {
-                                    let group_id = convert_group_id_from_readable_string(
-                                        threematrix_state.threematrix_threema_group_id.as_str(),
-                                    );
-
-                                    if let Ok(group_id) = group_id {
-                                        if let Err(e) = threema_client
-                                            .send_group_msg_by_group_id(
-                                                format!("*{}*: {}", sender_name, msg_body).as_str(),
-                                                group_id.as_slice(),
-                                            )
-                                            .await
-                                        {
-                                            let err_txt = format!(
-                                                "Couldn't send message to Threema group: {}",
-                                                e
-                                            );
-                                            send_error_message_to_matrix_room(&room, err_txt, true)
-                                                .await;
-                                        }
+            } = event;
+
+            match &msgtype {
+                MessageType::Text(TextMessageEventContent { body, .. }) => {
+                    debug!("Matrix: Incoming message: {}", body);
+                }
+                MessageType::Image(_)
+                | MessageType::File(_)
+                | MessageType::Video(_)
+                | MessageType::Audio(_) => {
+                    debug!("Matrix: Incoming {} attachment", msgtype.msgtype());
+                }
+                _ => return,
+            }
+
+            let sender_member = room.get_member(&sender).await;
+            match sender_member {
+                Ok(Some(sender_member)) => {
+                    let sender_name = sender_member
+                        .display_name()
+                        .unwrap_or_else(|| sender_member.user_id().as_str());
+
+                    // Filter out messages coming from our own bridge user and its puppets
+                    let from_puppet = appservice
+                        .as_ref()
+                        .map(|appservice| appservice.is_puppet(&sender))
+                        .unwrap_or(false);
+                    if sender != matrix_client.user_id().await.unwrap() && !from_puppet {
+                        if let MessageType::Text(TextMessageEventContent { body, .. }) = &msgtype {
+                            if let Some(command) = MatrixCommand::parse(body) {
+                                handle_matrix_command(
+                                    command,
+                                    &room,
+                                    &sender_member,
+                                    &threema_client,
+                                    &pending_bindings,
+                                    &stores.audit_log,
+                                    &stores.binding_index,
+                                )
+                                .await;
+                                return;
+                            }
+                        }
+
+                        match get_threematrix_room_state(&room).await {
+                            Ok(None) => {
+                                let err_txt = format!("Room {} does not have proper room state. Have you bound the room to a Threema group?",
+                                                      &room.display_name().await.unwrap_or(matrix_sdk::DisplayName::Named("UNKNOWN".to_owned())));
+                                send_error_message_to_matrix_room(&room, err_txt, false).await;
+                            }
+                            Ok(Some(threematrix_state)) => {
+                                let group_id = convert_group_id_from_readable_string(
+                                    threematrix_state.threematrix_threema_group_id.as_str(),
+                                );
+
+                                if let Ok(group_id) = group_id {
+                                    let message = match &msgtype {
+                                        MessageType::Text(TextMessageEventContent {
+                                            body,
+                                            formatted,
+                                            ..
+                                        }) => MatrixMessage::Text(matrix_text_to_threema_text(
+                                            sender_name,
+                                            body,
+                                            formatted.as_ref(),
+                                            relates_to.as_ref(),
+                                            &stores.message_map,
+                                        )),
+                                        _ => MatrixMessage::Attachment {
+                                            content: msgtype.clone(),
+                                            sender_name: sender_name.to_owned(),
+                                        },
+                                    };
+
+                                    let job = DeliveryJob::MatrixToThreema {
+                                        room_id: room.room_id().to_owned(),
+                                        event_id: Some(event_id),
+                                        group_id,
+                                        group_creator: threematrix_state
+                                            .threematrix_threema_group_creator,
+                                        message,
+                                    };
+                                    // The event is acknowledged to the homeserver afterwards,
+                                    // so the job has to be on disk already
+                                    let queue = &stores.delivery_queue;
+                                    if let Err(e) = async {
+                                        queue.push(&job).await?;
+                                        queue.flush().await
+                                    }
+                                    .await
+                                    {
+                                        let err_txt = format!(
+                                            "Couldn't queue message for Threema group: {}",
+                                            e
+                                        );
+                                        send_error_message_to_matrix_room(&room, err_txt, true)
+                                            .await;
                                     }
-                                }
-                                Err(e) => {
-                                    let err_txt = format!("Could not retrieve room state: {}", e);
-                                    send_error_message_to_matrix_room(&room, err_txt, true).await;
                                 }
                             }
+                            Err(e) => {
+                                let err_txt = format!("Could not retrieve room state: {}", e);
+                                send_error_message_to_matrix_room(&room, err_txt, true).await;
+                            }
                         }
                     }
-                    _ => {
-                        error!("Matrix: Could not resolve room member!");
-                    }
+                }
+                _ => {
+                    error!("Matrix: Could not resolve room member!");
                 }
             }
         }
@@ -360,6 +1475,585 @@ pub async fn matrix_incoming_message_handler(
     }
 }
 
+/// Sends a "read" delivery receipt to the Threema sender, once a Matrix user has read the
+/// forwarded message. Receipts of the bot and its puppets are ignored.
+pub async fn matrix_read_receipt_handler(
+    event: SyncEphemeralRoomEvent<ReceiptEventContent>,
+    room: Room,
+    threema_client: Ctx<ThreemaClient>,
+    appservice: Ctx<Option<Appservice>>,
+    message_map: Ctx<MessageMap>,
+) {
+    let own_user_id = room.own_user_id().to_owned();
+    for (event_id, receipts) in event.content.iter() {
+        let read_by_user = receipts
+            .get(&ReceiptType::Read)
+            .map(|users| {
+                users.keys().any(|user_id| {
+                    let from_puppet = appservice
+                        .as_ref()
+                        .map(|appservice| appservice.is_puppet(user_id))
+                        .unwrap_or(false);
+                    *user_id != own_user_id && !from_puppet
+                })
+            })
+            .unwrap_or(false);
+        if !read_by_user {
+            continue;
+        }
+
+        let messages = match message_map.get_by_event_id(event_id) {
+            Ok(messages) => messages,
+            Err(e) => {
+                error!("Store: Could not look up event {}: {}", event_id, e);
+                continue;
+            }
+        };
+        for threema_message in messages {
+            if threema_message.direction != BridgeDirection::ThreemaToMatrix {
+                continue;
+            }
+            match message_map.mark_read(&threema_message.threema_message_id) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(e) => {
+                    error!("Store: Could not mark message as read: {}", e);
+                    continue;
+                }
+            }
+            if let Err(e) = threema_client
+                .send_delivery_receipt(
+                    &threema_message.threema_id,
+                    DeliveryReceiptStatus::Read,
+                    &[threema_message.threema_message_id],
+                )
+                .await
+            {
+                warn!("Threema: Could not send read receipt: {}", e);
+            }
+        }
+    }
+}
+
+/// Executes a bot command sent in a Matrix room, if the sender has a sufficient power level
+async fn handle_matrix_command(
+    command: MatrixCommand,
+    room: &Joined,
+    sender_member: &RoomMember,
+    threema_client: &ThreemaClient,
+    pending_bindings: &PendingBindings,
+    audit_log: &AuditLog,
+    binding_index: &BindingIndex,
+) {
+    debug!(
+        "Matrix: Command from {}: {:?}",
+        sender_member.user_id(),
+        command
+    );
+    if sender_member.power_level() < command.required_power_level() {
+        let err_txt = format!(
+            "This command needs power level {} or higher",
+            command.required_power_level()
+        );
+        send_error_message_to_matrix_room(room, err_txt, false).await;
+        return;
+    }
+
+    match command {
+        MatrixCommand::Help => send_notice_to_matrix_room(room, HELP_TEXT).await,
+        MatrixCommand::Unknown(name) => {
+            let err_txt = format!("Unknown command \"{}\"\n\n{}", name, HELP_TEXT);
+            send_error_message_to_matrix_room(room, err_txt, false).await;
+        }
+        MatrixCommand::Confirm(code) => {
+            confirm_binding(
+                room,
+                sender_member,
+                code.as_deref(),
+                threema_client,
+                pending_bindings,
+                audit_log,
+                binding_index,
+            )
+            .await
+        }
+        MatrixCommand::CreateGroup { name, members } => {
+            create_group(
+                room,
+                sender_member,
+                name.as_deref(),
+                &members,
+                threema_client,
+                audit_log,
+                binding_index,
+            )
+            .await
+        }
+        MatrixCommand::Status | MatrixCommand::Members | MatrixCommand::Unbind => {
+            let threematrix_state = match get_threematrix_room_state(room).await {
+                Ok(Some(threematrix_state)) => threematrix_state,
+                Ok(None) => {
+                    send_notice_to_matrix_room(room, "This room is not bound to a Threema group")
+                        .await;
+                    return;
+                }
+                Err(e) => {
+                    let err_txt = format!("Could not retrieve room state: {}", e);
+                    send_error_message_to_matrix_room(room, err_txt, true).await;
+                    return;
+                }
+            };
+            let readable_group_id = threematrix_state.threematrix_threema_group_id;
+            let group_id = match convert_group_id_from_readable_string(&readable_group_id) {
+                Ok(group_id) => group_id,
+                Err(e) => {
+                    let err_txt = format!("Room state contains an invalid group id: {}", e);
+                    send_error_message_to_matrix_room(room, err_txt, true).await;
+                    return;
+                }
+            };
+            // Bindings of older versions don't contain the group creator
+            let group_creator = match threematrix_state.threematrix_threema_group_creator {
+                Some(group_creator) => Some(group_creator),
+                None => threema_client.find_group_creator(&group_id).await,
+            };
+            let group = match &group_creator {
+                Some(group_creator) => threema_client.get_group(group_creator, &group_id).await,
+                None => None,
+            };
+
+            match command {
+                MatrixCommand::Status => {
+                    let status_txt = match group {
+                        Some(group) => format!(
+                            "This room is bound to Threema group {} (\"{}\") with {} members",
+                            readable_group_id,
+                            group.name,
+                            group.members.len()
+                        ),
+                        None => format!(
+                            "This room is bound to Threema group {}, whose members are not known yet",
+                            readable_group_id
+                        ),
+                    };
+                    send_notice_to_matrix_room(room, &status_txt).await;
+                }
+                MatrixCommand::Members => {
+                    let members_txt = match group {
+                        Some(group) => {
+                            let mut lines =
+                                vec![format!("Members of Threema group {}:", readable_group_id)];
+                            for member in &group.members {
+                                match threema_client.get_nickname(member).await {
+                                    Some(nickname) => {
+                                        lines.push(format!("{} ({})", member, nickname))
+                                    }
+                                    None => lines.push(member.to_owned()),
+                                }
+                            }
+                            lines.join("\n")
+                        }
+                        None => format!(
+                            "Members of Threema group {} are not known yet",
+                            readable_group_id
+                        ),
+                    };
+                    send_notice_to_matrix_room(room, &members_txt).await;
+                }
+                _ => {
+                    if let Err(e) = unbind_room(
+                        room,
+                        sender_member.user_id().as_str(),
+                        group_creator.as_deref(),
+                        &group_id,
+                        threema_client,
+                        audit_log,
+                        binding_index,
+                    )
+                    .await
+                    {
+                        let err_txt = format!("Could not set Matrix room state: {}", e);
+                        send_error_message_to_matrix_room(room, err_txt, true).await;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Removes the binding of the room and informs both sides. The state event can't be deleted,
+/// so it is overwritten with an empty group id.
+async fn unbind_room(
+    room: &Joined,
+    actor: &str,
+    group_creator: Option<&str>,
+    group_id: &[u8],
+    threema_client: &ThreemaClient,
+    audit_log: &AuditLog,
+    binding_index: &BindingIndex,
+) -> Result<(), matrix_sdk::Error> {
+    let content = ThreematrixStateEventContent {
+        threematrix_threema_group_id: "".to_owned(),
+        threematrix_threema_group_creator: None,
+    };
+    set_threematrix_room_state(content, room).await?;
+    if let Err(e) = binding_index.set(room.room_id(), None).await {
+        error!("Matrix: Could not update binding index: {}", e);
+    }
+
+    let readable_group_id = convert_group_id_to_readable_string(group_id).unwrap_or_default();
+    info!(
+        "Matrix: {} unbound room {} from Threema group {}",
+        actor,
+        room.room_id(),
+        readable_group_id
+    );
+    if let Err(e) = audit_log
+        .record(
+            AuditAction::Unbind,
+            actor,
+            room.room_id().as_str(),
+            &readable_group_id,
+        )
+        .await
+    {
+        error!("{}", e);
+    }
+
+    let notice = format!("Room has been unbound from the Threema group by {}", actor);
+    send_notice_to_matrix_room(room, &notice).await;
+    let text = format!(
+        "Group has been unbound from Matrix room {} by {}",
+        room.room_id(),
+        actor
+    );
+    let result = match group_creator {
+        Some(group_creator) => threema_client
+            .send_group_msg_by_group_id(text.as_str(), group_creator, group_id)
+            .await
+            .map(|_| ()),
+        None => Err(SendGroupMessageError::GroupNotInCache),
+    };
+    if let Err(e) = result {
+        error!("Threema: Could not send unbind text: {}", e)
+    }
+    return Ok(());
+}
+
+/// Writes the room state of a binding requested from Threema, after a moderator of the Matrix
+/// room confirmed it with the one-time code
+async fn confirm_binding(
+    room: &Joined,
+    sender_member: &RoomMember,
+    code: Option<&str>,
+    threema_client: &ThreemaClient,
+    pending_bindings: &PendingBindings,
+    audit_log: &AuditLog,
+    binding_index: &BindingIndex,
+) {
+    let code = match code {
+        Some(code) => code,
+        None => {
+            send_error_message_to_matrix_room(room, "Missing code!".to_owned(), false).await;
+            return;
+        }
+    };
+
+    let binding = match pending_bindings.confirm(room.room_id(), code).await {
+        Some(binding) => binding,
+        None => {
+            let err_txt = "Invalid or expired code. Request a new one with \"!threematrix bind\" in the Threema group.".to_owned();
+            send_error_message_to_matrix_room(room, err_txt, false).await;
+            return;
+        }
+    };
+
+    if convert_group_id_to_readable_string(&binding.group_id).is_err() {
+        error!("Threema: Group Id not valid!");
+        return;
+    }
+    if let Err(e) = bind_room(
+        room,
+        sender_member.user_id().as_str(),
+        &binding.group_id,
+        &binding.group_creator,
+        audit_log,
+        binding_index,
+    )
+    .await
+    {
+        let err_txt = format!("Could not set Matrix room state: {}", e);
+        send_error_message_to_matrix_room(room, err_txt, true).await;
+        return;
+    }
+
+    let notice = RoomMessageEventContent::notice_plain("Room has been bound to the Threema group");
+    if let Err(e) = room.send(notice, None).await {
+        error!("Matrix: Could not send bind confirmation: {}", e);
+    }
+    let succ_text = format!(
+        "Group has been successfully bound to Matrix room: {}",
+        room.room_id()
+    );
+    if let Err(e) = threema_client
+        .send_group_msg_by_group_id(
+            succ_text.as_str(),
+            &binding.group_creator,
+            binding.group_id.as_slice(),
+        )
+        .await
+    {
+        error!("Threema: Could not send bind text: {}", e)
+    }
+}
+
+/// Writes the room state of a binding and records it in the binding index and the audit log
+async fn bind_room(
+    room: &Joined,
+    actor: &str,
+    group_id: &[u8],
+    group_creator: &str,
+    audit_log: &AuditLog,
+    binding_index: &BindingIndex,
+) -> Result<(), matrix_sdk::Error> {
+    let readable_group_id = convert_group_id_to_readable_string(group_id).unwrap_or_default();
+    let content = ThreematrixStateEventContent {
+        threematrix_threema_group_id: readable_group_id.clone(),
+        threematrix_threema_group_creator: Some(group_creator.to_owned()),
+    };
+    set_threematrix_room_state(content, room).await?;
+    if let Err(e) = binding_index.set(room.room_id(), Some(group_id)).await {
+        error!("Matrix: Could not update binding index: {}", e);
+    }
+
+    info!(
+        "Matrix: {} bound room {} to Threema group {}",
+        actor,
+        room.room_id(),
+        readable_group_id
+    );
+    if let Err(e) = audit_log
+        .record(
+            AuditAction::Bind,
+            actor,
+            room.room_id().as_str(),
+            &readable_group_id,
+        )
+        .await
+    {
+        error!("{}", e);
+    }
+    return Ok(());
+}
+
+/// Creates a Threema group with the gateway ID as creator and binds it to the room
+async fn create_group(
+    room: &Joined,
+    sender_member: &RoomMember,
+    name: Option<&str>,
+    members: &[String],
+    threema_client: &ThreemaClient,
+    audit_log: &AuditLog,
+    binding_index: &BindingIndex,
+) {
+    let name = match name {
+        Some(name) if !members.is_empty() => name,
+        _ => {
+            let err_txt = "Usage: !threematrix create-group <name> <Threema ID>…".to_owned();
+            send_error_message_to_matrix_room(room, err_txt, false).await;
+            return;
+        }
+    };
+    let invalid_ids: Vec<&str> = members
+        .iter()
+        .filter(|member| !is_valid_threema_id(member))
+        .map(|member| member.as_str())
+        .collect();
+    if !invalid_ids.is_empty() {
+        let err_txt = format!("Invalid Threema IDs: {}", invalid_ids.join(", "));
+        send_error_message_to_matrix_room(room, err_txt, false).await;
+        return;
+    }
+
+    match get_threematrix_room_state(room).await {
+        Ok(Some(threematrix_state))
+            if !threematrix_state.threematrix_threema_group_id.is_empty() =>
+        {
+            let err_txt = "This room is already bound to a Threema group. Unbind it first with \"!threematrix unbind\".".to_owned();
+            send_error_message_to_matrix_room(room, err_txt, false).await;
+            return;
+        }
+        Ok(_) => {}
+        Err(e) => {
+            let err_txt = format!("Could not retrieve room state: {}", e);
+            send_error_message_to_matrix_room(room, err_txt, true).await;
+            return;
+        }
+    }
+
+    let group_id = match threema_client.create_group(name, members).await {
+        Ok(group_id) => group_id,
+        Err(e) => {
+            let err_txt = format!("Could not create Threema group: {}", e);
+            send_error_message_to_matrix_room(room, err_txt, true).await;
+            return;
+        }
+    };
+    if let Err(e) = bind_room(
+        room,
+        sender_member.user_id().as_str(),
+        &group_id,
+        threema_client.own_id(),
+        audit_log,
+        binding_index,
+    )
+    .await
+    {
+        let err_txt = format!("Could not set Matrix room state: {}", e);
+        send_error_message_to_matrix_room(room, err_txt, true).await;
+        return;
+    }
+
+    // Members, who missed the setup, get it again when they ask for a group sync
+    match threema_client.send_group_setup(&group_id).await {
+        Ok(_) => {
+            let notice = format!(
+                "Threema group \"{}\" has been created and bound to this room",
+                name
+            );
+            send_notice_to_matrix_room(room, &notice).await;
+        }
+        Err(e) => {
+            let err_txt = format!(
+                "Threema group \"{}\" has been created and bound to this room, but not every member could be added: {}",
+                name, e
+            );
+            send_error_message_to_matrix_room(room, err_txt, true).await;
+        }
+    }
+}
+
+fn thumbnail_source_with_type(
+    thumbnail_source: &Option<MediaSource>,
+    thumbnail_info: &Option<Box<ThumbnailInfo>>,
+) -> Option<(MediaSource, Option<String>)> {
+    let thumbnail_type = thumbnail_info
+        .as_ref()
+        .and_then(|thumbnail_info| thumbnail_info.mimetype.clone());
+    return thumbnail_source
+        .clone()
+        .map(|source| (source, thumbnail_type));
+}
+
+/// Downloads (and decrypts) the media of a Matrix attachment message, so that it can be
+/// forwarded to Threema. The Matrix body is kept as file name and, if it differs from it, as
+/// caption. Failed downloads are temporary errors, attachments without media permanent ones.
+async fn download_matrix_attachment(
+    matrix_client: &Client,
+    msgtype: &MessageType,
+    sender_name: &str,
+) -> Result<ThreemaFile, DeliveryError> {
+    let (body, file_name, media_type, thumbnail) = match msgtype {
+        MessageType::Image(content) => (
+            &content.body,
+            None,
+            content.info.as_ref().and_then(|info| info.mimetype.clone()),
+            content.info.as_ref().and_then(|info| {
+                thumbnail_source_with_type(&info.thumbnail_source, &info.thumbnail_info)
+            }),
+        ),
+        MessageType::File(content) => (
+            &content.body,
+            content.filename.clone(),
+            content.info.as_ref().and_then(|info| info.mimetype.clone()),
+            content.info.as_ref().and_then(|info| {
+                thumbnail_source_with_type(&info.thumbnail_source, &info.thumbnail_info)
+            }),
+        ),
+        MessageType::Video(content) => (
+            &content.body,
+            None,
+            content.info.as_ref().and_then(|info| info.mimetype.clone()),
+            content.info.as_ref().and_then(|info| {
+                thumbnail_source_with_type(&info.thumbnail_source, &info.thumbnail_info)
+            }),
+        ),
+        MessageType::Audio(content) => (
+            &content.body,
+            None,
+            content.info.as_ref().and_then(|info| info.mimetype.clone()),
+            None,
+        ),
+        _ => {
+            return Err(DeliveryError::Permanent(format!(
+                "Unsupported attachment type {}",
+                msgtype.msgtype()
+            )))
+        }
+    };
+
+    let data = match msgtype {
+        MessageType::Image(content) => matrix_client.get_file(content.clone(), false).await,
+        MessageType::File(content) => matrix_client.get_file(content.clone(), false).await,
+        MessageType::Video(content) => matrix_client.get_file(content.clone(), false).await,
+        MessageType::Audio(content) => matrix_client.get_file(content.clone(), false).await,
+        _ => Ok(None),
+    };
+    let data = match data {
+        Ok(Some(data)) => data,
+        // The event does not refer to any media
+        Ok(None) => {
+            return Err(DeliveryError::Permanent(
+                "The attachment has no media source".to_owned(),
+            ))
+        }
+        Err(e) => {
+            return Err(DeliveryError::Temporary(format!(
+                "Could not download attachment: {}",
+                e
+            )))
+        }
+    };
+
+    let thumbnail = match thumbnail {
+        Some((source, thumbnail_type)) => {
+            let request = MediaRequest {
+                source,
+                format: MediaFormat::File,
+            };
+            match matrix_client.get_media_content(&request, false).await {
+                Ok(thumbnail_data) => Some(ThreemaThumbnail {
+                    data: thumbnail_data,
+                    media_type: thumbnail_type.unwrap_or(mime::IMAGE_JPEG.to_string()),
+                }),
+                Err(e) => {
+                    warn!("Matrix: Could not download thumbnail: {}", e);
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+
+    let caption = matrix_attachment_caption(sender_name, body, file_name.as_deref());
+    return Ok(ThreemaFile {
+        data,
+        media_type: media_type.unwrap_or(mime::APPLICATION_OCTET_STREAM.to_string()),
+        file_name: Some(file_name.unwrap_or(body.clone())),
+        caption: Some(caption),
+        thumbnail,
+    });
+}
+
+async fn send_notice_to_matrix_room(room: &Joined, text: &str) {
+    let content = RoomMessageEventContent::notice_plain(text);
+    let txn_id = TransactionId::new();
+
+    if let Err(e) = room.send(content, Some(&txn_id)).await {
+        error!("Matrix: Could not send notice: \"{}\". {}", text, e)
+    }
+}
+
 async fn send_error_message_to_matrix_room(room: &Joined, err_txt: String, log_level_err: bool) {
     if log_level_err {
         error!("Matrix: {}", err_txt);
@@ -1,20 +1,44 @@
 use actix_web::{web, App, HttpServer};
 use flexi_logger::Logger;
 use futures::stream::StreamExt;
-use log::{debug, info};
+use log::{debug, error, info};
 use matrix_sdk::config::SyncSettings;
 use matrix_sdk::reqwest::Url;
-use matrix_sdk::Client;
+use matrix_sdk::{Client, LoopCtrl};
 use signal_hook::consts::{SIGINT, SIGQUIT, SIGTERM};
 use signal_hook_tokio::Signals;
+use std::env::args;
 use std::error::Error;
+use std::fs::write;
 use std::process;
+use std::time::Duration;
 use tokio::sync::Mutex;
 
-use threematrix::matrix::on_stripped_state_member;
+use matrix_sdk::store::make_store_config;
+use threematrix::audit_log::{AuditLog, DEFAULT_AUDIT_LOG_FILE};
+use threematrix::delivery_queue::{
+    DeliveryQueue, MATRIX_TO_THREEMA_TREE_NAME, THREEMA_TO_MATRIX_TREE_NAME,
+};
+use threematrix::matrix::appservice::Appservice;
+use threematrix::matrix::binding_index::{on_threematrix_state_event, BindingIndex};
+use threematrix::matrix::encryption::bootstrap_cross_signing;
+use threematrix::matrix::pending_bindings::PendingBindings;
+use threematrix::matrix::session::{login, save_session, DEFAULT_SESSION_FILE};
+use threematrix::matrix::transaction_log::TransactionLog;
+use threematrix::matrix::{on_room_member_invite, on_stripped_state_member};
+use threematrix::message_map::{
+    run_message_map_pruning, MessageMap, DEFAULT_MESSAGE_RETENTION_SECS,
+};
+use threematrix::threema::group_cache::GroupCache;
+use threematrix::threema::pending_messages::{
+    PendingMessageQueue, DEFAULT_PENDING_MESSAGE_LIMIT, DEFAULT_PENDING_MESSAGE_TTL_SECS,
+};
+use threematrix::threema::pubkey_cache::{PublicKeyCache, DEFAULT_PUBKEY_CACHE_TTL_SECS};
 use threematrix::threema::ThreemaClient;
 use threematrix::{
-    matrix_incoming_message_handler, threema_incoming_message_handler, AppState, LoggerConfig,
+    matrix_appservice_transaction_handler, matrix_incoming_message_handler,
+    matrix_read_receipt_handler, run_matrix_to_threema_worker, run_threema_to_matrix_worker,
+    threema_incoming_message_handler, AppState, LoggerConfig, MatrixHandlerStores,
     ThreematrixConfig,
 };
 
@@ -30,64 +54,218 @@ async fn main() -> Result<(), Box<dyn Error>> {
         "Starting Threematrix Server v{}. Waiting for Threema callback on {}:{}",
         VERSION,
         cfg.threema.host.clone().unwrap_or("localhost".to_owned()),
-        cfg.threema.port.clone().unwrap_or(443)
+        cfg.threema.port.unwrap_or(443)
     );
 
-    let mut signals = Signals::new(&[SIGTERM, SIGINT, SIGQUIT])?;
+    let mut signals = Signals::new([SIGTERM, SIGINT, SIGQUIT])?;
 
     if let Some(LoggerConfig { level }) = cfg.logger {
         logger.parse_new_spec(format!("{}={}", CRATE_NAME, level.as_str()).as_str())?
     }
 
-    let threema_client = ThreemaClient::new(
+    let store_path = cfg
+        .store
+        .as_ref()
+        .and_then(|store| store.path.clone())
+        .unwrap_or("./threematrix_store".to_owned());
+    let message_retention = Duration::from_secs(
+        cfg.store
+            .as_ref()
+            .and_then(|store| store.message_retention)
+            .unwrap_or(DEFAULT_MESSAGE_RETENTION_SECS),
+    );
+    let db = sled::open(&store_path)?;
+    let binding_index = BindingIndex::open(&db)?;
+    let threema_to_matrix_queue = DeliveryQueue::open(&db, THREEMA_TO_MATRIX_TREE_NAME)?;
+    let matrix_to_threema_queue = DeliveryQueue::open(&db, MATRIX_TO_THREEMA_TREE_NAME)?;
+    let message_map = MessageMap::open(&db)?;
+    let transaction_log = TransactionLog::open(&db)?;
+    debug!("Store: Opened store at {}", store_path);
+
+    let mut threema_client = ThreemaClient::new(
         &cfg.threema.gateway_own_id,
         &cfg.threema.secret,
         &cfg.threema.private_key,
+        GroupCache::open(&db)?,
+        PublicKeyCache::open(
+            &db,
+            Duration::from_secs(
+                cfg.threema
+                    .pubkey_cache_ttl
+                    .unwrap_or(DEFAULT_PUBKEY_CACHE_TTL_SECS),
+            ),
+        )?,
+        PendingMessageQueue::new(
+            Duration::from_secs(
+                cfg.threema
+                    .pending_message_ttl
+                    .unwrap_or(DEFAULT_PENDING_MESSAGE_TTL_SECS),
+            ),
+            cfg.threema
+                .pending_message_limit
+                .unwrap_or(DEFAULT_PENDING_MESSAGE_LIMIT),
+        ),
     )?;
+    if let Some(send_concurrency) = cfg.threema.send_concurrency {
+        threema_client.set_send_concurrency(send_concurrency);
+    }
 
     let homeserver_url = Url::parse(&cfg.matrix.homeserver_url)?;
-    let matrix_client = Client::new(homeserver_url).await?;
+    let appservice = cfg
+        .matrix
+        .appservice
+        .clone()
+        .map(|appservice_cfg| Appservice::new(appservice_cfg, homeserver_url.clone()));
+
+    if args().any(|arg| arg == "--generate-registration") {
+        let appservice = appservice.ok_or("Appservice mode is not configured")?;
+        write(
+            appservice.registration_file(),
+            serde_yaml::to_string(&appservice.registration())?,
+        )?;
+        info!(
+            "Appservice registration written to {}",
+            appservice.registration_file()
+        );
+        return Ok(());
+    }
+
+    // Persistent state and crypto store, so that the bot can take part in encrypted rooms
+    let matrix_store_path = cfg
+        .matrix
+        .store_path
+        .clone()
+        .unwrap_or("./threematrix_matrix_store".to_owned());
+    let matrix_store_config =
+        make_store_config(&matrix_store_path, cfg.matrix.store_passphrase.as_deref())?;
+    debug!("Matrix: Opened store at {}", matrix_store_path);
+
+    let session_path = cfg
+        .matrix
+        .session_file
+        .clone()
+        .unwrap_or(DEFAULT_SESSION_FILE.to_owned());
+    let (matrix_client, sync_token) = match &appservice {
+        Some(appservice) => (appservice.bot_client(matrix_store_config).await?, None),
+        None => {
+            let matrix_client = Client::builder()
+                .homeserver_url(homeserver_url)
+                .store_config(matrix_store_config)
+                .build()
+                .await?;
+            let sync_token = login(&matrix_client, &cfg.matrix, &session_path).await?;
+            (matrix_client, sync_token)
+        }
+    };
+
+    if args().any(|arg| arg == "--bootstrap-cross-signing") {
+        if appservice.is_some() {
+            return Err("Cross-signing bootstrap is not supported in appservice mode".into());
+        }
+        let password = cfg
+            .matrix
+            .password
+            .as_deref()
+            .ok_or("Cross-signing bootstrap needs the password of the Matrix user")?;
+        bootstrap_cross_signing(&matrix_client, &cfg.matrix.user, password).await?;
+        return Ok(());
+    }
 
+    let pending_bindings = PendingBindings::new();
+    let audit_log = AuditLog::new(
+        cfg.audit_log
+            .as_ref()
+            .and_then(|audit_log| audit_log.path.as_deref())
+            .unwrap_or(DEFAULT_AUDIT_LOG_FILE),
+    );
     let app_state = web::Data::new(AppState {
         threema_client: threema_client.clone(),
         matrix_client: Mutex::new(matrix_client.clone()),
+        appservice: appservice.clone(),
+        pending_bindings: pending_bindings.clone(),
+        audit_log: audit_log.clone(),
+        binding_index: binding_index.clone(),
+        threema_to_matrix_queue,
+        matrix_to_threema_queue: matrix_to_threema_queue.clone(),
+        message_map: message_map.clone(),
+        transaction_log,
     });
 
-    matrix_client
-        .login(
-            &cfg.matrix.user,
-            &cfg.matrix.password,
-            None,
-            Some("command bot"),
-        )
-        .await?;
-
     debug!("Matrix: Successfully logged in");
 
-    matrix_client
-        .sync_once(SyncSettings::default())
-        .await
-        .unwrap();
+    let mut initial_settings = SyncSettings::default();
+    if let Some(sync_token) = sync_token {
+        initial_settings = initial_settings.token(sync_token);
+    }
+    matrix_client.sync_once(initial_settings).await.unwrap();
 
     debug!("Matrix: Initial sync successful");
 
+    binding_index.rebuild(&matrix_client).await?;
+
     matrix_client
         .register_event_handler_context(threema_client.clone())
+        .register_event_handler_context(appservice.clone())
+        .register_event_handler_context(pending_bindings)
+        .register_event_handler_context(MatrixHandlerStores {
+            audit_log,
+            binding_index: binding_index.clone(),
+            delivery_queue: matrix_to_threema_queue,
+            message_map: message_map.clone(),
+        })
+        .register_event_handler_context(binding_index)
+        .register_event_handler_context(message_map)
         .register_event_handler(matrix_incoming_message_handler)
+        .await
+        .register_event_handler(on_threematrix_state_event)
         .await;
 
     matrix_client
         .register_event_handler(on_stripped_state_member)
         .await;
 
+    if appservice.is_some() {
+        matrix_client
+            .register_event_handler(on_room_member_invite)
+            .await;
+    }
+
+    if cfg.threema.send_read_receipts.unwrap_or(false) {
+        matrix_client
+            .register_event_handler(matrix_read_receipt_handler)
+            .await;
+    }
+
     let settings = SyncSettings::default().token(matrix_client.sync_token().await.unwrap());
 
+    // Deliver messages in the background, so that neither the gateway nor the homeserver has to
+    // wait for the other side
+    let workers = [
+        tokio::spawn(run_threema_to_matrix_worker(app_state.clone())),
+        tokio::spawn(run_matrix_to_threema_worker(app_state.clone())),
+        tokio::spawn(run_message_map_pruning(
+            app_state.message_map.clone(),
+            message_retention,
+        )),
+    ];
+
     let threema_server = tokio::spawn(
         HttpServer::new(move || {
-            App::new().app_data(app_state.clone()).route(
-                "/callback",
-                web::post().to(threema_incoming_message_handler),
-            )
+            App::new()
+                .app_data(app_state.clone())
+                .route(
+                    "/callback",
+                    web::post().to(threema_incoming_message_handler),
+                )
+                .route(
+                    "/_matrix/app/v1/transactions/{txn_id}",
+                    web::put().to(matrix_appservice_transaction_handler),
+                )
+                // Legacy path, which is still used by some homeservers
+                .route(
+                    "/transactions/{txn_id}",
+                    web::put().to(matrix_appservice_transaction_handler),
+                )
         })
         .bind((
             cfg.threema.host.unwrap_or("localhost".to_owned()),
@@ -96,13 +274,40 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .run(),
     );
 
-    let matrix_server = tokio::spawn(async move { matrix_client.sync(settings).await });
+    // In appservice mode the homeserver pushes events to the transaction endpoint instead
+    let matrix_server = match appservice {
+        Some(_) => None,
+        None => Some(tokio::spawn(async move {
+            // Keep the sync token, so that a restart continues where we left off
+            matrix_client
+                .sync_with_callback(settings, |response| {
+                    let matrix_client = matrix_client.clone();
+                    let session_path = session_path.clone();
+                    async move {
+                        if let Some(session) = matrix_client.session().await {
+                            if let Err(e) =
+                                save_session(&session_path, &session, Some(&response.next_batch))
+                            {
+                                error!("Matrix: Could not save session: {}", e);
+                            }
+                        }
+                        LoopCtrl::Continue
+                    }
+                })
+                .await
+        })),
+    };
 
-    while let Some(signal) = signals.next().await {
+    if let Some(signal) = signals.next().await {
         match signal {
             SIGTERM | SIGINT | SIGQUIT => {
-                matrix_server.abort();
+                if let Some(matrix_server) = &matrix_server {
+                    matrix_server.abort();
+                }
                 threema_server.abort();
+                for worker in &workers {
+                    worker.abort();
+                }
                 process::exit(1);
             }
             _ => unreachable!(),
@@ -0,0 +1,311 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use log::{debug, info};
+use matrix_sdk::config::{StoreConfig, SyncSettings};
+use matrix_sdk::reqwest::Url;
+use matrix_sdk::room::Joined;
+use matrix_sdk::ruma::api::client::account::register::{self, LoginType};
+use matrix_sdk::ruma::api::client::error::ErrorKind;
+use matrix_sdk::ruma::api::client::membership::leave_room;
+use matrix_sdk::ruma::api::client::uiaa::UiaaResponse;
+use matrix_sdk::ruma::api::error::{FromHttpResponseError, ServerError};
+use matrix_sdk::ruma::events::room::member::MembershipState;
+use matrix_sdk::ruma::{IdParseError, OwnedUserId, UserId};
+use matrix_sdk::{Client, HttpError, Session};
+use serde_derive::Serialize;
+use tokio::sync::Mutex;
+
+use crate::errors::AppserviceError;
+use crate::AppserviceConfig;
+
+pub const DEFAULT_PUPPET_PREFIX: &str = "threema_";
+pub const DEFAULT_REGISTRATION_FILE: &str = "./threematrix_registration.yaml";
+const DEVICE_ID: &str = "THREEMATRIX";
+
+/// Registration file, which tells the homeserver about the appservice
+#[derive(Debug, Serialize)]
+pub struct Registration {
+    pub id: String,
+    pub url: String,
+    pub as_token: String,
+    pub hs_token: String,
+    pub sender_localpart: String,
+    pub rate_limited: bool,
+    pub namespaces: Namespaces,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Namespaces {
+    pub users: Vec<Namespace>,
+    pub aliases: Vec<Namespace>,
+    pub rooms: Vec<Namespace>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Namespace {
+    pub exclusive: bool,
+    pub regex: String,
+}
+
+struct Puppet {
+    client: Client,
+    display_name: String,
+}
+
+/// Application service mode, in which every Threema user is represented by a virtual Matrix user
+#[derive(Clone)]
+pub struct Appservice {
+    config: AppserviceConfig,
+    homeserver_url: Url,
+    puppets: Arc<Mutex<HashMap<String, Puppet>>>,
+}
+
+impl Appservice {
+    pub fn new(config: AppserviceConfig, homeserver_url: Url) -> Appservice {
+        return Appservice {
+            config,
+            homeserver_url,
+            puppets: Arc::new(Mutex::new(HashMap::new())),
+        };
+    }
+
+    /// Checks the token, which the homeserver authenticated with, in constant time
+    pub fn is_valid_hs_token(&self, token: &str) -> bool {
+        return sodiumoxide::utils::memcmp(token.as_bytes(), self.config.hs_token.as_bytes());
+    }
+
+    pub fn registration_file(&self) -> &str {
+        return self
+            .config
+            .registration_file
+            .as_deref()
+            .unwrap_or(DEFAULT_REGISTRATION_FILE);
+    }
+
+    pub fn registration(&self) -> Registration {
+        let user_regex = format!(
+            "@{}.*:{}",
+            self.puppet_prefix(),
+            self.config.server_name.replace('.', "\\.")
+        );
+        return Registration {
+            id: self.config.id.clone(),
+            url: self.config.url.clone(),
+            as_token: self.config.as_token.clone(),
+            hs_token: self.config.hs_token.clone(),
+            sender_localpart: self.config.sender_localpart.clone(),
+            rate_limited: false,
+            namespaces: Namespaces {
+                users: vec![Namespace {
+                    exclusive: true,
+                    regex: user_regex,
+                }],
+                aliases: vec![],
+                rooms: vec![],
+            },
+        };
+    }
+
+    /// Logs in as the bot user of the appservice (`sender_localpart`)
+    pub async fn bot_client(&self, store_config: StoreConfig) -> Result<Client, AppserviceError> {
+        let user_id = UserId::parse(format!(
+            "@{}:{}",
+            self.config.sender_localpart, self.config.server_name
+        ))
+        .map_err(|e| AppserviceError::InvalidUserId(e))?;
+        return self.new_client(user_id, store_config).await;
+    }
+
+    /// Matrix user ID of the puppet of a Threema ID, e.g. `@threema_abcd1234:server`
+    pub fn puppet_user_id(&self, threema_id: &str) -> Result<OwnedUserId, IdParseError> {
+        // Matrix localparts must be lowercase and must not contain the * of Gateway IDs
+        let localpart = threema_id.to_lowercase().replace('*', "_");
+        return UserId::parse(format!(
+            "@{}{}:{}",
+            self.puppet_prefix(),
+            localpart,
+            self.config.server_name
+        ));
+    }
+
+    pub fn is_puppet(&self, user_id: &UserId) -> bool {
+        return user_id.server_name() == self.config.server_name.as_str()
+            && user_id.localpart().starts_with(self.puppet_prefix());
+    }
+
+    /// Returns the given room as seen by the puppet of a Threema user, which is invited and
+    /// joined first if necessary
+    pub async fn get_puppet_room(
+        &self,
+        threema_id: &str,
+        display_name: Option<&str>,
+        room: &Joined,
+    ) -> Result<Joined, AppserviceError> {
+        let puppet = self.get_puppet(threema_id, display_name).await?;
+        if let Some(puppet_room) = puppet.get_joined_room(room.room_id()) {
+            return Ok(puppet_room);
+        }
+
+        let user_id = self
+            .puppet_user_id(threema_id)
+            .map_err(|e| AppserviceError::InvalidUserId(e))?;
+        debug!("Matrix: Inviting {} to room {}", user_id, room.room_id());
+        // Fails if the puppet has been invited or joined before, which is fine
+        if let Err(e) = room.invite_user_by_id(&user_id).await {
+            debug!("Matrix: Could not invite {}: {}", user_id, e);
+        }
+        puppet
+            .join_room_by_id(room.room_id())
+            .await
+            .map_err(|e| AppserviceError::MatrixError(e.into()))?;
+
+        // The puppet only knows about rooms it has synced
+        let mut settings = SyncSettings::default();
+        if let Some(token) = puppet.sync_token().await {
+            settings = settings.token(token);
+        }
+        puppet
+            .sync_once(settings)
+            .await
+            .map_err(|e| AppserviceError::MatrixError(e))?;
+        info!("Matrix: {} joined room {}", user_id, room.room_id());
+
+        return puppet
+            .get_joined_room(room.room_id())
+            .ok_or(AppserviceError::PuppetNotJoined);
+    }
+
+    /// Makes the puppet of a Threema user leave the room
+    pub async fn leave_puppet_room(
+        &self,
+        threema_id: &str,
+        display_name: Option<&str>,
+        room: &Joined,
+    ) -> Result<(), AppserviceError> {
+        let user_id = self
+            .puppet_user_id(threema_id)
+            .map_err(|e| AppserviceError::InvalidUserId(e))?;
+        let is_joined = room
+            .get_member_no_sync(&user_id)
+            .await
+            .map_err(|e| AppserviceError::MatrixError(e))?
+            .map(|member| *member.membership() == MembershipState::Join)
+            .unwrap_or(false);
+        if !is_joined {
+            debug!("Matrix: {} is not in room {}", user_id, room.room_id());
+            return Ok(());
+        }
+
+        // Puppets keep their state in memory, so they might not know the room after a restart
+        let puppet = self.get_puppet(threema_id, display_name).await?;
+        puppet
+            .send(leave_room::v3::Request::new(room.room_id()), None)
+            .await
+            .map_err(|e| AppserviceError::MatrixError(e.into()))?;
+        info!("Matrix: {} left room {}", user_id, room.room_id());
+        return Ok(());
+    }
+
+    async fn get_puppet(
+        &self,
+        threema_id: &str,
+        display_name: Option<&str>,
+    ) -> Result<Client, AppserviceError> {
+        let mut puppets = self.puppets.lock().await;
+        let display_name = display_name.unwrap_or(threema_id);
+
+        if let Some(puppet) = puppets.get_mut(threema_id) {
+            if puppet.display_name != display_name {
+                puppet
+                    .client
+                    .account()
+                    .set_display_name(Some(display_name))
+                    .await
+                    .map_err(|e| AppserviceError::MatrixError(e))?;
+                puppet.display_name = display_name.to_owned();
+            }
+            return Ok(puppet.client.clone());
+        }
+
+        let user_id = self
+            .puppet_user_id(threema_id)
+            .map_err(|e| AppserviceError::InvalidUserId(e))?;
+        // Puppets keep their state in memory
+        let client = self
+            .new_client(user_id.clone(), StoreConfig::default())
+            .await?;
+        self.register_puppet(&client, &user_id).await?;
+        client
+            .account()
+            .set_display_name(Some(display_name))
+            .await
+            .map_err(|e| AppserviceError::MatrixError(e))?;
+
+        puppets.insert(
+            threema_id.to_owned(),
+            Puppet {
+                client: client.clone(),
+                display_name: display_name.to_owned(),
+            },
+        );
+        return Ok(client);
+    }
+
+    async fn register_puppet(
+        &self,
+        client: &Client,
+        user_id: &UserId,
+    ) -> Result<(), AppserviceError> {
+        let mut request = register::v3::Request::new();
+        request.username = Some(user_id.localpart());
+        request.login_type = Some(&LoginType::ApplicationService);
+        request.inhibit_login = true;
+
+        match client.register(request).await {
+            Ok(_) => {
+                info!("Matrix: Registered puppet {}", user_id);
+                return Ok(());
+            }
+            Err(HttpError::UiaaError(FromHttpResponseError::Server(ServerError::Known(
+                UiaaResponse::MatrixError(e),
+            )))) if e.kind == ErrorKind::UserInUse => {
+                debug!("Matrix: Puppet {} is already registered", user_id);
+                return Ok(());
+            }
+            Err(e) => return Err(AppserviceError::MatrixError(e.into())),
+        }
+    }
+
+    async fn new_client(
+        &self,
+        user_id: OwnedUserId,
+        store_config: StoreConfig,
+    ) -> Result<Client, AppserviceError> {
+        let client = Client::builder()
+            .homeserver_url(self.homeserver_url.clone())
+            .store_config(store_config)
+            .appservice_mode()
+            .assert_identity()
+            .build()
+            .await
+            .map_err(|e| AppserviceError::ClientBuildError(e))?;
+        client
+            .restore_login(Session {
+                access_token: self.config.as_token.clone(),
+                user_id,
+                device_id: DEVICE_ID.into(),
+            })
+            .await
+            .map_err(|e| AppserviceError::MatrixError(e))?;
+        return Ok(client);
+    }
+
+    fn puppet_prefix(&self) -> &str {
+        return self
+            .config
+            .puppet_prefix
+            .as_deref()
+            .unwrap_or(DEFAULT_PUPPET_PREFIX);
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use log::{debug, error, warn};
+use matrix_sdk::event_handler::Ctx;
+use matrix_sdk::room::Room;
+use matrix_sdk::ruma::events::OriginalSyncStateEvent;
+use matrix_sdk::ruma::{OwnedRoomId, RoomId};
+use matrix_sdk::Client;
+use tokio::sync::Mutex;
+
+use crate::errors::StoreError;
+use crate::matrix::util::{get_threematrix_room_state, ThreematrixStateEventContent};
+use crate::threema::util::convert_group_id_from_readable_string;
+
+const BINDINGS_TREE_NAME: &str = "matrix_bindings";
+
+struct Bindings {
+    rooms: HashMap<OwnedRoomId, Vec<u8>>,
+    groups: HashMap<Vec<u8>, HashSet<OwnedRoomId>>,
+    tree: sled::Tree,
+}
+
+impl Bindings {
+    fn insert_in_memory(&mut self, room_id: OwnedRoomId, group_id: Vec<u8>) {
+        self.groups
+            .entry(group_id.clone())
+            .or_default()
+            .insert(room_id.clone());
+        self.rooms.insert(room_id, group_id);
+    }
+
+    fn remove_in_memory(&mut self, room_id: &RoomId) {
+        if let Some(group_id) = self.rooms.remove(room_id) {
+            if let Some(rooms) = self.groups.get_mut(&group_id) {
+                rooms.remove(room_id);
+                if rooms.is_empty() {
+                    self.groups.remove(&group_id);
+                }
+            }
+        }
+    }
+}
+
+/// Index of Threema group id -> bound Matrix rooms, so that messages from Threema can be routed
+/// without reading the room state of every joined room. It is written through to the on-disk
+/// store and kept current from m.threematrix state events.
+#[derive(Clone)]
+pub struct BindingIndex {
+    bindings: Arc<Mutex<Bindings>>,
+}
+
+impl BindingIndex {
+    pub fn open(db: &sled::Db) -> Result<BindingIndex, StoreError> {
+        let tree = db
+            .open_tree(BINDINGS_TREE_NAME)
+            .map_err(|e| StoreError::DbError(e))?;
+
+        let mut bindings = Bindings {
+            rooms: HashMap::new(),
+            groups: HashMap::new(),
+            tree,
+        };
+        for entry in bindings.tree.iter() {
+            let (room_id, group_id) = entry.map_err(|e| StoreError::DbError(e))?;
+            match RoomId::parse(String::from_utf8_lossy(&room_id).as_ref()) {
+                Ok(room_id) => bindings.insert_in_memory(room_id, group_id.to_vec()),
+                Err(e) => warn!("Store: Ignoring binding with invalid room id: {}", e),
+            }
+        }
+        debug!(
+            "Matrix: Loaded {} bindings from store",
+            bindings.rooms.len()
+        );
+
+        return Ok(BindingIndex {
+            bindings: Arc::new(Mutex::new(bindings)),
+        });
+    }
+
+    /// Binds the room to the group or, if `group_id` is `None`, removes its binding
+    pub async fn set(&self, room_id: &RoomId, group_id: Option<&[u8]>) -> Result<(), StoreError> {
+        let mut bindings = self.bindings.lock().await;
+        if bindings.rooms.get(room_id).map(|id| id.as_slice()) == group_id {
+            return Ok(());
+        }
+
+        bindings.remove_in_memory(room_id);
+        match group_id {
+            Some(group_id) => {
+                bindings.insert_in_memory(room_id.to_owned(), group_id.to_vec());
+                bindings
+                    .tree
+                    .insert(room_id.as_str(), group_id)
+                    .map_err(|e| StoreError::DbError(e))?;
+            }
+            None => {
+                bindings
+                    .tree
+                    .remove(room_id.as_str())
+                    .map_err(|e| StoreError::DbError(e))?;
+            }
+        }
+        return Ok(());
+    }
+
+    pub async fn get_rooms(&self, group_id: &[u8]) -> Vec<OwnedRoomId> {
+        return self
+            .bindings
+            .lock()
+            .await
+            .groups
+            .get(group_id)
+            .map(|rooms| rooms.iter().cloned().collect())
+            .unwrap_or_default();
+    }
+
+    pub async fn get_group_id(&self, room_id: &RoomId) -> Option<Vec<u8>> {
+        return self.bindings.lock().await.rooms.get(room_id).cloned();
+    }
+
+    /// Reads the room state of all joined rooms and replaces the index with it. Stored bindings
+    /// of rooms, which the bot has left meanwhile, are removed.
+    pub async fn rebuild(&self, client: &Client) -> Result<(), StoreError> {
+        let joined_rooms = client.joined_rooms();
+        let stale_rooms: Vec<OwnedRoomId> = self
+            .bindings
+            .lock()
+            .await
+            .rooms
+            .keys()
+            .filter(|room_id| !joined_rooms.iter().any(|room| room.room_id() == *room_id))
+            .cloned()
+            .collect();
+        for room_id in stale_rooms {
+            self.set(&room_id, None).await?;
+        }
+
+        for room in joined_rooms {
+            match get_threematrix_room_state(&room).await {
+                Ok(state) => {
+                    let group_id = state.and_then(|state| {
+                        convert_group_id_from_readable_string(&state.threematrix_threema_group_id)
+                            .ok()
+                    });
+                    self.set(room.room_id(), group_id.as_deref()).await?;
+                }
+                Err(e) => warn!(
+                    "Matrix: Could not retrieve room state of {}: {}",
+                    room.room_id(),
+                    e
+                ),
+            }
+        }
+        debug!(
+            "Matrix: Indexed {} bound rooms",
+            self.bindings.lock().await.rooms.len()
+        );
+        return Ok(());
+    }
+}
+
+/// Keeps the binding index current, when the m.threematrix state of a room changes
+pub async fn on_threematrix_state_event(
+    event: OriginalSyncStateEvent<ThreematrixStateEventContent>,
+    room: Room,
+    binding_index: Ctx<BindingIndex>,
+) {
+    // An empty group id marks the room as unbound
+    let group_id =
+        convert_group_id_from_readable_string(&event.content.threematrix_threema_group_id).ok();
+    if let Err(e) = binding_index.set(room.room_id(), group_id.as_deref()).await {
+        error!("Matrix: Could not update binding index: {}", e);
+    }
+}
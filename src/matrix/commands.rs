@@ -0,0 +1,83 @@
+pub const COMMAND_PREFIX: &str = "!threematrix";
+/// Power level needed to inspect group members, or to change or confirm the binding (moderator)
+pub const MODERATOR_POWER_LEVEL: i64 = 50;
+
+pub const HELP_TEXT: &str = r#"Available commands:
+!threematrix status – Shows the bound Threema group
+!threematrix members – Lists the members of the bound Threema group (moderators only)
+!threematrix unbind – Removes the binding to the Threema group (moderators only)
+!threematrix confirm <code> – Confirms a binding requested from a Threema group (moderators only)
+!threematrix create-group <name> <Threema ID>… – Creates a Threema group with the given members and binds it to this room (moderators only). Put names with spaces in quotes.
+!threematrix help – Shows this help"#;
+
+/// Bot command sent in a Matrix room
+#[derive(Debug, PartialEq, Eq)]
+pub enum MatrixCommand {
+    Help,
+    Status,
+    Members,
+    Unbind,
+    Confirm(Option<String>),
+    CreateGroup {
+        name: Option<String>,
+        members: Vec<String>,
+    },
+    Unknown(String),
+}
+
+impl MatrixCommand {
+    /// Returns `None`, if the message is not meant for the bot
+    pub fn parse(body: &str) -> Option<MatrixCommand> {
+        let mut words = body.split_whitespace();
+        if words.next() != Some(COMMAND_PREFIX) {
+            return None;
+        }
+
+        let command = match words.next() {
+            None | Some("help") => MatrixCommand::Help,
+            Some("status") => MatrixCommand::Status,
+            Some("members") => MatrixCommand::Members,
+            Some("unbind") => MatrixCommand::Unbind,
+            Some("confirm") => MatrixCommand::Confirm(words.next().map(|code| code.to_owned())),
+            Some("create-group") => {
+                let (name, members) = split_group_name(words.collect::<Vec<&str>>().join(" "));
+                MatrixCommand::CreateGroup {
+                    name,
+                    members: members
+                        .split_whitespace()
+                        .map(|member| member.to_uppercase())
+                        .collect(),
+                }
+            }
+            Some(other) => MatrixCommand::Unknown(other.to_owned()),
+        };
+        return Some(command);
+    }
+
+    pub fn required_power_level(&self) -> i64 {
+        match self {
+            MatrixCommand::Help | MatrixCommand::Status | MatrixCommand::Unknown(_) => return 0,
+            MatrixCommand::Members
+            | MatrixCommand::Unbind
+            | MatrixCommand::Confirm(_)
+            | MatrixCommand::CreateGroup { .. } => return MODERATOR_POWER_LEVEL,
+        }
+    }
+}
+
+/// Splits the group name from the rest of the arguments. The name is either the first word or
+/// enclosed in quotes.
+fn split_group_name(arguments: String) -> (Option<String>, String) {
+    let arguments = arguments.trim_start();
+    let (name, rest) = match arguments.strip_prefix('"') {
+        Some(quoted) => match quoted.split_once('"') {
+            Some((name, rest)) => (name.trim(), rest),
+            None => (quoted.trim(), ""),
+        },
+        None => arguments.split_once(' ').unwrap_or((arguments, "")),
+    };
+    if name.is_empty() {
+        return (None, rest.to_owned());
+    }
+    return (Some(name.to_owned()), rest.to_owned());
+}
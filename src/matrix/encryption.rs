@@ -0,0 +1,37 @@
+use log::{debug, info};
+use matrix_sdk::ruma::api::client::uiaa::{AuthData, Password, UserIdentifier};
+use matrix_sdk::Client;
+
+/// Creates and uploads a cross-signing identity for the bot and signs its own device with it,
+/// so that Matrix users only have to verify the bot user once
+pub async fn bootstrap_cross_signing(
+    client: &Client,
+    user: &str,
+    password: &str,
+) -> Result<(), matrix_sdk::Error> {
+    if let Some(status) = client.encryption().cross_signing_status().await {
+        if status.has_master && status.has_self_signing && status.has_user_signing {
+            info!("Matrix: Cross-signing is already set up");
+            return Ok(());
+        }
+    }
+
+    // The first request fails and tells us how to authenticate
+    if let Err(e) = client.encryption().bootstrap_cross_signing(None).await {
+        if let Some(response) = e.uiaa_response() {
+            debug!("Matrix: Authenticating cross-signing bootstrap with password");
+            let mut password_auth =
+                Password::new(UserIdentifier::UserIdOrLocalpart(user), password);
+            password_auth.session = response.session.as_deref();
+            client
+                .encryption()
+                .bootstrap_cross_signing(Some(AuthData::Password(password_auth)))
+                .await?;
+        } else {
+            return Err(e);
+        }
+    }
+
+    info!("Matrix: Cross-signing has been set up");
+    return Ok(());
+}
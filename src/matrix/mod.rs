@@ -1,7 +1,17 @@
+pub mod appservice;
+pub mod binding_index;
+pub mod commands;
+pub mod encryption;
+pub mod pending_bindings;
+pub mod session;
+pub mod transaction_log;
 pub mod util;
 
 use log::{debug, error, info};
-use matrix_sdk::{room::Room, ruma::events::room::member::StrippedRoomMemberEvent, Client};
+use matrix_sdk::ruma::events::room::member::{
+    MembershipState, OriginalSyncRoomMemberEvent, StrippedRoomMemberEvent,
+};
+use matrix_sdk::{room::Room, Client};
 use tokio::time::{sleep, Duration};
 
 // Source: https://github.com/matrix-org/matrix-rust-sdk/blob/matrix-sdk-0.5.0/crates/matrix-sdk/examples/autojoin.rs
@@ -40,3 +50,22 @@ pub async fn on_stripped_state_member(
         info!("Matrix: Successfully joined room {}", room.room_id());
     }
 }
+
+/// In appservice mode invites arrive as timeline events of a transaction instead of stripped state
+pub async fn on_room_member_invite(
+    room_member: OriginalSyncRoomMemberEvent,
+    client: Client,
+    room: Room,
+) {
+    if room_member.state_key != client.user_id().await.unwrap()
+        || room_member.content.membership != MembershipState::Invite
+    {
+        return;
+    }
+
+    debug!("Matrix: Autojoining room {}", room.room_id());
+    match client.join_room_by_id(room.room_id()).await {
+        Ok(_) => info!("Matrix: Successfully joined room {}", room.room_id()),
+        Err(e) => error!("Matrix: Can't join room {} ({:?})", room.room_id(), e),
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use matrix_sdk::ruma::{OwnedRoomId, RoomId};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use tokio::sync::Mutex;
+
+/// Time a Matrix user has to confirm a binding requested from Threema
+pub const PENDING_BINDING_TTL: Duration = Duration::from_secs(10 * 60);
+const CODE_LENGTH: usize = 8;
+
+pub struct PendingBinding {
+    pub group_id: Vec<u8>,
+    pub group_creator: String,
+    code: String,
+    created_at: Instant,
+}
+
+/// Bindings requested by a Threema group, which still have to be confirmed in the Matrix room
+#[derive(Clone, Default)]
+pub struct PendingBindings {
+    bindings: Arc<Mutex<HashMap<OwnedRoomId, PendingBinding>>>,
+}
+
+impl PendingBindings {
+    pub fn new() -> PendingBindings {
+        return PendingBindings::default();
+    }
+
+    /// Creates a pending binding for the room and returns its one-time code. Returns `None`, if
+    /// the room already has a pending binding, which has not expired yet, so that it can't be
+    /// replaced by another group.
+    pub async fn create(
+        &self,
+        room_id: &RoomId,
+        group_id: &[u8],
+        group_creator: &str,
+    ) -> Option<String> {
+        let mut bindings = self.bindings.lock().await;
+        bindings.retain(|_, binding| binding.created_at.elapsed() < PENDING_BINDING_TTL);
+        if bindings.contains_key(room_id) {
+            return None;
+        }
+
+        let code: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(CODE_LENGTH)
+            .map(char::from)
+            .collect::<String>()
+            .to_uppercase();
+
+        bindings.insert(
+            room_id.to_owned(),
+            PendingBinding {
+                group_id: group_id.to_vec(),
+                group_creator: group_creator.to_owned(),
+                code: code.clone(),
+                created_at: Instant::now(),
+            },
+        );
+        return Some(code);
+    }
+
+    /// Returns the pending binding of the room, if the code matches and has not expired yet.
+    /// The code can only be used once.
+    pub async fn confirm(&self, room_id: &RoomId, code: &str) -> Option<PendingBinding> {
+        let mut bindings = self.bindings.lock().await;
+        bindings.retain(|_, binding| binding.created_at.elapsed() < PENDING_BINDING_TTL);
+
+        match bindings.get(room_id) {
+            Some(binding) if binding.code.eq_ignore_ascii_case(code) => {
+                return bindings.remove(room_id);
+            }
+            _ => return None,
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use std::fs::{read_to_string, rename, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+
+use log::{debug, info, warn};
+use matrix_sdk::ruma::{OwnedDeviceId, OwnedUserId, UserId};
+use matrix_sdk::{Client, Session};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::errors::SessionError;
+use crate::MatrixConfig;
+
+pub const DEFAULT_DEVICE_ID: &str = "THREEMATRIX";
+pub const DEFAULT_SESSION_FILE: &str = "./threematrix_session.json";
+
+/// Matrix session of the bot, which is kept between restarts
+#[derive(Debug, Serialize, Deserialize)]
+struct SessionFile {
+    user_id: OwnedUserId,
+    device_id: OwnedDeviceId,
+    access_token: String,
+    sync_token: Option<String>,
+}
+
+pub fn load_session(path: &str) -> Option<(Session, Option<String>)> {
+    let session_file: SessionFile = match read_to_string(path) {
+        Ok(content) => match serde_json::from_str(&content) {
+            Ok(session_file) => session_file,
+            Err(e) => {
+                warn!("Matrix: Ignoring invalid session file {}: {}", path, e);
+                return None;
+            }
+        },
+        Err(e) => {
+            debug!("Matrix: Could not read session file {}: {}", path, e);
+            return None;
+        }
+    };
+
+    let session = Session {
+        access_token: session_file.access_token,
+        user_id: session_file.user_id,
+        device_id: session_file.device_id,
+    };
+    return Some((session, session_file.sync_token));
+}
+
+/// Writes the session file, if the session changed. It contains the access token, so only the
+/// owner may read it. The file is replaced at once, so that a crash can't leave it half written.
+pub fn save_session(
+    path: &str,
+    session: &Session,
+    sync_token: Option<&str>,
+) -> Result<(), SessionError> {
+    let session_file = SessionFile {
+        user_id: session.user_id.clone(),
+        device_id: session.device_id.clone(),
+        access_token: session.access_token.clone(),
+        sync_token: sync_token.map(|token| token.to_owned()),
+    };
+    let content = serde_json::to_string_pretty(&session_file)
+        .map_err(|e| SessionError::SerializationError(e))?;
+    if read_to_string(path).is_ok_and(|saved| saved == content) {
+        return Ok(());
+    }
+
+    let tmp_path = format!("{}.tmp", path);
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp_path)
+        .map_err(|e| SessionError::IoError(e))?;
+    file.write_all(content.as_bytes())
+        .map_err(|e| SessionError::IoError(e))?;
+    file.sync_all().map_err(|e| SessionError::IoError(e))?;
+    rename(&tmp_path, path).map_err(|e| SessionError::IoError(e))?;
+    return Ok(());
+}
+
+/// Restores the session from the session file. Otherwise logs in with the configured access token
+/// or as fallback with the password. Returns the sync token of a restored session.
+pub async fn login(
+    client: &Client,
+    config: &MatrixConfig,
+    session_path: &str,
+) -> Result<Option<String>, SessionError> {
+    if let Some((session, sync_token)) = load_session(session_path) {
+        client
+            .restore_login(session)
+            .await
+            .map_err(|e| SessionError::MatrixError(Box::new(e)))?;
+        match client.whoami().await {
+            Ok(_) => {
+                info!("Matrix: Restored session from {}", session_path);
+                return Ok(sync_token);
+            }
+            Err(e) => warn!("Matrix: Stored session is not valid anymore: {}", e),
+        }
+    }
+
+    // Always use the same device, because the crypto store belongs to it
+    let device_id = config.device_id.as_deref().unwrap_or(DEFAULT_DEVICE_ID);
+
+    if let Some(access_token) = &config.access_token {
+        let user_id =
+            UserId::parse(config.user.as_str()).map_err(|e| SessionError::InvalidUserId(e))?;
+        client
+            .restore_login(Session {
+                access_token: access_token.clone(),
+                user_id,
+                device_id: device_id.into(),
+            })
+            .await
+            .map_err(|e| SessionError::MatrixError(Box::new(e)))?;
+        client
+            .whoami()
+            .await
+            .map_err(|e| SessionError::MatrixError(Box::new(e.into())))?;
+        info!("Matrix: Logged in with access token");
+    } else if let Some(password) = &config.password {
+        client
+            .login(&config.user, password, Some(device_id), Some("command bot"))
+            .await
+            .map_err(|e| SessionError::MatrixError(Box::new(e)))?;
+        info!("Matrix: Logged in with password");
+    } else {
+        return Err(SessionError::MissingCredentials);
+    }
+
+    if let Some(session) = client.session().await {
+        save_session(session_path, &session, None)?;
+    }
+    return Ok(None);
+}
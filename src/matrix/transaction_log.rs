@@ -0,0 +1,64 @@
+use std::time::Duration;
+
+use crate::errors::StoreError;
+use crate::util::now;
+
+const TRANSACTIONS_TREE_NAME: &str = "matrix_appservice_transactions";
+/// The homeserver only sends a transaction again, until it was acknowledged, so older ids are
+/// forgotten on startup
+pub const TRANSACTION_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Ids of the processed appservice transactions, so that a transaction, which the homeserver sends
+/// again, is not dispatched twice
+#[derive(Clone)]
+pub struct TransactionLog {
+    tree: sled::Tree,
+}
+
+impl TransactionLog {
+    pub fn open(db: &sled::Db) -> Result<TransactionLog, StoreError> {
+        let tree = db
+            .open_tree(TRANSACTIONS_TREE_NAME)
+            .map_err(|e| StoreError::DbError(e))?;
+        let transaction_log = TransactionLog { tree };
+        transaction_log.prune(TRANSACTION_RETENTION)?;
+        return Ok(transaction_log);
+    }
+
+    pub fn contains(&self, txn_id: &str) -> Result<bool, StoreError> {
+        return self
+            .tree
+            .contains_key(txn_id.as_bytes())
+            .map_err(|e| StoreError::DbError(e));
+    }
+
+    pub fn insert(&self, txn_id: &str) -> Result<(), StoreError> {
+        self.tree
+            .insert(txn_id.as_bytes(), &now().to_be_bytes())
+            .map_err(|e| StoreError::DbError(e))?;
+        return Ok(());
+    }
+
+    /// Removes the ids, which were processed before the retention period. Returns the number of
+    /// removed ids.
+    pub fn prune(&self, retention: Duration) -> Result<usize, StoreError> {
+        let cutoff = now().saturating_sub(retention.as_secs());
+        let mut pruned = 0;
+        for entry in self.tree.iter() {
+            let (txn_id, processed_at) = entry.map_err(|e| StoreError::DbError(e))?;
+            let processed_at = processed_at
+                .as_ref()
+                .try_into()
+                .map(u64::from_be_bytes)
+                .unwrap_or(0);
+            if processed_at >= cutoff {
+                continue;
+            }
+            self.tree
+                .remove(txn_id)
+                .map_err(|e| StoreError::DbError(e))?;
+            pruned += 1;
+        }
+        return Ok(pruned);
+    }
+}
@@ -1,7 +1,7 @@
 use log::debug;
 use matrix_sdk::room::Joined;
 use matrix_sdk::ruma::events::macros::EventContent;
-use matrix_sdk::ruma::events::SyncStateEvent::Original;
+use matrix_sdk::ruma::events::SyncStateEvent::{self, Original};
 
 use serde_derive::{Deserialize, Serialize};
 
@@ -11,6 +11,9 @@ use crate::util::retry_request;
 #[ruma_event(type = "m.threematrix", kind = State, state_key_type = String)]
 pub struct ThreematrixStateEventContent {
     pub threematrix_threema_group_id: String,
+    /// Creator of the Threema group, who is asked for the group members if they are unknown
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threematrix_threema_group_creator: Option<String>,
 }
 
 pub async fn set_threematrix_room_state(
@@ -34,10 +37,13 @@ pub async fn get_threematrix_room_state(
         retry_request(|| async { room.get_state_event_static("").await }, 20000, 6).await?;
 
     if let Some(raw) = sync_state {
-        let sync_state = raw.deserialize().unwrap();
+        let sync_state: SyncStateEvent<ThreematrixStateEventContent> = raw.deserialize().unwrap();
 
+        // An empty group id is written when a room is unbound
         if let Original(event) = sync_state {
-            return Ok(Some(event.content));
+            if !event.content.threematrix_threema_group_id.is_empty() {
+                return Ok(Some(event.content));
+            }
         }
     }
     return Ok(None);
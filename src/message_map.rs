@@ -0,0 +1,222 @@
+use std::time::Duration;
+
+use log::{error, info};
+use matrix_sdk::ruma::{EventId, OwnedEventId, OwnedRoomId, UserId};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::errors::StoreError;
+use crate::util::now;
+
+const BY_THREEMA_ID_TREE_NAME: &str = "messages_by_threema_id";
+const BY_EVENT_ID_TREE_NAME: &str = "messages_by_event_id";
+const REACTIONS_TREE_NAME: &str = "messages_reactions";
+const READ_TREE_NAME: &str = "messages_read";
+
+pub const DEFAULT_MESSAGE_RETENTION_SECS: u64 = 30 * 24 * 60 * 60;
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Separates the two ids of a key. It is not valid UTF-8, so it can't be part of an id.
+const KEY_SEPARATOR: u8 = 0xff;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BridgeDirection {
+    ThreemaToMatrix,
+    MatrixToThreema,
+}
+
+/// A message, which has been bridged from one side to the other
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BridgedMessage {
+    pub direction: BridgeDirection,
+    /// Sender of an incoming, receiver of an outgoing Threema message
+    pub threema_id: String,
+    pub threema_message_id: String,
+    pub group_id: Vec<u8>,
+    pub room_id: OwnedRoomId,
+    pub event_id: OwnedEventId,
+    /// Unix timestamp in seconds of the bridging
+    pub timestamp: u64,
+}
+
+impl BridgedMessage {
+    pub fn new(
+        direction: BridgeDirection,
+        threema_id: &str,
+        threema_message_id: &str,
+        group_id: &[u8],
+        room_id: OwnedRoomId,
+        event_id: OwnedEventId,
+    ) -> BridgedMessage {
+        return BridgedMessage {
+            direction,
+            threema_id: threema_id.to_owned(),
+            threema_message_id: threema_message_id.to_owned(),
+            group_id: group_id.to_vec(),
+            room_id,
+            event_id,
+            timestamp: now(),
+        };
+    }
+}
+
+fn key(first: &str, second: &str) -> Vec<u8> {
+    let mut key = prefix(first);
+    key.extend_from_slice(second.as_bytes());
+    return key;
+}
+
+fn prefix(id: &str) -> Vec<u8> {
+    let mut prefix = id.as_bytes().to_vec();
+    prefix.push(KEY_SEPARATOR);
+    return prefix;
+}
+
+/// On-disk mapping between bridged Threema messages and Matrix events, which can be looked up
+/// from both sides. A Threema message can belong to several events (one per bound room) and a
+/// Matrix event to several Threema messages (one per group member).
+#[derive(Clone)]
+pub struct MessageMap {
+    by_threema_id: sled::Tree,
+    by_event_id: sled::Tree,
+    reactions: sled::Tree,
+    read: sled::Tree,
+}
+
+impl MessageMap {
+    pub fn open(db: &sled::Db) -> Result<MessageMap, StoreError> {
+        let by_threema_id = db
+            .open_tree(BY_THREEMA_ID_TREE_NAME)
+            .map_err(|e| StoreError::DbError(e))?;
+        let by_event_id = db
+            .open_tree(BY_EVENT_ID_TREE_NAME)
+            .map_err(|e| StoreError::DbError(e))?;
+        let reactions = db
+            .open_tree(REACTIONS_TREE_NAME)
+            .map_err(|e| StoreError::DbError(e))?;
+        let read = db
+            .open_tree(READ_TREE_NAME)
+            .map_err(|e| StoreError::DbError(e))?;
+        return Ok(MessageMap {
+            by_threema_id,
+            by_event_id,
+            reactions,
+            read,
+        });
+    }
+
+    pub fn insert(&self, message: &BridgedMessage) -> Result<(), StoreError> {
+        let serialized =
+            serde_json::to_vec(message).map_err(|e| StoreError::SerializationError(e))?;
+        self.by_threema_id
+            .insert(
+                key(&message.threema_message_id, message.event_id.as_str()),
+                serialized.clone(),
+            )
+            .map_err(|e| StoreError::DbError(e))?;
+        self.by_event_id
+            .insert(
+                key(message.event_id.as_str(), &message.threema_message_id),
+                serialized,
+            )
+            .map_err(|e| StoreError::DbError(e))?;
+        return Ok(());
+    }
+
+    pub fn get_by_threema_id(
+        &self,
+        threema_message_id: &str,
+    ) -> Result<Vec<BridgedMessage>, StoreError> {
+        return scan(&self.by_threema_id, threema_message_id);
+    }
+
+    pub fn get_by_event_id(&self, event_id: &EventId) -> Result<Vec<BridgedMessage>, StoreError> {
+        return scan(&self.by_event_id, event_id.as_str());
+    }
+
+    /// Remembers that the Threema message has been marked as read. Returns `false`, if it already
+    /// was, so that the sender gets only one receipt.
+    pub fn mark_read(&self, threema_message_id: &str) -> Result<bool, StoreError> {
+        let previous = self
+            .read
+            .insert(threema_message_id, &[])
+            .map_err(|e| StoreError::DbError(e))?;
+        return Ok(previous.is_none());
+    }
+
+    /// Remembers that the user reacted to the event with the key. Returns `false`, if they
+    /// already did, because the homeserver rejects the same reaction twice.
+    pub fn add_reaction(
+        &self,
+        event_id: &EventId,
+        user_id: &UserId,
+        key: &str,
+    ) -> Result<bool, StoreError> {
+        let reaction = format!("{}|{}|{}", event_id, user_id, key);
+        let previous = self
+            .reactions
+            .insert(reaction.as_bytes(), &[])
+            .map_err(|e| StoreError::DbError(e))?;
+        return Ok(previous.is_none());
+    }
+
+    /// Removes the messages, which have been bridged before the retention period, together with
+    /// their reactions and read markers. Returns the number of removed messages.
+    pub fn prune(&self, retention: Duration) -> Result<usize, StoreError> {
+        let cutoff = now().saturating_sub(retention.as_secs());
+        let mut pruned = 0;
+        for entry in self.by_threema_id.iter() {
+            let (threema_key, serialized) = entry.map_err(|e| StoreError::DbError(e))?;
+            let message: BridgedMessage = serde_json::from_slice(&serialized)
+                .map_err(|e| StoreError::SerializationError(e))?;
+            if message.timestamp >= cutoff {
+                continue;
+            }
+
+            self.by_threema_id
+                .remove(threema_key)
+                .map_err(|e| StoreError::DbError(e))?;
+            self.by_event_id
+                .remove(key(message.event_id.as_str(), &message.threema_message_id))
+                .map_err(|e| StoreError::DbError(e))?;
+            self.read
+                .remove(message.threema_message_id.as_bytes())
+                .map_err(|e| StoreError::DbError(e))?;
+            let reaction_prefix = format!("{}|", message.event_id);
+            for reaction in self
+                .reactions
+                .scan_prefix(reaction_prefix.as_bytes())
+                .keys()
+            {
+                let reaction = reaction.map_err(|e| StoreError::DbError(e))?;
+                self.reactions
+                    .remove(reaction)
+                    .map_err(|e| StoreError::DbError(e))?;
+            }
+            pruned += 1;
+        }
+        return Ok(pruned);
+    }
+}
+
+fn scan(tree: &sled::Tree, id: &str) -> Result<Vec<BridgedMessage>, StoreError> {
+    return tree
+        .scan_prefix(prefix(id))
+        .values()
+        .map(|serialized| {
+            let serialized = serialized.map_err(|e| StoreError::DbError(e))?;
+            serde_json::from_slice(&serialized).map_err(|e| StoreError::SerializationError(e))
+        })
+        .collect();
+}
+
+/// Prunes the message map regularly, so that it does not grow forever
+pub async fn run_message_map_pruning(message_map: MessageMap, retention: Duration) {
+    let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+    loop {
+        interval.tick().await;
+        match message_map.prune(retention) {
+            Ok(0) => {}
+            Ok(pruned) => info!("Store: Pruned {} bridged messages", pruned),
+            Err(e) => error!("Store: Could not prune bridged messages: {}", e),
+        }
+    }
+}
@@ -0,0 +1,119 @@
+/// Length of the hex encoded message id in a quote
+const QUOTED_MESSAGE_ID_LENGTH: usize = 16;
+const QUOTE_V2_PREFIX: &str = "> quote #";
+
+/// Quote at the beginning of a Threema text message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ThreemaQuote {
+    /// Newer clients refer to the quoted message by its hex encoded id
+    MessageId(String),
+    /// Older clients copy the quoted text
+    Text(String),
+}
+
+/// Splits a Threema text into its quote and the actual reply. Returns `None`, if the text does
+/// not start with a quote.
+pub fn parse_threema_quote(text: &str) -> Option<(ThreemaQuote, String)> {
+    // Quote V2: "> quote #<message id>", a blank line and the reply
+    if let Some(rest) = text.strip_prefix(QUOTE_V2_PREFIX) {
+        if let Some(message_id) = rest.get(..QUOTED_MESSAGE_ID_LENGTH) {
+            let reply =
+                strip_line_break(&rest[QUOTED_MESSAGE_ID_LENGTH..]).and_then(strip_line_break);
+            if let Some(reply) = reply {
+                if message_id
+                    .chars()
+                    .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+                    && !reply.is_empty()
+                {
+                    return Some((
+                        ThreemaQuote::MessageId(message_id.to_owned()),
+                        reply.to_owned(),
+                    ));
+                }
+            }
+        }
+    }
+
+    let (quoted, reply) = split_quote(text)?;
+    return Some((ThreemaQuote::Text(quoted), reply));
+}
+
+fn strip_line_break(text: &str) -> Option<&str> {
+    return text
+        .strip_prefix("\r\n")
+        .or_else(|| text.strip_prefix('\n'));
+}
+
+/// Puts the quote in front of the reply, the way Threema clients show it
+pub fn format_threema_quote(quote: &ThreemaQuote, reply: &str) -> String {
+    match quote {
+        ThreemaQuote::MessageId(message_id) => {
+            return format!("{}{}\n\n{}", QUOTE_V2_PREFIX, message_id, reply);
+        }
+        ThreemaQuote::Text(quoted) => {
+            let quoted_lines: Vec<String> =
+                quoted.lines().map(|line| format!("> {}", line)).collect();
+            return format!("{}\n\n{}", quoted_lines.join("\n"), reply);
+        }
+    }
+}
+
+/// Removes the fallback, which Matrix clients put in front of the body of a reply. Returns the
+/// quoted text without its sender and the actual reply.
+pub fn strip_matrix_reply_fallback(body: &str) -> (Option<String>, String) {
+    match split_quote(body) {
+        Some((quoted, reply)) => {
+            // The fallback starts with the sender, e.g. "<@alice:example.org> "
+            let quoted = match quoted
+                .strip_prefix('<')
+                .and_then(|quoted| quoted.split_once("> "))
+            {
+                Some((_, quoted)) => quoted.to_owned(),
+                None => quoted,
+            };
+            return (Some(quoted), reply);
+        }
+        None => return (None, body.to_owned()),
+    }
+}
+
+/// Checks, if the body of a Matrix message is the quoted text. Either of them may be prefixed with
+/// the name of the sender, if it was bridged.
+pub fn matches_quoted_text(body: &str, quoted_text: &str) -> bool {
+    let (_, body) = strip_matrix_reply_fallback(body);
+    let body = body.trim();
+    let quoted_text = quoted_text.trim();
+    if body.is_empty() || quoted_text.is_empty() {
+        return false;
+    }
+    return body == quoted_text
+        || body.ends_with(&format!(": {}", quoted_text))
+        || quoted_text.ends_with(&format!(": {}", body));
+}
+
+/// Splits the leading "> " lines from the rest of the text, which usually follows after an empty
+/// line. A quote without reply is not split.
+fn split_quote(text: &str) -> Option<(String, String)> {
+    let lines: Vec<&str> = text.split('\n').collect();
+    let quote_length = lines
+        .iter()
+        .take_while(|line| line.starts_with('>'))
+        .count();
+    if quote_length == 0 {
+        return None;
+    }
+
+    let reply = lines[quote_length..].join("\n");
+    let reply = reply.trim_start_matches('\n');
+    if reply.is_empty() {
+        return None;
+    }
+    let quoted_lines: Vec<&str> = lines[..quote_length]
+        .iter()
+        .map(|line| {
+            let line = &line[1..];
+            line.strip_prefix(' ').unwrap_or(line)
+        })
+        .collect();
+    return Some((quoted_lines.join("\n"), reply.to_owned()));
+}
@@ -0,0 +1,72 @@
+use reqwest::{Client, StatusCode};
+use sodiumoxide::crypto::secretbox;
+use threema_gateway::errors::{ApiError, CryptoError};
+use threema_gateway::BlobId;
+
+pub const BLOB_ID_NUM_BYTES: usize = 16;
+pub const BLOB_SIZE_NUM_BYTES: usize = 4;
+pub const BLOB_KEY_NUM_BYTES: usize = 32;
+pub const NONCE_NUM_BYTES: usize = 24;
+
+// Threema uses fixed nonces for symmetrically encrypted blobs, because every blob gets a fresh key
+const FILE_NONCE: [u8; NONCE_NUM_BYTES] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1,
+];
+const THUMBNAIL_NONCE: [u8; NONCE_NUM_BYTES] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2,
+];
+
+pub async fn download_blob(
+    client: &Client,
+    endpoint: &str,
+    from: &str,
+    secret: &str,
+    blob_id: &BlobId,
+) -> Result<Vec<u8>, ApiError> {
+    let url = format!(
+        "{}/blobs/{}?from={}&secret={}",
+        endpoint, blob_id, from, secret
+    );
+    let res = client.get(&url).send().await?;
+    match res.status() {
+        StatusCode::OK => {}
+        StatusCode::UNAUTHORIZED => return Err(ApiError::BadCredentials),
+        StatusCode::PAYMENT_REQUIRED => return Err(ApiError::NoCredits),
+        StatusCode::NOT_FOUND => return Err(ApiError::BadBlobId),
+        StatusCode::INTERNAL_SERVER_ERROR => return Err(ApiError::ServerError),
+        e => return Err(ApiError::Other(format!("Bad response status code: {}", e))),
+    }
+    return Ok(res.bytes().await?.to_vec());
+}
+
+/// Decrypts the blob of a group image message, which is encrypted with a symmetric key
+pub fn decrypt_group_image_blob(blob: &[u8], key: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    return decrypt_symmetric_blob(blob, key, FILE_NONCE);
+}
+
+/// Decrypts the file blob of a file message
+pub fn decrypt_file_blob(blob: &[u8], key: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    return decrypt_symmetric_blob(blob, key, FILE_NONCE);
+}
+
+/// Decrypts the thumbnail blob of a file message, which shares the key with the file blob
+pub fn decrypt_thumbnail_blob(blob: &[u8], key: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    return decrypt_symmetric_blob(blob, key, THUMBNAIL_NONCE);
+}
+
+fn decrypt_symmetric_blob(
+    blob: &[u8],
+    key: &[u8],
+    nonce: [u8; NONCE_NUM_BYTES],
+) -> Result<Vec<u8>, CryptoError> {
+    let key = secretbox::Key::from_slice(key)
+        .ok_or_else(|| CryptoError::BadKey("Invalid blob key length".to_owned()))?;
+    return secretbox::open(blob, &secretbox::Nonce(nonce), &key)
+        .map_err(|_| CryptoError::DecryptionFailed);
+}
+
+pub fn parse_blob_id(data: &[u8]) -> BlobId {
+    let mut blob_id = [0; BLOB_ID_NUM_BYTES];
+    blob_id.copy_from_slice(&data[..BLOB_ID_NUM_BYTES]);
+    return BlobId::new(blob_id);
+}
@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+
+use log::debug;
+
+use crate::errors::StoreError;
+use crate::threema::types::MessageGroup;
+use crate::threema::GROUP_ID_NUM_BYTES;
+
+const GROUPS_TREE_NAME: &str = "threema_groups";
+const NICKNAMES_TREE_NAME: &str = "threema_nicknames";
+
+/// In-memory cache of known Threema groups and the nicknames of their members, which is written
+/// through to the on-disk store, so that group members are still known after a restart.
+/// Group ids are only unique per creator, so groups are stored by creator and group id.
+pub struct GroupCache {
+    groups: HashMap<(String, Vec<u8>), MessageGroup>,
+    nicknames: HashMap<String, String>,
+    tree: sled::Tree,
+    nicknames_tree: sled::Tree,
+}
+
+impl GroupCache {
+    pub fn open(db: &sled::Db) -> Result<GroupCache, StoreError> {
+        let tree = db
+            .open_tree(GROUPS_TREE_NAME)
+            .map_err(|e| StoreError::DbError(e))?;
+
+        let mut groups = HashMap::new();
+        for entry in tree.iter() {
+            let (key, serialized) = entry.map_err(|e| StoreError::DbError(e))?;
+            let group: MessageGroup = serde_json::from_slice(&serialized)
+                .map_err(|e| StoreError::SerializationError(e))?;
+            let group_id = key[key.len().saturating_sub(GROUP_ID_NUM_BYTES)..].to_vec();
+            // Older versions stored groups by group id only
+            if key.len() == GROUP_ID_NUM_BYTES {
+                tree.insert(store_key(&group.group_creator, &group_id), serialized)
+                    .map_err(|e| StoreError::DbError(e))?;
+                tree.remove(&key).map_err(|e| StoreError::DbError(e))?;
+            }
+            groups.insert((group.group_creator.clone(), group_id), group);
+        }
+        debug!("Threema: Loaded {} groups from store", groups.len());
+
+        let nicknames_tree = db
+            .open_tree(NICKNAMES_TREE_NAME)
+            .map_err(|e| StoreError::DbError(e))?;
+        let mut nicknames = HashMap::new();
+        for entry in nicknames_tree.iter() {
+            let (threema_id, nickname) = entry.map_err(|e| StoreError::DbError(e))?;
+            nicknames.insert(
+                String::from_utf8_lossy(&threema_id).into_owned(),
+                String::from_utf8_lossy(&nickname).into_owned(),
+            );
+        }
+
+        return Ok(GroupCache {
+            groups,
+            nicknames,
+            tree,
+            nicknames_tree,
+        });
+    }
+
+    pub fn get(&self, group_creator: &str, group_id: &[u8]) -> Option<&MessageGroup> {
+        return self
+            .groups
+            .get(&(group_creator.to_owned(), group_id.to_vec()));
+    }
+
+    /// Returns the creator of the group with the given id, if exactly one such group is known
+    pub fn find_creator(&self, group_id: &[u8]) -> Option<&str> {
+        let mut creators = self
+            .groups
+            .keys()
+            .filter(|(_, id)| id == group_id)
+            .map(|(group_creator, _)| group_creator.as_str());
+        return match (creators.next(), creators.next()) {
+            (Some(group_creator), None) => Some(group_creator),
+            _ => None,
+        };
+    }
+
+    /// Stores a group under its creator and the given group id
+    pub fn insert(&mut self, group_id: &[u8], group: MessageGroup) -> Result<(), StoreError> {
+        let serialized =
+            serde_json::to_vec(&group).map_err(|e| StoreError::SerializationError(e))?;
+        self.tree
+            .insert(store_key(&group.group_creator, group_id), serialized)
+            .map_err(|e| StoreError::DbError(e))?;
+        self.groups
+            .insert((group.group_creator.clone(), group_id.to_vec()), group);
+        return Ok(());
+    }
+
+    pub fn remove(&mut self, group_creator: &str, group_id: &[u8]) -> Result<(), StoreError> {
+        self.groups
+            .remove(&(group_creator.to_owned(), group_id.to_vec()));
+        self.tree
+            .remove(store_key(group_creator, group_id))
+            .map_err(|e| StoreError::DbError(e))?;
+        return Ok(());
+    }
+
+    pub fn get_nickname(&self, threema_id: &str) -> Option<&str> {
+        return self
+            .nicknames
+            .get(threema_id)
+            .map(|nickname| nickname.as_str());
+    }
+
+    pub fn set_nickname(&mut self, threema_id: &str, nickname: &str) -> Result<(), StoreError> {
+        if self.get_nickname(threema_id) == Some(nickname) {
+            return Ok(());
+        }
+        self.nicknames
+            .insert(threema_id.to_owned(), nickname.to_owned());
+        self.nicknames_tree
+            .insert(threema_id, nickname)
+            .map_err(|e| StoreError::DbError(e))?;
+        return Ok(());
+    }
+}
+
+fn store_key(group_creator: &str, group_id: &[u8]) -> Vec<u8> {
+    let mut key = group_creator.as_bytes().to_vec();
+    key.extend(group_id);
+    return key;
+}
@@ -1,64 +1,393 @@
 use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use threema_gateway::{ApiBuilder, E2eApi, IncomingMessage, PublicKey};
+use data_encoding::HEXLOWER_PERMISSIVE;
+use futures::stream::{self, StreamExt};
+use mime::Mime;
+use rand::Rng;
+use threema_gateway::{
+    encrypt_file_data, ApiBuilder, BlobId, E2eApi, EncryptedMessage, IncomingMessage, PublicKey,
+    RecipientKey, RenderingType,
+};
 use tokio::sync::Mutex;
 
-use crate::errors::{ProcessIncomingMessageError, SendGroupMessageError};
-use log::{debug, info};
+use crate::errors::{ProcessIncomingMessageError, SendGroupMessageError, StoreError};
+use crate::formatting::sanitize_nickname;
+use log::{debug, error, info, warn};
 use threema_gateway::errors::{ApiBuilderError, ApiError};
 
+use crate::threema::blob::{
+    decrypt_file_blob, decrypt_group_image_blob, decrypt_thumbnail_blob, download_blob,
+    parse_blob_id, BLOB_ID_NUM_BYTES, BLOB_KEY_NUM_BYTES, BLOB_SIZE_NUM_BYTES,
+};
 use crate::threema::serialization::encrypt_group_sync_req_msg;
 use crate::threema::types::{
-    GroupCreateMessage, GroupRenameMessage, GroupTextMessage, MessageBase, MessageType, TextMessage,
+    DeliveryReceiptMessage, DeliveryReceiptStatus, FileMessagePayload, GroupCreateMessage,
+    GroupFileMessage, GroupImageMessage, GroupLeaveMessage, GroupRenameMessage,
+    GroupRequestSyncMessage, GroupSendReport, GroupTextMessage, MessageBase, MessageType,
+    OutgoingGroupMessage, TextMessage, ThreemaFile, ThreemaThumbnail,
 };
-use crate::util::retry_request;
 
-use self::serialization::encrypt_group_text_msg;
+use self::group_cache::GroupCache;
+use self::pending_messages::PendingMessageQueue;
+use self::pubkey_cache::PublicKeyCache;
+use self::serialization::{
+    encrypt_delivery_receipt_msg, encrypt_group_create_msg, encrypt_group_delete_photo_msg,
+    encrypt_group_file_msg, encrypt_group_rename_msg, encrypt_group_text_msg,
+};
 use self::types::{Message, MessageGroup};
 
+pub mod blob;
+pub mod group_cache;
+pub mod pending_messages;
+pub mod pubkey_cache;
 pub mod serialization;
 pub mod types;
 pub mod util;
 
+/// Threema id of the requester and id of the group
+type SyncRequestKey = (String, Vec<u8>);
+
 #[derive(Clone)]
 pub struct ThreemaClient {
-    api: Arc<Mutex<E2eApi>>,
-    groups: Arc<Mutex<HashMap<Vec<u8>, MessageGroup>>>,
+    api: Arc<E2eApi>,
+    groups: Arc<Mutex<GroupCache>>,
+    pubkeys: Arc<Mutex<PublicKeyCache>>,
+    pending_messages: Arc<Mutex<PendingMessageQueue>>,
+    /// Time of the last answered sync request per requester and group
+    sync_answers: Arc<Mutex<HashMap<SyncRequestKey, Instant>>>,
+    own_id: String,
+    secret: String,
+    endpoint: String,
+    http_client: reqwest::Client,
+    send_concurrency: usize,
 }
 
 pub const GROUP_ID_NUM_BYTES: usize = 8;
 pub const GROUP_CREATOR_NUM_BYTES: usize = 8;
 pub const MESSAGE_TYPE_NUM_BYTES: usize = 1;
 pub const THREEMA_ID_LENGTH: usize = 8;
+pub const MESSAGE_ID_NUM_BYTES: usize = 8;
+/// Number of group members, which a message is sent to at the same time
+pub const DEFAULT_SEND_CONCURRENCY: usize = 8;
+/// Minimum time between two answers to sync requests of the same requester for the same group
+pub const GROUP_SYNC_ANSWER_INTERVAL: Duration = Duration::from_secs(60 * 60);
 
 impl ThreemaClient {
     pub fn new(
         own_id: &str,
         secret: &str,
         private_key: &str,
+        groups: GroupCache,
+        pubkeys: PublicKeyCache,
+        pending_messages: PendingMessageQueue,
     ) -> Result<ThreemaClient, ApiBuilderError> {
-        let api = ApiBuilder::new(own_id, secret)
-            .with_private_key_str(private_key.as_ref())
-            .and_then(|builder| builder.into_e2e())?;
+        let builder = ApiBuilder::new(own_id, secret).with_private_key_str(private_key)?;
+        return ThreemaClient::from_builder(builder, groups, pubkeys, pending_messages);
+    }
+
+    /// Creates a client, which talks to another Gateway API endpoint (e.g. a mock server)
+    pub fn with_custom_endpoint(
+        own_id: &str,
+        secret: &str,
+        private_key: &str,
+        endpoint: &str,
+        groups: GroupCache,
+        pubkeys: PublicKeyCache,
+        pending_messages: PendingMessageQueue,
+    ) -> Result<ThreemaClient, ApiBuilderError> {
+        let builder = ApiBuilder::new(own_id, secret)
+            .with_custom_endpoint(endpoint.to_owned())
+            .with_private_key_str(private_key)?;
+        return ThreemaClient::from_builder(builder, groups, pubkeys, pending_messages);
+    }
+
+    fn from_builder(
+        builder: ApiBuilder,
+        groups: GroupCache,
+        pubkeys: PublicKeyCache,
+        pending_messages: PendingMessageQueue,
+    ) -> Result<ThreemaClient, ApiBuilderError> {
+        let own_id = builder.id.clone();
+        let secret = builder.secret.clone();
+        let endpoint = builder.endpoint.to_string();
+        let api = builder.into_e2e()?;
         return Ok(ThreemaClient {
-            api: Arc::new(Mutex::new(api)),
-            groups: Arc::new(Mutex::new(HashMap::new())),
+            api: Arc::new(api),
+            groups: Arc::new(Mutex::new(groups)),
+            pubkeys: Arc::new(Mutex::new(pubkeys)),
+            pending_messages: Arc::new(Mutex::new(pending_messages)),
+            sync_answers: Arc::new(Mutex::new(HashMap::new())),
+            own_id,
+            secret,
+            endpoint,
+            http_client: reqwest::Client::new(),
+            send_concurrency: DEFAULT_SEND_CONCURRENCY,
         });
     }
 
+    pub fn set_send_concurrency(&mut self, send_concurrency: usize) {
+        self.send_concurrency = send_concurrency.max(1);
+    }
+
+    pub async fn download_blob(&self, blob_id: &BlobId) -> Result<Vec<u8>, ApiError> {
+        debug!("Threema: Downloading blob {}", blob_id);
+        download_blob(
+            &self.http_client,
+            &self.endpoint,
+            &self.own_id,
+            &self.secret,
+            blob_id,
+        )
+        .await
+    }
+
+    pub fn own_id(&self) -> &str {
+        return &self.own_id;
+    }
+
+    /// Stores a new group with a random id, whose creator is the gateway ID. The members have to
+    /// be told with [`ThreemaClient::send_group_setup`].
+    pub async fn create_group(
+        &self,
+        name: &str,
+        members: &[String],
+    ) -> Result<Vec<u8>, StoreError> {
+        let mut groups = self.groups.lock().await;
+        let group_id = loop {
+            let group_id: [u8; GROUP_ID_NUM_BYTES] = rand::thread_rng().gen();
+            if groups.get(&self.own_id, &group_id).is_none() {
+                break group_id.to_vec();
+            }
+        };
+        let mut group_members: Vec<String> = Vec::new();
+        for member in members {
+            if *member != self.own_id && !group_members.contains(member) {
+                group_members.push(member.clone());
+            }
+        }
+        groups.insert(
+            &group_id,
+            MessageGroup {
+                members: group_members,
+                group_creator: self.own_id.clone(),
+                name: name.to_owned(),
+            },
+        )?;
+        info!("Threema: Created group {:?} \"{}\"", group_id, name);
+        return Ok(group_id);
+    }
+
+    /// Sends the members and the name of a group, which we created, to all of its members
+    pub async fn send_group_setup(
+        &self,
+        group_id: &[u8],
+    ) -> Result<GroupSendReport, SendGroupMessageError> {
+        let group = self
+            .get_group(&self.own_id, group_id)
+            .await
+            .ok_or(SendGroupMessageError::GroupNotInCache)?;
+        let receivers: Vec<&str> = group.members.iter().map(|str| str.as_str()).collect();
+        let mut create_report = match self
+            .send_to_group_members(&receivers, |public_key, api| {
+                Ok(encrypt_group_create_msg(
+                    group_id,
+                    &group.members,
+                    public_key,
+                    api,
+                ))
+            })
+            .await
+        {
+            Ok(report) => report,
+            Err(SendGroupMessageError::PartialDelivery(report)) => report,
+            Err(e) => return Err(e),
+        };
+
+        // The name is only sent to the members, who know about the group
+        let receivers: Vec<&str> = create_report
+            .sent
+            .iter()
+            .map(|(receiver, _)| receiver.as_str())
+            .collect();
+        let mut report = match self
+            .send_to_group_members(&receivers, |public_key, api| {
+                Ok(encrypt_group_rename_msg(
+                    group_id,
+                    &group.name,
+                    public_key,
+                    api,
+                ))
+            })
+            .await
+        {
+            Ok(report) => report,
+            Err(SendGroupMessageError::PartialDelivery(report)) => report,
+            Err(e) => return Err(e),
+        };
+        report.failed.append(&mut create_report.failed);
+        if !report.is_complete() {
+            return Err(SendGroupMessageError::PartialDelivery(report));
+        }
+        return Ok(report);
+    }
+
+    pub async fn get_group(&self, group_creator: &str, group_id: &[u8]) -> Option<MessageGroup> {
+        return self
+            .groups
+            .lock()
+            .await
+            .get(group_creator, group_id)
+            .cloned();
+    }
+
+    /// Returns the creator of a known group, for callers which only know the group id
+    pub async fn find_group_creator(&self, group_id: &[u8]) -> Option<String> {
+        return self
+            .groups
+            .lock()
+            .await
+            .find_creator(group_id)
+            .map(|group_creator| group_creator.to_owned());
+    }
+
+    pub async fn get_nickname(&self, threema_id: &str) -> Option<String> {
+        return self
+            .groups
+            .lock()
+            .await
+            .get_nickname(threema_id)
+            .map(|nickname| nickname.to_owned());
+    }
+
+    pub async fn send_outgoing_group_msg_by_group_id(
+        &self,
+        message: &OutgoingGroupMessage,
+        group_creator: &str,
+        group_id: &[u8],
+    ) -> Result<GroupSendReport, SendGroupMessageError> {
+        return match message {
+            OutgoingGroupMessage::Text(text) => {
+                self.send_group_msg_by_group_id(text, group_creator, group_id)
+                    .await
+            }
+            OutgoingGroupMessage::File(file) => {
+                self.send_group_file_msg_by_group_id(file, group_creator, group_id)
+                    .await
+            }
+        };
+    }
+
+    /// Sends a message to a group. If the group members are not known yet, the message is queued
+    /// and the group creator is asked for a group sync, see [`ThreemaClient::send_pending_group_msgs`].
+    /// Returns `None`, if the message has been queued. Its delivery job `job_id` has to be kept
+    /// until then, because queued messages are lost on a restart. Without a group creator, only
+    /// groups with a known creator can be sent to.
+    pub async fn send_or_queue_group_msg(
+        &self,
+        message: OutgoingGroupMessage,
+        group_id: &[u8],
+        group_creator: Option<&str>,
+        job_id: u64,
+    ) -> Result<Option<GroupSendReport>, SendGroupMessageError> {
+        let group_creator = match group_creator {
+            Some(group_creator) => group_creator.to_owned(),
+            None => self
+                .find_group_creator(group_id)
+                .await
+                .ok_or(SendGroupMessageError::GroupNotInCache)?,
+        };
+        match self
+            .send_outgoing_group_msg_by_group_id(&message, &group_creator, group_id)
+            .await
+        {
+            Err(SendGroupMessageError::GroupNotInCache) => {
+                let first_pending = self.pending_messages.lock().await.push(
+                    &group_creator,
+                    group_id,
+                    job_id,
+                    message,
+                )?;
+                info!("Threema: Group members unknown, queued message until group sync");
+                // Only ask once, the creator answers with the whole group anyway
+                if first_pending {
+                    if let Err(e) = self.send_group_sync_req_msg(group_id, &group_creator).await {
+                        // Nobody would send the message, and later messages would not ask again
+                        self.pending_messages
+                            .lock()
+                            .await
+                            .take(&group_creator, group_id);
+                        return Err(SendGroupMessageError::ApiError(e));
+                    }
+                }
+                return Ok(None);
+            }
+            result => return result.map(|report| Some(report)),
+        }
+    }
+
+    /// Sends all queued messages of a group in order, after its members became known. Returns the
+    /// delivery jobs of the queued messages, which can be completed now.
+    pub async fn send_pending_group_msgs(&self, group_creator: &str, group_id: &[u8]) -> Vec<u64> {
+        let pending_messages = self
+            .pending_messages
+            .lock()
+            .await
+            .take(group_creator, group_id);
+        let job_ids = pending_messages.iter().map(|(job_id, _)| *job_id).collect();
+        if pending_messages.is_empty() {
+            return job_ids;
+        }
+        if self.get_group(group_creator, group_id).await.is_none() {
+            warn!(
+                "Threema: Dropping {} queued messages, because we are not a member of the group",
+                pending_messages.len()
+            );
+            return job_ids;
+        }
+
+        debug!(
+            "Threema: Sending {} queued group messages",
+            pending_messages.len()
+        );
+        for (_, message) in pending_messages {
+            if let Err(e) = self
+                .send_outgoing_group_msg_by_group_id(&message, group_creator, group_id)
+                .await
+            {
+                error!("Threema: Could not send queued group message: {}", e);
+            }
+        }
+        return job_ids;
+    }
+
+    /// Returns the delivery jobs of the queued messages, which expired without a group sync
+    pub async fn take_expired_pending_jobs(&self) -> Vec<u64> {
+        let job_ids = self.pending_messages.lock().await.take_expired();
+        if !job_ids.is_empty() {
+            warn!(
+                "Threema: Dropping {} queued messages, because the group creator didn't answer",
+                job_ids.len()
+            );
+        }
+        return job_ids;
+    }
+
     pub async fn send_group_msg_by_group_id(
         &self,
         text: &str,
+        group_creator: &str,
         group_id: &[u8],
-    ) -> Result<(), SendGroupMessageError> {
-        let groups = self.groups.lock().await;
-        if let Some(group) = groups.get(group_id) {
+    ) -> Result<GroupSendReport, SendGroupMessageError> {
+        // Don't hold the lock while sending, other messages need the group cache as well
+        let group = self.get_group(group_creator, group_id).await;
+        if let Some(group) = group {
             let receiver: Vec<&str> = group.members.iter().map(|str| str.as_str()).collect();
             return self
                 .send_group_msg(text, &group.group_creator, group_id, receiver.as_slice())
-                .await
-                .map_err(|e| SendGroupMessageError::ApiError(e));
+                .await;
         } else {
             return Err(SendGroupMessageError::GroupNotInCache);
         }
@@ -70,24 +399,203 @@ impl ThreemaClient {
         group_creator: &str,
         group_id: &[u8],
         receivers: &[&str],
-    ) -> Result<(), ApiError> {
-        let api = self.api.lock().await;
-        for user_id in receivers {
-            debug!("Threema: Sending message to: {}", user_id);
-            let public_key = self.lookup_pubkey_with_retry(user_id, &api).await?; //TODO cache
-
-            let encrypted_msg =
-                encrypt_group_text_msg(text, group_creator, group_id, &public_key.into(), &api);
-
-            retry_request(
-                || async { api.send(&user_id, &encrypted_msg, false).await },
-                20 * 1000,
-                6,
-            )
-            .await?;
-            debug!("Threema: Message sent successfully");
+    ) -> Result<GroupSendReport, SendGroupMessageError> {
+        return self
+            .send_to_group_members(receivers, |public_key, api| {
+                Ok(encrypt_group_text_msg(
+                    text,
+                    group_creator,
+                    group_id,
+                    public_key,
+                    api,
+                ))
+            })
+            .await;
+    }
+
+    pub async fn send_group_file_msg_by_group_id(
+        &self,
+        file: &ThreemaFile,
+        group_creator: &str,
+        group_id: &[u8],
+    ) -> Result<GroupSendReport, SendGroupMessageError> {
+        let group = self.get_group(group_creator, group_id).await;
+        if let Some(group) = group {
+            let receiver: Vec<&str> = group.members.iter().map(|str| str.as_str()).collect();
+            return self
+                .send_group_file_msg(file, &group.group_creator, group_id, receiver.as_slice())
+                .await;
+        } else {
+            return Err(SendGroupMessageError::GroupNotInCache);
         }
-        return Ok(());
+    }
+
+    pub async fn send_group_file_msg(
+        &self,
+        file: &ThreemaFile,
+        group_creator: &str,
+        group_id: &[u8],
+        receivers: &[&str],
+    ) -> Result<GroupSendReport, SendGroupMessageError> {
+        let file_msg = self.upload_file(file).await?;
+        return self
+            .send_to_group_members(receivers, |public_key, api| {
+                encrypt_group_file_msg(&file_msg, group_creator, group_id, public_key, api)
+                    .map_err(|e| SendGroupMessageError::SerializationError(e))
+            })
+            .await;
+    }
+
+    /// Encrypts a file (and its thumbnail) with a fresh key and uploads it to the blob server
+    async fn upload_file(
+        &self,
+        file: &ThreemaFile,
+    ) -> Result<threema_gateway::FileMessage, SendGroupMessageError> {
+        let (encrypted_file, encrypted_thumbnail, key) = encrypt_file_data(
+            &file.data,
+            file.thumbnail
+                .as_ref()
+                .map(|thumbnail| thumbnail.data.as_slice()),
+        );
+
+        let api = &self.api;
+        // Persist blobs, because every group member downloads them
+        let blob_id = api
+            .blob_upload_raw(&encrypted_file, true)
+            .await
+            .map_err(|e| SendGroupMessageError::ApiError(e))?;
+
+        let thumbnail = match (encrypted_thumbnail, &file.thumbnail) {
+            (Some(encrypted_thumbnail), Some(thumbnail)) => {
+                let thumbnail_blob_id = api
+                    .blob_upload_raw(&encrypted_thumbnail, true)
+                    .await
+                    .map_err(|e| SendGroupMessageError::ApiError(e))?;
+                let thumbnail_type = thumbnail
+                    .media_type
+                    .parse::<Mime>()
+                    .unwrap_or(mime::IMAGE_JPEG);
+                Some((thumbnail_blob_id, thumbnail_type))
+            }
+            _ => None,
+        };
+        debug!("Threema: File uploaded successfully");
+
+        let media_type = file
+            .media_type
+            .parse::<Mime>()
+            .unwrap_or(mime::APPLICATION_OCTET_STREAM);
+        let rendering_type = match media_type.type_() {
+            mime::IMAGE | mime::VIDEO | mime::AUDIO => RenderingType::Media,
+            _ => RenderingType::File,
+        };
+
+        return threema_gateway::FileMessage::builder(
+            blob_id,
+            key,
+            media_type,
+            file.data.len() as u32,
+        )
+        .thumbnail_opt(thumbnail)
+        .file_name_opt(file.file_name.as_ref())
+        .description_opt(file.caption.as_ref())
+        .rendering_type(rendering_type)
+        .build()
+        .map_err(|e| SendGroupMessageError::InvalidFile(e));
+    }
+
+    /// Encrypts and sends the message to the receivers concurrently (up to the configured limit).
+    /// If it could not be sent to some of them, the error reports each failed receiver.
+    async fn send_to_group_members<F>(
+        &self,
+        receivers: &[&str],
+        encrypt: F,
+    ) -> Result<GroupSendReport, SendGroupMessageError>
+    where
+        F: Fn(&RecipientKey, &E2eApi) -> Result<EncryptedMessage, SendGroupMessageError>,
+    {
+        // Create the futures upfront, a lazily mapped stream of borrowing futures isn't Send
+        let encrypt = &encrypt;
+        let sends: Vec<_> = receivers
+            .iter()
+            .map(|user_id| async move {
+                let result = self.send_to_group_member(user_id, encrypt).await;
+                (user_id.to_string(), result)
+            })
+            .collect();
+        let results: Vec<(String, Result<String, SendGroupMessageError>)> = stream::iter(sends)
+            .buffer_unordered(self.send_concurrency)
+            .collect()
+            .await;
+
+        let mut api_results = Vec::with_capacity(results.len());
+        for (receiver, result) in results {
+            match result {
+                Ok(message_id) => api_results.push((receiver, Ok(message_id))),
+                Err(SendGroupMessageError::ApiError(e)) => api_results.push((receiver, Err(e))),
+                // Encrypting fails for every member alike, so nobody got the message
+                Err(e) => return Err(e),
+            }
+        }
+        let report = GroupSendReport::from_results(api_results);
+        if !report.is_complete() {
+            return Err(SendGroupMessageError::PartialDelivery(report));
+        }
+        debug!(
+            "Threema: Message sent successfully to {} group members",
+            report.sent.len()
+        );
+        return Ok(report);
+    }
+
+    /// Returns the id of the sent message
+    async fn send_to_group_member<F>(
+        &self,
+        user_id: &str,
+        encrypt: &F,
+    ) -> Result<String, SendGroupMessageError>
+    where
+        F: Fn(&RecipientKey, &E2eApi) -> Result<EncryptedMessage, SendGroupMessageError>,
+    {
+        debug!("Threema: Sending message to: {}", user_id);
+        let public_key = self
+            .lookup_pubkey(user_id)
+            .await
+            .map_err(|e| SendGroupMessageError::ApiError(e))?;
+        let encrypted_msg = encrypt(&public_key.into(), &self.api)?;
+
+        // Ask for delivery receipts, so that they can be shown in Matrix
+        let message_id = self
+            .api
+            .send(user_id, &encrypted_msg, true)
+            .await
+            .map_err(|e| SendGroupMessageError::ApiError(e))?;
+        debug!("Threema: Message sent successfully to: {}", user_id);
+        return Ok(message_id);
+    }
+
+    /// Returns the public key from the cache or looks it up at the gateway
+    async fn lookup_pubkey(&self, user_id: &str) -> Result<PublicKey, ApiError> {
+        if let Some(public_key) = self.pubkeys.lock().await.get(user_id) {
+            return Ok(public_key);
+        }
+        return self.refresh_pubkey(user_id).await;
+    }
+
+    /// Looks up the public key at the gateway and updates the cache. A changed key is reported,
+    /// because the identity may have been recreated or somebody may impersonate it.
+    async fn refresh_pubkey(&self, user_id: &str) -> Result<PublicKey, ApiError> {
+        debug!("Threema: Looking up public key of {}", user_id);
+        let public_key = self.lookup_pubkey_with_retry(user_id, &self.api).await?;
+        match self.pubkeys.lock().await.insert(user_id, &public_key) {
+            Ok(Some(_)) => warn!(
+                "Threema: Public key of {} has changed! The identity may have been recreated or somebody may impersonate it.",
+                user_id
+            ),
+            Ok(None) => {}
+            Err(e) => error!("Threema: Could not cache public key of {}: {}", user_id, e),
+        }
+        return Ok(public_key);
     }
 
     async fn lookup_pubkey_with_retry(
@@ -95,7 +603,7 @@ impl ThreemaClient {
         user_id: &str,
         api: &E2eApi,
     ) -> Result<PublicKey, ApiError> {
-        retry_request(|| async { api.lookup_pubkey(user_id).await }, 20 * 1000, 6).await
+        api.lookup_pubkey(user_id).await
     }
 
     pub async fn send_group_sync_req_msg(
@@ -103,49 +611,246 @@ impl ThreemaClient {
         group_id: &[u8],
         receiver: &str,
     ) -> Result<(), ApiError> {
-        let api = self.api.lock().await;
-        let public_key = self.lookup_pubkey_with_retry(receiver, &api).await?;
-        let encrypted_message = encrypt_group_sync_req_msg(group_id, &public_key.into(), &api);
-
-        retry_request(
-            || async { api.send(receiver, &encrypted_message, false).await },
-            20 * 1000,
-            6,
-        )
-        .await?;
+        let api = &self.api;
+        let public_key = self.lookup_pubkey(receiver).await?;
+        let encrypted_message = encrypt_group_sync_req_msg(group_id, &public_key.into(), api);
+
+        api.send(receiver, &encrypted_message, false).await?;
         debug!("Threema: Group sync message sent successfully");
         return Ok(());
     }
 
+    /// Returns whether a group sync request should be answered. Only groups, which we created,
+    /// are synced, and each requester gets at most one answer per group within
+    /// [`GROUP_SYNC_ANSWER_INTERVAL`].
+    pub async fn accept_group_sync_request(&self, requester: &str, group_id: &[u8]) -> bool {
+        // Only the creator of a group knows its members
+        if self.get_group(&self.own_id, group_id).await.is_none() {
+            debug!(
+                "Threema: Ignoring sync request of {} for group {:?}, which we did not create",
+                requester, group_id
+            );
+            return false;
+        }
+
+        let mut sync_answers = self.sync_answers.lock().await;
+        sync_answers.retain(|_, answered_at| answered_at.elapsed() < GROUP_SYNC_ANSWER_INTERVAL);
+        let key = (requester.to_owned(), group_id.to_vec());
+        if sync_answers.contains_key(&key) {
+            debug!(
+                "Threema: Ignoring repeated sync request of {} for group {:?}",
+                requester, group_id
+            );
+            return false;
+        }
+        sync_answers.insert(key, Instant::now());
+        return true;
+    }
+
+    /// Answers a group sync request with the members, the name and the photo of a group, which we
+    /// created. A requester, who is not a member, only gets an empty member list, so that they
+    /// leave.
+    pub async fn send_group_sync(&self, group_id: &[u8], receiver: &str) -> Result<(), ApiError> {
+        let group = match self.get_group(&self.own_id, group_id).await {
+            Some(group) => group,
+            None => return Ok(()),
+        };
+        let api = &self.api;
+        let public_key: RecipientKey = self.lookup_pubkey(receiver).await?.into();
+
+        let is_member = group.members.iter().any(|member| member == receiver);
+        let mut encrypted_messages = Vec::new();
+        if is_member {
+            encrypted_messages.push(encrypt_group_create_msg(
+                group_id,
+                &group.members,
+                &public_key,
+                api,
+            ));
+            encrypted_messages.push(encrypt_group_rename_msg(
+                group_id,
+                &group.name,
+                &public_key,
+                api,
+            ));
+            // The bridge does not keep group photos
+            encrypted_messages.push(encrypt_group_delete_photo_msg(group_id, &public_key, api));
+        } else {
+            encrypted_messages.push(encrypt_group_create_msg(group_id, &[], &public_key, api));
+        }
+
+        for encrypted_message in &encrypted_messages {
+            api.send(receiver, encrypted_message, false).await?;
+        }
+        debug!("Threema: Group sync sent to {}", receiver);
+        return Ok(());
+    }
+
+    /// Tells the sender, that their messages have been received or read. Message ids are hex
+    /// encoded.
+    pub async fn send_delivery_receipt(
+        &self,
+        receiver: &str,
+        status: DeliveryReceiptStatus,
+        message_ids: &[String],
+    ) -> Result<(), ApiError> {
+        let raw_message_ids = message_ids
+            .iter()
+            .map(|message_id| {
+                HEXLOWER_PERMISSIVE
+                    .decode(message_id.as_bytes())
+                    .ok()
+                    .filter(|message_id| message_id.len() == MESSAGE_ID_NUM_BYTES)
+                    .ok_or_else(|| {
+                        ApiError::ParseError(format!("Invalid message id {}", message_id))
+                    })
+            })
+            .collect::<Result<Vec<Vec<u8>>, ApiError>>()?;
+        let api = &self.api;
+        let public_key = self.lookup_pubkey(receiver).await?;
+        let encrypted_message =
+            encrypt_delivery_receipt_msg(status, &raw_message_ids, &public_key.into(), api);
+
+        // No receipts for receipts
+        api.send(receiver, &encrypted_message, false).await?;
+        debug!(
+            "Threema: Delivery receipt {:?} sent to {} for {:?}",
+            status, receiver, message_ids
+        );
+        return Ok(());
+    }
+
+    async fn download_file(
+        &self,
+        payload: &[u8],
+    ) -> Result<ThreemaFile, ProcessIncomingMessageError> {
+        let payload: FileMessagePayload = serde_json::from_slice(payload)
+            .map_err(|e| ProcessIncomingMessageError::InvalidFileMessage(e.to_string()))?;
+        debug!("Threema: File message payload: {:?}", payload);
+
+        let key = HEXLOWER_PERMISSIVE
+            .decode(payload.blob_encryption_key.as_bytes())
+            .map_err(|e| ProcessIncomingMessageError::InvalidFileMessage(e.to_string()))?;
+        let blob_id = BlobId::from_str(&payload.blob_id)
+            .map_err(|e| ProcessIncomingMessageError::ApiError(e))?;
+
+        let blob = self
+            .download_blob(&blob_id)
+            .await
+            .map_err(|e| ProcessIncomingMessageError::ApiError(e))?;
+        let data = decrypt_file_blob(&blob, &key)
+            .map_err(|e| ProcessIncomingMessageError::CryptoError(e))?;
+
+        let thumbnail = match payload.thumbnail_blob_id {
+            Some(thumbnail_blob_id) => {
+                let thumbnail_blob_id = BlobId::from_str(&thumbnail_blob_id)
+                    .map_err(|e| ProcessIncomingMessageError::ApiError(e))?;
+                let thumbnail_blob = self
+                    .download_blob(&thumbnail_blob_id)
+                    .await
+                    .map_err(|e| ProcessIncomingMessageError::ApiError(e))?;
+                let thumbnail_data = decrypt_thumbnail_blob(&thumbnail_blob, &key)
+                    .map_err(|e| ProcessIncomingMessageError::CryptoError(e))?;
+                Some(ThreemaThumbnail {
+                    data: thumbnail_data,
+                    media_type: payload
+                        .thumbnail_media_type
+                        .unwrap_or("image/jpeg".to_owned()),
+                })
+            }
+            None => None,
+        };
+
+        return Ok(ThreemaFile {
+            data,
+            media_type: payload.media_type,
+            file_name: payload.file_name,
+            caption: payload.caption,
+            thumbnail,
+        });
+    }
+
+    async fn request_group_sync_if_unknown(
+        &self,
+        group_id: &[u8],
+        group_creator: &str,
+    ) -> Result<(), ProcessIncomingMessageError> {
+        // Don't hold the lock while sending, other messages need the group cache as well
+        if self.get_group(group_creator, group_id).await.is_none() {
+            debug!("Threema: Unknown group, sending sync req");
+            self.send_group_sync_req_msg(group_id, group_creator)
+                .await
+                .map_err(|e| ProcessIncomingMessageError::ApiError(e))?;
+        }
+        return Ok(());
+    }
+
     pub async fn process_incoming_msg(
         &self,
         incoming_message: &IncomingMessage,
     ) -> Result<Message, ProcessIncomingMessageError> {
+        let cached_pubkey = self.pubkeys.lock().await.get(&incoming_message.from);
+        let data = match cached_pubkey {
+            Some(cached_pubkey) => {
+                match self
+                    .api
+                    .decrypt_incoming_message(incoming_message, &cached_pubkey)
+                {
+                    Ok(data) => data,
+                    Err(e) => {
+                        // The sender may have a new key
+                        let pubkey = self
+                            .refresh_pubkey(&incoming_message.from)
+                            .await
+                            .map_err(|e| ProcessIncomingMessageError::ApiError(e))?;
+                        if pubkey == cached_pubkey {
+                            return Err(ProcessIncomingMessageError::CryptoError(e));
+                        }
+                        self.api
+                            .decrypt_incoming_message(incoming_message, &pubkey)
+                            .map_err(|e| ProcessIncomingMessageError::CryptoError(e))?
+                    }
+                }
+            }
+            None => {
+                let pubkey = self
+                    .refresh_pubkey(&incoming_message.from)
+                    .await
+                    .map_err(|e| ProcessIncomingMessageError::ApiError(e))?;
+                self.api
+                    .decrypt_incoming_message(incoming_message, &pubkey)
+                    .map_err(|e| ProcessIncomingMessageError::CryptoError(e))?
+            }
+        };
+        let message_type: u8 = *data
+            .first()
+            .ok_or(ProcessIncomingMessageError::InvalidPayloadLength)?;
+        debug!("Threema: Parsed and validated message from request:\nFrom: {}\nSender nickname: {:?}\nTo: {}\nTimestamp: {}\nMessage type: {:#02x}", incoming_message.from,incoming_message.nickname,incoming_message.to,incoming_message.date, message_type);
 
-        let data;
-        {
-            let api = self.api.lock().await;
-            let pubkey = self
-                .lookup_pubkey_with_retry(&incoming_message.from, &api)
+        // The nickname is chosen by the sender and shown in Matrix
+        let nickname = incoming_message
+            .nickname
+            .as_deref()
+            .and_then(|nickname| sanitize_nickname(nickname));
+        if let Some(nickname) = &nickname {
+            self.groups
+                .lock()
                 .await
-                .map_err(|e| ProcessIncomingMessageError::ApiError(e))?;
-
-            data = api
-                .decrypt_incoming_message(&incoming_message, &pubkey)
-                .map_err(|e| ProcessIncomingMessageError::CryptoError(e))?;
+                .set_nickname(&incoming_message.from, nickname)
+                .map_err(|e| ProcessIncomingMessageError::StoreError(e))?;
         }
-        let message_type: u8 = &data[0] & 0xFF;
-        debug!("Threema: Parsed and validated message from request:\nFrom: {}\nSender nickname: {:?}\nTo: {}\nTimestamp: {}\nMessage type: {:#02x}", incoming_message.from,incoming_message.nickname,incoming_message.to,incoming_message.date, message_type);
 
         let base = MessageBase {
             from_identity: incoming_message.from.clone(),
             to_identity: incoming_message.to.clone(),
-            push_from_name: incoming_message.nickname.clone(),
+            push_from_name: nickname,
             message_id: incoming_message.message_id.clone(),
             date: incoming_message.date as u64,
         };
 
-        match MessageType::from(message_type) {
+        let message_type = MessageType::try_from(message_type)
+            .map_err(|e| ProcessIncomingMessageError::UnknownMessageType(e))?;
+        match message_type {
             MessageType::Text => {
                 let text = String::from_utf8(data[MESSAGE_TYPE_NUM_BYTES..].to_vec())
                     .map_err(|e| ProcessIncomingMessageError::Utf8ConvertError(e))?;
@@ -153,18 +858,9 @@ impl ThreemaClient {
                 return Ok(Message::TextMessage(TextMessage { base, text }));
             }
             MessageType::GroupText => {
-                let group_creator = String::from_utf8(
-                    data[MESSAGE_TYPE_NUM_BYTES..MESSAGE_TYPE_NUM_BYTES + GROUP_CREATOR_NUM_BYTES]
-                        .to_vec(),
-                )
-                .map_err(|e| ProcessIncomingMessageError::Utf8ConvertError(e))?;
-                let group_id = &data[MESSAGE_TYPE_NUM_BYTES + GROUP_CREATOR_NUM_BYTES
-                    ..MESSAGE_TYPE_NUM_BYTES + GROUP_CREATOR_NUM_BYTES + GROUP_ID_NUM_BYTES];
-                let text = String::from_utf8(
-                    data[MESSAGE_TYPE_NUM_BYTES + GROUP_CREATOR_NUM_BYTES + GROUP_ID_NUM_BYTES..]
-                        .to_vec(),
-                )
-                .map_err(|e| ProcessIncomingMessageError::Utf8ConvertError(e))?;
+                let (group_creator, group_id, payload) = parse_group_message_header(&data)?;
+                let text = String::from_utf8(payload.to_vec())
+                    .map_err(|e| ProcessIncomingMessageError::Utf8ConvertError(e))?;
 
                 // Show result
                 debug!(
@@ -172,15 +868,8 @@ impl ThreemaClient {
                     group_creator, group_id, text
                 );
 
-                {
-                    let groups = self.groups.lock().await;
-                    if let None = groups.get(group_id) {
-                        debug!("Threema: Unknown group, sending sync req");
-                        self.send_group_sync_req_msg(group_id, group_creator.as_str())
-                            .await
-                            .map_err(|e| ProcessIncomingMessageError::ApiError(e))?;
-                    }
-                }
+                self.request_group_sync_if_unknown(group_id, group_creator.as_str())
+                    .await?;
 
                 return Ok(Message::GroupTextMessage(GroupTextMessage {
                     base,
@@ -189,25 +878,77 @@ impl ThreemaClient {
                     group_id: group_id.to_vec(),
                 }));
             }
+            MessageType::GroupImage => {
+                let (group_creator, group_id, payload) = parse_group_message_header(&data)?;
+                if payload.len() < BLOB_ID_NUM_BYTES + BLOB_SIZE_NUM_BYTES + BLOB_KEY_NUM_BYTES {
+                    return Err(ProcessIncomingMessageError::InvalidPayloadLength);
+                }
+                let blob_id = parse_blob_id(payload);
+                let key = &payload[BLOB_ID_NUM_BYTES + BLOB_SIZE_NUM_BYTES
+                    ..BLOB_ID_NUM_BYTES + BLOB_SIZE_NUM_BYTES + BLOB_KEY_NUM_BYTES];
+
+                self.request_group_sync_if_unknown(group_id, group_creator.as_str())
+                    .await?;
+
+                let blob = self
+                    .download_blob(&blob_id)
+                    .await
+                    .map_err(|e| ProcessIncomingMessageError::ApiError(e))?;
+                let image = decrypt_group_image_blob(&blob, key)
+                    .map_err(|e| ProcessIncomingMessageError::CryptoError(e))?;
+                debug!(
+                    "Threema: GroupCreator: {}\ngroupId: {:?}\nimage with {} bytes",
+                    group_creator,
+                    group_id,
+                    image.len()
+                );
+
+                return Ok(Message::GroupImageMessage(GroupImageMessage {
+                    base,
+                    group_creator,
+                    group_id: group_id.to_vec(),
+                    image,
+                }));
+            }
+            MessageType::GroupFile => {
+                let (group_creator, group_id, payload) = parse_group_message_header(&data)?;
+
+                self.request_group_sync_if_unknown(group_id, group_creator.as_str())
+                    .await?;
+
+                let file = self.download_file(payload).await?;
+                debug!(
+                    "Threema: GroupCreator: {}\ngroupId: {:?}\nfile of type {} with {} bytes",
+                    group_creator,
+                    group_id,
+                    file.media_type,
+                    file.data.len()
+                );
+
+                return Ok(Message::GroupFileMessage(GroupFileMessage {
+                    base,
+                    group_creator,
+                    group_id: group_id.to_vec(),
+                    file,
+                }));
+            }
             MessageType::GroupCreate => {
-                let group_id =
-                    &data[MESSAGE_TYPE_NUM_BYTES..MESSAGE_TYPE_NUM_BYTES + GROUP_CREATOR_NUM_BYTES];
-                let mut members: HashSet<String> = HashSet::new();
+                let payload = &data[MESSAGE_TYPE_NUM_BYTES..];
+                if payload.len() < GROUP_ID_NUM_BYTES {
+                    return Err(ProcessIncomingMessageError::InvalidPayloadLength);
+                }
+                let group_id = &payload[..GROUP_ID_NUM_BYTES];
 
-                let mut counter = 0;
-                let mut current_member_id = "".to_owned();
-                for char in &data[MESSAGE_TYPE_NUM_BYTES + GROUP_CREATOR_NUM_BYTES..] {
-                    current_member_id = current_member_id
-                        + String::from_utf8(vec![*char])
-                            .map_err(|e| ProcessIncomingMessageError::Utf8ConvertError(e))?
-                            .as_str();
-                    counter = counter + 1;
-                    if counter == THREEMA_ID_LENGTH {
-                        members.insert(current_member_id.clone());
-
-                        current_member_id = "".to_owned();
-                        counter = 0;
-                    }
+                let member_ids = payload[GROUP_ID_NUM_BYTES..].chunks_exact(THREEMA_ID_LENGTH);
+                if !member_ids.remainder().is_empty() {
+                    return Err(ProcessIncomingMessageError::InvalidPayloadLength);
+                }
+                let mut members: HashSet<String> = HashSet::new();
+                for member_id in member_ids {
+                    members.insert(
+                        String::from_utf8(member_id.to_vec())
+                            .map_err(|e| ProcessIncomingMessageError::Utf8ConvertError(e))?,
+                    );
                 }
 
                 let me_in_group = members
@@ -220,32 +961,39 @@ impl ThreemaClient {
                     .filter(|member| *member != &incoming_message.to)
                     .collect();
 
-                if members_without_me.len() != 0 && me_in_group {
+                let mut groups = self.groups.lock().await;
+                check_group_creator(&groups, group_id, &incoming_message.from)?;
+                if !members_without_me.is_empty() && me_in_group {
                     // Make sure to always add sender/group creator (different behavior between Android and iOS)
                     members_without_me.insert(&incoming_message.from);
 
                     {
-                        let mut groups = self.groups.lock().await;
                         let new_members: Vec<String> = members_without_me
                             .iter()
                             .map(|member| (*member).to_owned())
                             .collect();
-                        groups
-                            .entry(group_id.to_vec())
-                            .and_modify(|group| {
-                                group.members = new_members.clone();
-                            })
-                            .or_insert(MessageGroup {
+                        let group = match groups.get(&incoming_message.from, group_id) {
+                            Some(group) => MessageGroup {
+                                members: new_members,
+                                ..group.clone()
+                            },
+                            None => MessageGroup {
                                 members: new_members,
                                 name: "".to_owned(),
                                 group_creator: incoming_message.from.clone(),
-                            });
+                            },
+                        };
+                        groups
+                            .insert(group_id, group)
+                            .map_err(|e| ProcessIncomingMessageError::StoreError(e))?;
                     }
                 } else {
-                    let mut groups = self.groups.lock().await;
                     info!("Threema: Leaving group");
-                    groups.remove(group_id);
+                    groups
+                        .remove(&incoming_message.from, group_id)
+                        .map_err(|e| ProcessIncomingMessageError::StoreError(e))?;
                 }
+                drop(groups);
 
                 return Ok(Message::GroupCreateMessage(GroupCreateMessage {
                     base,
@@ -257,23 +1005,31 @@ impl ThreemaClient {
                 }));
             }
             MessageType::GroupRename => {
-                let group_id =
-                    &data[MESSAGE_TYPE_NUM_BYTES..MESSAGE_TYPE_NUM_BYTES + GROUP_ID_NUM_BYTES];
-                let group_name = String::from_utf8(
-                    data[MESSAGE_TYPE_NUM_BYTES + GROUP_CREATOR_NUM_BYTES..].to_vec(),
-                )
-                .map_err(|e| ProcessIncomingMessageError::Utf8ConvertError(e))?;
+                let payload = &data[MESSAGE_TYPE_NUM_BYTES..];
+                if payload.len() < GROUP_ID_NUM_BYTES {
+                    return Err(ProcessIncomingMessageError::InvalidPayloadLength);
+                }
+                let group_id = &payload[..GROUP_ID_NUM_BYTES];
+                let group_name = String::from_utf8(payload[GROUP_ID_NUM_BYTES..].to_vec())
+                    .map_err(|e| ProcessIncomingMessageError::Utf8ConvertError(e))?;
 
                 {
                     let mut groups = self.groups.lock().await;
-                    groups
-                        .entry(group_id.to_vec())
-                        .and_modify(|group| group.name = group_name.clone())
-                        .or_insert(MessageGroup {
+                    check_group_creator(&groups, group_id, &incoming_message.from)?;
+                    let group = match groups.get(&incoming_message.from, group_id) {
+                        Some(group) => MessageGroup {
+                            name: group_name.clone(),
+                            ..group.clone()
+                        },
+                        None => MessageGroup {
                             members: Vec::new(),
                             name: group_name.clone(),
                             group_creator: incoming_message.from.clone(),
-                        });
+                        },
+                    };
+                    groups
+                        .insert(group_id, group)
+                        .map_err(|e| ProcessIncomingMessageError::StoreError(e))?;
                 }
 
                 return Ok(Message::GroupRenameMessage(GroupRenameMessage {
@@ -282,16 +1038,124 @@ impl ThreemaClient {
                     group_id: group_id.to_vec(),
                 }));
             }
-            // MessageType::GroupRequestSync => {}
-            // MessageType::Image => {}
-            // MessageType::Video => {}
-            // MessageType::File => {}
-            // MessageType::DeliveryReceipt => {}
+            MessageType::GroupLeave => {
+                let (group_creator, group_id, _) = parse_group_message_header(&data)?;
+                debug!(
+                    "Threema: {} left group {:?} of {}",
+                    incoming_message.from, group_id, group_creator
+                );
+
+                let mut member_removed = false;
+                {
+                    let mut groups = self.groups.lock().await;
+                    let group = groups
+                        .get(&group_creator, group_id)
+                        .filter(|group| group.members.contains(&incoming_message.from));
+                    if let Some(group) = group {
+                        // Messages to the group must not reach the member anymore
+                        let group = MessageGroup {
+                            members: group
+                                .members
+                                .iter()
+                                .filter(|member| **member != incoming_message.from)
+                                .cloned()
+                                .collect(),
+                            ..group.clone()
+                        };
+                        groups
+                            .insert(group_id, group)
+                            .map_err(|e| ProcessIncomingMessageError::StoreError(e))?;
+                        member_removed = true;
+                    }
+                }
+
+                return Ok(Message::GroupLeaveMessage(GroupLeaveMessage {
+                    base,
+                    group_creator,
+                    group_id: group_id.to_vec(),
+                    member_removed,
+                }));
+            }
+            MessageType::GroupRequestSync => {
+                let payload = &data[MESSAGE_TYPE_NUM_BYTES..];
+                if payload.len() < GROUP_ID_NUM_BYTES {
+                    return Err(ProcessIncomingMessageError::InvalidPayloadLength);
+                }
+                let group_id = &payload[..GROUP_ID_NUM_BYTES];
+                debug!(
+                    "Threema: {} requested sync of group {:?}",
+                    incoming_message.from, group_id
+                );
+
+                return Ok(Message::GroupRequestSyncMessage(GroupRequestSyncMessage {
+                    base,
+                    group_id: group_id.to_vec(),
+                }));
+            }
+            MessageType::DeliveryReceipt => {
+                let payload = &data[MESSAGE_TYPE_NUM_BYTES..];
+                if payload.len() < 1 + MESSAGE_ID_NUM_BYTES
+                    || (payload.len() - 1) % MESSAGE_ID_NUM_BYTES != 0
+                {
+                    return Err(ProcessIncomingMessageError::InvalidPayloadLength);
+                }
+                let status = DeliveryReceiptStatus::try_from(payload[0])
+                    .map_err(|e| ProcessIncomingMessageError::UnknownDeliveryReceiptStatus(e))?;
+                let message_ids: Vec<String> = payload[1..]
+                    .chunks(MESSAGE_ID_NUM_BYTES)
+                    .map(|message_id| HEXLOWER_PERMISSIVE.encode(message_id))
+                    .collect();
+                debug!(
+                    "Threema: Delivery receipt {:?} for {:?}",
+                    status, message_ids
+                );
+
+                return Ok(Message::DeliveryReceiptMessage(DeliveryReceiptMessage {
+                    base,
+                    status,
+                    message_ids,
+                }));
+            }
             _ => {
-                info!("Unknown message type received");
-                info!("content: {:?}", &data[1..]);
-                Err(ProcessIncomingMessageError::UnknownMessageTypeError)
+                debug!("Threema: content: {:?}", &data[MESSAGE_TYPE_NUM_BYTES..]);
+                return Err(ProcessIncomingMessageError::UnsupportedMessageType(
+                    message_type,
+                ));
             }
         }
     }
 }
+
+/// Group create and rename messages may only be sent by the creator of a group. Otherwise any
+/// member could take over a group, which is known under the same id.
+fn check_group_creator(
+    groups: &GroupCache,
+    group_id: &[u8],
+    sender: &str,
+) -> Result<(), ProcessIncomingMessageError> {
+    match groups.find_creator(group_id) {
+        Some(group_creator) if group_creator != sender => {
+            return Err(ProcessIncomingMessageError::NotGroupCreator(
+                sender.to_owned(),
+            ));
+        }
+        _ => return Ok(()),
+    }
+}
+
+/// Splits a decrypted group message into group creator, group id and the remaining payload
+fn parse_group_message_header(
+    data: &[u8],
+) -> Result<(String, &[u8], &[u8]), ProcessIncomingMessageError> {
+    if data.len() < MESSAGE_TYPE_NUM_BYTES + GROUP_CREATOR_NUM_BYTES + GROUP_ID_NUM_BYTES {
+        return Err(ProcessIncomingMessageError::InvalidPayloadLength);
+    }
+    let group_creator = String::from_utf8(
+        data[MESSAGE_TYPE_NUM_BYTES..MESSAGE_TYPE_NUM_BYTES + GROUP_CREATOR_NUM_BYTES].to_vec(),
+    )
+    .map_err(|e| ProcessIncomingMessageError::Utf8ConvertError(e))?;
+    let group_id = &data[MESSAGE_TYPE_NUM_BYTES + GROUP_CREATOR_NUM_BYTES
+        ..MESSAGE_TYPE_NUM_BYTES + GROUP_CREATOR_NUM_BYTES + GROUP_ID_NUM_BYTES];
+    let payload = &data[MESSAGE_TYPE_NUM_BYTES + GROUP_CREATOR_NUM_BYTES + GROUP_ID_NUM_BYTES..];
+    return Ok((group_creator, group_id, payload));
+}
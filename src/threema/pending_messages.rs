@@ -0,0 +1,101 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::errors::SendGroupMessageError;
+use crate::threema::types::OutgoingGroupMessage;
+
+pub const DEFAULT_PENDING_MESSAGE_TTL_SECS: u64 = 60 * 60;
+pub const DEFAULT_PENDING_MESSAGE_LIMIT: usize = 100;
+
+struct PendingMessage {
+    /// Delivery job of the message, which is kept until the message was handled
+    job_id: u64,
+    message: OutgoingGroupMessage,
+    queued_at: Instant,
+}
+
+/// Holds outgoing messages for groups, whose members are not known yet,
+/// until the group creator answered our group sync request
+pub struct PendingMessageQueue {
+    queues: HashMap<(String, Vec<u8>), VecDeque<PendingMessage>>,
+    /// Delivery jobs of the expired messages, which were not taken yet
+    expired_job_ids: Vec<u64>,
+    ttl: Duration,
+    limit: usize,
+}
+
+impl PendingMessageQueue {
+    /// `limit` is the maximum number of queued messages per group
+    pub fn new(ttl: Duration, limit: usize) -> PendingMessageQueue {
+        return PendingMessageQueue {
+            queues: HashMap::new(),
+            expired_job_ids: Vec::new(),
+            ttl,
+            limit,
+        };
+    }
+
+    /// Queues a message and returns whether it is the first pending message of the group
+    pub fn push(
+        &mut self,
+        group_creator: &str,
+        group_id: &[u8],
+        job_id: u64,
+        message: OutgoingGroupMessage,
+    ) -> Result<bool, SendGroupMessageError> {
+        self.remove_expired();
+        let queue = self
+            .queues
+            .entry((group_creator.to_owned(), group_id.to_vec()))
+            .or_default();
+        if queue.len() >= self.limit {
+            return Err(SendGroupMessageError::PendingQueueFull);
+        }
+        queue.push_back(PendingMessage {
+            job_id,
+            message,
+            queued_at: Instant::now(),
+        });
+        return Ok(queue.len() == 1);
+    }
+
+    /// Removes all pending messages of a group with their delivery jobs, oldest first
+    pub fn take(
+        &mut self,
+        group_creator: &str,
+        group_id: &[u8],
+    ) -> Vec<(u64, OutgoingGroupMessage)> {
+        self.remove_expired();
+        return self
+            .queues
+            .remove(&(group_creator.to_owned(), group_id.to_vec()))
+            .map(|queue| {
+                queue
+                    .into_iter()
+                    .map(|pending| (pending.job_id, pending.message))
+                    .collect()
+            })
+            .unwrap_or_default();
+    }
+
+    /// Removes the expired messages and returns the delivery jobs of all messages, which expired
+    /// since the last call
+    pub fn take_expired(&mut self) -> Vec<u64> {
+        self.remove_expired();
+        return std::mem::take(&mut self.expired_job_ids);
+    }
+
+    fn remove_expired(&mut self) {
+        let ttl = self.ttl;
+        for queue in self.queues.values_mut() {
+            while let Some(pending) = queue.front() {
+                if pending.queued_at.elapsed() < ttl {
+                    break;
+                }
+                self.expired_job_ids.push(pending.job_id);
+                queue.pop_front();
+            }
+        }
+        self.queues.retain(|_, queue| !queue.is_empty());
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use log::debug;
+use serde_derive::{Deserialize, Serialize};
+use threema_gateway::PublicKey;
+
+use crate::errors::StoreError;
+use crate::util::now;
+
+const PUBKEYS_TREE_NAME: &str = "threema_pubkeys";
+pub const DEFAULT_PUBKEY_CACHE_TTL_SECS: u64 = 7 * 24 * 60 * 60;
+
+#[derive(Clone, Serialize, Deserialize)]
+struct CachedPublicKey {
+    key: Vec<u8>,
+    /// Unix timestamp in seconds of the lookup
+    fetched_at: u64,
+}
+
+/// Cache of public keys looked up at the gateway, which is written through to the on-disk store.
+/// Every lookup costs credits, so keys are only looked up again after the TTL.
+pub struct PublicKeyCache {
+    keys: HashMap<String, CachedPublicKey>,
+    ttl: Duration,
+    tree: sled::Tree,
+}
+
+impl PublicKeyCache {
+    pub fn open(db: &sled::Db, ttl: Duration) -> Result<PublicKeyCache, StoreError> {
+        let tree = db
+            .open_tree(PUBKEYS_TREE_NAME)
+            .map_err(|e| StoreError::DbError(e))?;
+
+        let mut keys = HashMap::new();
+        for entry in tree.iter() {
+            let (threema_id, cached) = entry.map_err(|e| StoreError::DbError(e))?;
+            let cached: CachedPublicKey =
+                serde_json::from_slice(&cached).map_err(|e| StoreError::SerializationError(e))?;
+            keys.insert(String::from_utf8_lossy(&threema_id).into_owned(), cached);
+        }
+        debug!("Threema: Loaded {} public keys from store", keys.len());
+
+        return Ok(PublicKeyCache { keys, ttl, tree });
+    }
+
+    /// Returns the cached key, unless it is older than the TTL
+    pub fn get(&self, threema_id: &str) -> Option<PublicKey> {
+        let cached = self.keys.get(threema_id)?;
+        if now().saturating_sub(cached.fetched_at) >= self.ttl.as_secs() {
+            return None;
+        }
+        return PublicKey::from_slice(&cached.key);
+    }
+
+    /// Stores a freshly looked up key and returns the previously known key, if it was different
+    pub fn insert(
+        &mut self,
+        threema_id: &str,
+        key: &PublicKey,
+    ) -> Result<Option<PublicKey>, StoreError> {
+        let cached = CachedPublicKey {
+            key: key.0.to_vec(),
+            fetched_at: now(),
+        };
+        let serialized =
+            serde_json::to_vec(&cached).map_err(|e| StoreError::SerializationError(e))?;
+        let previous = self.keys.insert(threema_id.to_owned(), cached);
+        self.tree
+            .insert(threema_id, serialized)
+            .map_err(|e| StoreError::DbError(e))?;
+
+        return Ok(previous
+            .and_then(|previous| PublicKey::from_slice(&previous.key))
+            .filter(|previous| previous != key));
+    }
+}
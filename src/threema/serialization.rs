@@ -1,9 +1,9 @@
-use std::iter::repeat;
+use std::iter::repeat_n;
 
 use rand::Rng;
-use threema_gateway::{E2eApi, EncryptedMessage, RecipientKey};
+use threema_gateway::{E2eApi, EncryptedMessage, FileMessage, RecipientKey};
 
-use crate::threema::types::MessageType;
+use crate::threema::types::{DeliveryReceiptStatus, MessageType};
 
 pub fn encrypt_group_sync_req_msg(
     group_id: &[u8],
@@ -11,15 +11,15 @@ pub fn encrypt_group_sync_req_msg(
     threema_api: &E2eApi,
 ) -> EncryptedMessage {
     let padding_amount = random_padding_amount();
-    let padding = repeat(padding_amount).take(padding_amount as usize);
-    let msgtype_byte = repeat(MessageType::GroupRequestSync.into()).take(1);
+    let padding = repeat_n(padding_amount, padding_amount as usize);
+    let msgtype_byte = repeat_n(MessageType::GroupRequestSync.into(), 1);
 
     let padded_plaintext: Vec<u8> = msgtype_byte
         .chain(group_id.iter().cloned())
         .chain(padding)
         .collect();
 
-    threema_api.encrypt_raw(&padded_plaintext, &recipient_key)
+    threema_api.encrypt_raw(&padded_plaintext, recipient_key)
 }
 
 pub fn encrypt_group_text_msg(
@@ -30,8 +30,8 @@ pub fn encrypt_group_text_msg(
     threema_api: &E2eApi,
 ) -> EncryptedMessage {
     let padding_amount = random_padding_amount();
-    let padding = repeat(padding_amount).take(padding_amount as usize);
-    let msgtype_byte = repeat(MessageType::GroupText.into()).take(1);
+    let padding = repeat_n(padding_amount, padding_amount as usize);
+    let msgtype_byte = repeat_n(MessageType::GroupText.into(), 1);
 
     let data: Vec<u8> = group_creator
         .as_bytes()
@@ -45,7 +45,103 @@ pub fn encrypt_group_text_msg(
         .chain(padding)
         .collect();
 
-    threema_api.encrypt_raw(&padded_plaintext, &recipient_key)
+    threema_api.encrypt_raw(&padded_plaintext, recipient_key)
+}
+
+pub fn encrypt_group_file_msg(
+    file_msg: &FileMessage,
+    group_creator: &str,
+    group_id: &[u8],
+    recipient_key: &RecipientKey,
+    threema_api: &E2eApi,
+) -> Result<EncryptedMessage, serde_json::Error> {
+    let file_msg_json = serde_json::to_string(file_msg)?;
+    let data: Vec<u8> = group_creator
+        .bytes()
+        .chain(group_id.iter().cloned())
+        .chain(file_msg_json.bytes())
+        .collect();
+    return Ok(encrypt_padded_msg(
+        MessageType::GroupFile,
+        &data,
+        recipient_key,
+        threema_api,
+    ));
+}
+
+/// Message ids have to be the raw 8 bytes, as the gateway returned them hex encoded
+pub fn encrypt_delivery_receipt_msg(
+    status: DeliveryReceiptStatus,
+    message_ids: &[Vec<u8>],
+    recipient_key: &RecipientKey,
+    threema_api: &E2eApi,
+) -> EncryptedMessage {
+    let data: Vec<u8> = repeat_n(status.into(), 1)
+        .chain(message_ids.iter().flatten().cloned())
+        .collect();
+    return encrypt_padded_msg(
+        MessageType::DeliveryReceipt,
+        &data,
+        recipient_key,
+        threema_api,
+    );
+}
+
+/// Tells the receiver the members of a group, which we created. The receiver leaves the group,
+/// if they are not a member.
+pub fn encrypt_group_create_msg(
+    group_id: &[u8],
+    members: &[String],
+    recipient_key: &RecipientKey,
+    threema_api: &E2eApi,
+) -> EncryptedMessage {
+    let data: Vec<u8> = group_id
+        .iter()
+        .cloned()
+        .chain(members.iter().flat_map(|member| member.bytes()))
+        .collect();
+    return encrypt_padded_msg(MessageType::GroupCreate, &data, recipient_key, threema_api);
+}
+
+pub fn encrypt_group_rename_msg(
+    group_id: &[u8],
+    group_name: &str,
+    recipient_key: &RecipientKey,
+    threema_api: &E2eApi,
+) -> EncryptedMessage {
+    let data: Vec<u8> = group_id.iter().cloned().chain(group_name.bytes()).collect();
+    return encrypt_padded_msg(MessageType::GroupRename, &data, recipient_key, threema_api);
+}
+
+pub fn encrypt_group_delete_photo_msg(
+    group_id: &[u8],
+    recipient_key: &RecipientKey,
+    threema_api: &E2eApi,
+) -> EncryptedMessage {
+    return encrypt_padded_msg(
+        MessageType::GroupDeletePhoto,
+        group_id,
+        recipient_key,
+        threema_api,
+    );
+}
+
+fn encrypt_padded_msg(
+    message_type: MessageType,
+    data: &[u8],
+    recipient_key: &RecipientKey,
+    threema_api: &E2eApi,
+) -> EncryptedMessage {
+    let padding_amount = random_padding_amount();
+    let padding = repeat_n(padding_amount, padding_amount as usize);
+    let msgtype_byte = repeat_n(message_type.into(), 1);
+
+    let padded_plaintext: Vec<u8> = msgtype_byte
+        .chain(data.iter().cloned())
+        .chain(padding)
+        .collect();
+
+    threema_api.encrypt_raw(&padded_plaintext, recipient_key)
 }
 
 fn random_padding_amount() -> u8 {
@@ -1,17 +1,124 @@
+use std::fmt::{Display, Formatter};
+
+use serde_derive::{Deserialize, Serialize};
+use threema_gateway::errors::ApiError;
+
 // Custom internal types
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MessageGroup {
     pub members: Vec<String>,
     pub group_creator: String,
     pub name: String,
 }
 
+/// Outcome of sending a message to each member of a group
+#[derive(Debug, Default)]
+pub struct GroupSendReport {
+    /// Receivers with the id of the message, which was sent to them
+    pub sent: Vec<(String, String)>,
+    pub failed: Vec<(String, ApiError)>,
+}
+
+impl GroupSendReport {
+    pub fn from_results(results: Vec<(String, Result<String, ApiError>)>) -> GroupSendReport {
+        let mut report = GroupSendReport::default();
+        for (receiver, result) in results {
+            match result {
+                Ok(message_id) => report.sent.push((receiver, message_id)),
+                Err(e) => report.failed.push((receiver, e)),
+            }
+        }
+        return report;
+    }
+
+    pub fn is_complete(&self) -> bool {
+        return self.failed.is_empty();
+    }
+}
+
+impl Display for GroupSendReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let failed: Vec<String> = self
+            .failed
+            .iter()
+            .map(|(receiver, e)| format!("{} ({})", receiver, e))
+            .collect();
+        return write!(
+            f,
+            "Could not send to {} of {} group members: {}",
+            self.failed.len(),
+            self.failed.len() + self.sent.len(),
+            failed.join(", ")
+        );
+    }
+}
+
 // Threema types
 pub enum Message {
     GroupTextMessage(GroupTextMessage),
     TextMessage(TextMessage),
     GroupCreateMessage(GroupCreateMessage),
     GroupRenameMessage(GroupRenameMessage),
+    GroupLeaveMessage(GroupLeaveMessage),
+    GroupRequestSyncMessage(GroupRequestSyncMessage),
+    GroupImageMessage(GroupImageMessage),
+    GroupFileMessage(GroupFileMessage),
+    DeliveryReceiptMessage(DeliveryReceiptMessage),
+}
+
+pub struct GroupFileMessage {
+    pub base: MessageBase,
+    pub group_creator: String,
+    pub group_id: Vec<u8>,
+    pub file: ThreemaFile,
+}
+
+/// Downloaded and decrypted content of a Threema file message
+pub struct ThreemaFile {
+    pub data: Vec<u8>,
+    pub media_type: String,
+    pub file_name: Option<String>,
+    pub caption: Option<String>,
+    pub thumbnail: Option<ThreemaThumbnail>,
+}
+
+pub struct ThreemaThumbnail {
+    pub data: Vec<u8>,
+    pub media_type: String,
+}
+
+/// Message from Matrix, which is sent to a Threema group
+pub enum OutgoingGroupMessage {
+    Text(String),
+    File(ThreemaFile),
+}
+
+/// JSON payload of a Threema file message
+#[derive(Debug, Deserialize)]
+pub struct FileMessagePayload {
+    #[serde(rename = "b")]
+    pub blob_id: String,
+    #[serde(rename = "t")]
+    pub thumbnail_blob_id: Option<String>,
+    #[serde(rename = "k")]
+    pub blob_encryption_key: String,
+    #[serde(rename = "m")]
+    pub media_type: String,
+    #[serde(rename = "p")]
+    pub thumbnail_media_type: Option<String>,
+    #[serde(rename = "n")]
+    pub file_name: Option<String>,
+    #[serde(rename = "s")]
+    pub file_size_bytes: u32,
+    #[serde(rename = "d")]
+    pub caption: Option<String>,
+}
+
+pub struct GroupImageMessage {
+    pub base: MessageBase,
+    pub group_creator: String,
+    pub group_id: Vec<u8>,
+    pub image: Vec<u8>,
 }
 
 pub struct GroupRenameMessage {
@@ -20,6 +127,21 @@ pub struct GroupRenameMessage {
     pub group_name: String,
 }
 
+/// The sender has left the group
+pub struct GroupLeaveMessage {
+    pub base: MessageBase,
+    pub group_creator: String,
+    pub group_id: Vec<u8>,
+    /// Whether the sender was removed from the cached members of the group
+    pub member_removed: bool,
+}
+
+/// The sender asks the creator of the group for its members and name
+pub struct GroupRequestSyncMessage {
+    pub base: MessageBase,
+    pub group_id: Vec<u8>,
+}
+
 pub struct GroupCreateMessage {
     pub base: MessageBase,
     pub group_id: Vec<u8>,
@@ -31,7 +153,7 @@ pub struct TextMessage {
     pub text: String,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct GroupTextMessage {
     pub base: MessageBase,
     pub text: String,
@@ -39,7 +161,49 @@ pub struct GroupTextMessage {
     pub group_id: Vec<u8>,
 }
 
-#[derive(Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeliveryReceiptStatus {
+    Received,
+    Read,
+    /// The receiver tapped "thumbs up"
+    Agreed,
+    /// The receiver tapped "thumbs down"
+    Disagreed,
+}
+
+impl TryFrom<u8> for DeliveryReceiptStatus {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x01 => return Ok(DeliveryReceiptStatus::Received),
+            0x02 => return Ok(DeliveryReceiptStatus::Read),
+            0x03 => return Ok(DeliveryReceiptStatus::Agreed),
+            0x04 => return Ok(DeliveryReceiptStatus::Disagreed),
+            _ => return Err(value),
+        }
+    }
+}
+
+impl From<DeliveryReceiptStatus> for u8 {
+    fn from(value: DeliveryReceiptStatus) -> Self {
+        match value {
+            DeliveryReceiptStatus::Received => 0x01,
+            DeliveryReceiptStatus::Read => 0x02,
+            DeliveryReceiptStatus::Agreed => 0x03,
+            DeliveryReceiptStatus::Disagreed => 0x04,
+        }
+    }
+}
+
+pub struct DeliveryReceiptMessage {
+    pub base: MessageBase,
+    pub status: DeliveryReceiptStatus,
+    /// Hex encoded ids of the messages, which the receipt refers to
+    pub message_ids: Vec<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct MessageBase {
     pub from_identity: String,
     pub to_identity: String,
@@ -48,49 +212,135 @@ pub struct MessageBase {
     pub date: u64,
 }
 
+/// Types of Threema end-to-end encrypted messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MessageType {
     Text,
+    Image,
+    Location,
+    Video,
+    Audio,
+    BallotCreate,
+    BallotVote,
+    File,
+    ContactSetPhoto,
+    ContactDeletePhoto,
+    ContactRequestPhoto,
     GroupText,
+    GroupLocation,
+    GroupImage,
+    GroupVideo,
+    GroupAudio,
+    GroupFile,
     GroupCreate,
     GroupRename,
+    GroupLeave,
+    GroupSetPhoto,
     GroupRequestSync,
-    Image,
-    Video,
-    File,
+    GroupBallotCreate,
+    GroupBallotVote,
+    GroupDeletePhoto,
+    VoipCallOffer,
+    VoipCallAnswer,
+    VoipIceCandidates,
+    VoipCallHangup,
+    VoipCallRinging,
     DeliveryReceipt,
+    GroupDeliveryReceipt,
+    TypingIndicator,
+    Edit,
+    Delete,
+    GroupEdit,
+    GroupDelete,
 }
 
-impl From<u8> for MessageType {
-    fn from(value: u8) -> Self {
+impl TryFrom<u8> for MessageType {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
         match value {
-            0x01 => MessageType::Text,
-            0x41 => MessageType::GroupText,
-            0x4a => MessageType::GroupCreate,
-            0x4b => MessageType::GroupRename,
-            0x51 => MessageType::GroupRequestSync,
-            0x02 => MessageType::Image,
-            0x13 => MessageType::Video,
-            0x17 => MessageType::File,
-            0x80 => MessageType::DeliveryReceipt,
-            _ => {
-                panic!("Message type not implemented!");
-            }
+            0x01 => return Ok(MessageType::Text),
+            0x02 => return Ok(MessageType::Image),
+            0x10 => return Ok(MessageType::Location),
+            0x13 => return Ok(MessageType::Video),
+            0x14 => return Ok(MessageType::Audio),
+            0x15 => return Ok(MessageType::BallotCreate),
+            0x16 => return Ok(MessageType::BallotVote),
+            0x17 => return Ok(MessageType::File),
+            0x18 => return Ok(MessageType::ContactSetPhoto),
+            0x19 => return Ok(MessageType::ContactDeletePhoto),
+            0x1a => return Ok(MessageType::ContactRequestPhoto),
+            0x41 => return Ok(MessageType::GroupText),
+            0x42 => return Ok(MessageType::GroupLocation),
+            0x43 => return Ok(MessageType::GroupImage),
+            0x44 => return Ok(MessageType::GroupVideo),
+            0x45 => return Ok(MessageType::GroupAudio),
+            0x46 => return Ok(MessageType::GroupFile),
+            0x4a => return Ok(MessageType::GroupCreate),
+            0x4b => return Ok(MessageType::GroupRename),
+            0x4c => return Ok(MessageType::GroupLeave),
+            0x50 => return Ok(MessageType::GroupSetPhoto),
+            0x51 => return Ok(MessageType::GroupRequestSync),
+            0x52 => return Ok(MessageType::GroupBallotCreate),
+            0x53 => return Ok(MessageType::GroupBallotVote),
+            0x54 => return Ok(MessageType::GroupDeletePhoto),
+            0x60 => return Ok(MessageType::VoipCallOffer),
+            0x61 => return Ok(MessageType::VoipCallAnswer),
+            0x62 => return Ok(MessageType::VoipIceCandidates),
+            0x63 => return Ok(MessageType::VoipCallHangup),
+            0x64 => return Ok(MessageType::VoipCallRinging),
+            0x80 => return Ok(MessageType::DeliveryReceipt),
+            0x81 => return Ok(MessageType::GroupDeliveryReceipt),
+            0x90 => return Ok(MessageType::TypingIndicator),
+            0x91 => return Ok(MessageType::Edit),
+            0x92 => return Ok(MessageType::Delete),
+            0x93 => return Ok(MessageType::GroupEdit),
+            0x94 => return Ok(MessageType::GroupDelete),
+            _ => return Err(value),
         }
     }
 }
 
-impl Into<u8> for MessageType {
-    fn into(self) -> u8 {
-        match self {
+impl From<MessageType> for u8 {
+    fn from(value: MessageType) -> Self {
+        match value {
             MessageType::Text => 0x01,
+            MessageType::Image => 0x02,
+            MessageType::Location => 0x10,
+            MessageType::Video => 0x13,
+            MessageType::Audio => 0x14,
+            MessageType::BallotCreate => 0x15,
+            MessageType::BallotVote => 0x16,
+            MessageType::File => 0x17,
+            MessageType::ContactSetPhoto => 0x18,
+            MessageType::ContactDeletePhoto => 0x19,
+            MessageType::ContactRequestPhoto => 0x1a,
             MessageType::GroupText => 0x41,
+            MessageType::GroupLocation => 0x42,
+            MessageType::GroupImage => 0x43,
+            MessageType::GroupVideo => 0x44,
+            MessageType::GroupAudio => 0x45,
+            MessageType::GroupFile => 0x46,
             MessageType::GroupCreate => 0x4a,
             MessageType::GroupRename => 0x4b,
+            MessageType::GroupLeave => 0x4c,
+            MessageType::GroupSetPhoto => 0x50,
             MessageType::GroupRequestSync => 0x51,
-            MessageType::Image => 0x02,
-            MessageType::Video => 0x13,
-            MessageType::File => 0x17,
+            MessageType::GroupBallotCreate => 0x52,
+            MessageType::GroupBallotVote => 0x53,
+            MessageType::GroupDeletePhoto => 0x54,
+            MessageType::VoipCallOffer => 0x60,
+            MessageType::VoipCallAnswer => 0x61,
+            MessageType::VoipIceCandidates => 0x62,
+            MessageType::VoipCallHangup => 0x63,
+            MessageType::VoipCallRinging => 0x64,
             MessageType::DeliveryReceipt => 0x80,
+            MessageType::GroupDeliveryReceipt => 0x81,
+            MessageType::TypingIndicator => 0x90,
+            MessageType::Edit => 0x91,
+            MessageType::Delete => 0x92,
+            MessageType::GroupEdit => 0x93,
+            MessageType::GroupDelete => 0x94,
         }
     }
 }
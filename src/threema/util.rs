@@ -1,5 +1,14 @@
 use crate::errors::{ParseGroupIdError, StringifyGroupIdError};
-use crate::threema::GROUP_ID_NUM_BYTES;
+use crate::threema::{GROUP_ID_NUM_BYTES, THREEMA_ID_LENGTH};
+
+/// Threema IDs consist of 8 uppercase letters and digits, Gateway IDs start with a `*`
+pub fn is_valid_threema_id(threema_id: &str) -> bool {
+    return threema_id.len() == THREEMA_ID_LENGTH
+        && threema_id
+            .chars()
+            .enumerate()
+            .all(|(i, c)| c.is_ascii_uppercase() || c.is_ascii_digit() || (i == 0 && c == '*'));
+}
 
 pub fn convert_group_id_to_readable_string(group_id: &[u8]) -> Result<String, StringifyGroupIdError> {
     let result = group_id
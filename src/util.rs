@@ -1,4 +1,5 @@
 use log::debug;
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::{error::Error, future::Future};
 use tokio::time::{sleep, Duration};
 
@@ -16,8 +17,16 @@ pub async fn retry_request<B: Future<Output = Result<T, E>>, T, E: Error>(
         }
         debug!("Retrying due to error: {}", msg);
         sleep(Duration::from_millis(delay_in_ms)).await;
-        retry_counter = retry_counter - 1;
+        retry_counter -= 1;
         result = callback().await;
     }
     return result;
 }
+
+/// Current Unix timestamp in seconds
+pub fn now() -> u64 {
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+}
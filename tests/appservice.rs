@@ -0,0 +1,54 @@
+use matrix_sdk::reqwest::Url;
+use matrix_sdk::ruma::user_id;
+
+use threematrix::matrix::appservice::Appservice;
+use threematrix::AppserviceConfig;
+
+fn appservice() -> Appservice {
+    Appservice::new(
+        AppserviceConfig {
+            id: "threematrix".to_owned(),
+            url: "http://localhost:8888".to_owned(),
+            as_token: "as_secret".to_owned(),
+            hs_token: "hs_secret".to_owned(),
+            server_name: "matrix.example.com".to_owned(),
+            sender_localpart: "threematrix".to_owned(),
+            puppet_prefix: None,
+            registration_file: None,
+        },
+        Url::parse("https://matrix.example.com").unwrap(),
+    )
+}
+
+#[test]
+fn registration_claims_puppet_namespace() {
+    let registration = serde_yaml::to_string(&appservice().registration()).unwrap();
+
+    assert!(registration.contains("as_token: as_secret"));
+    assert!(registration.contains("hs_token: hs_secret"));
+    assert!(registration.contains("sender_localpart: threematrix"));
+    assert!(registration.contains(r"regex: '@threema_.*:matrix\.example\.com'"));
+}
+
+#[test]
+fn puppets_are_named_after_threema_ids() {
+    let appservice = appservice();
+
+    let puppet = appservice.puppet_user_id("ABCD1234").unwrap();
+    assert_eq!(puppet, user_id!("@threema_abcd1234:matrix.example.com"));
+    assert!(appservice.is_puppet(&puppet));
+    assert!(appservice.is_puppet(&appservice.puppet_user_id("*GATEWAY").unwrap()));
+    assert!(!appservice.is_puppet(user_id!("@threematrix:matrix.example.com")));
+    assert!(!appservice.is_puppet(user_id!("@threema_abcd1234:other.example.com")));
+}
+
+#[test]
+fn only_the_hs_token_is_accepted() {
+    let appservice = appservice();
+
+    assert!(appservice.is_valid_hs_token("hs_secret"));
+    assert!(!appservice.is_valid_hs_token("hs_secreT"));
+    assert!(!appservice.is_valid_hs_token("hs_secret2"));
+    assert!(!appservice.is_valid_hs_token("as_secret"));
+    assert!(!appservice.is_valid_hs_token(""));
+}
@@ -0,0 +1,45 @@
+use std::env::temp_dir;
+use std::fs::{read_to_string, remove_file};
+
+use threematrix::audit_log::{AuditAction, AuditLog};
+
+#[tokio::test]
+async fn entries_are_appended_as_json_lines() {
+    let path = temp_dir()
+        .join(format!("threematrix_audit_{}.log", std::process::id()))
+        .to_string_lossy()
+        .into_owned();
+    let audit_log = AuditLog::new(&path);
+
+    audit_log
+        .record(
+            AuditAction::Bind,
+            "@mod:example.com",
+            "!room:example.com",
+            "1 2 3 4 5 6 7 8",
+        )
+        .await
+        .unwrap();
+    audit_log
+        .record(
+            AuditAction::Unbind,
+            "ABCDEFGH",
+            "!room:example.com",
+            "1 2 3 4 5 6 7 8",
+        )
+        .await
+        .unwrap();
+    let content = read_to_string(&path).unwrap();
+    remove_file(&path).unwrap();
+
+    let entries: Vec<serde_json::Value> = content
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0]["action"], "bind");
+    assert_eq!(entries[0]["actor"], "@mod:example.com");
+    assert_eq!(entries[1]["action"], "unbind");
+    assert_eq!(entries[1]["actor"], "ABCDEFGH");
+    assert_eq!(entries[1]["group_id"], "1 2 3 4 5 6 7 8");
+}
@@ -0,0 +1,68 @@
+use std::env::temp_dir;
+use std::fs::remove_dir_all;
+
+use matrix_sdk::ruma::room_id;
+
+use threematrix::matrix::binding_index::BindingIndex;
+
+use common::reopen_db;
+
+mod common;
+
+const GROUP_ID: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
+const OTHER_GROUP_ID: [u8; 8] = [8, 7, 6, 5, 4, 3, 2, 1];
+
+#[tokio::test]
+async fn rooms_are_found_by_group_id() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let binding_index = BindingIndex::open(&db).unwrap();
+    let room = room_id!("!room:example.com");
+    let other_room = room_id!("!other:example.com");
+
+    binding_index.set(room, Some(&GROUP_ID)).await.unwrap();
+    binding_index
+        .set(other_room, Some(&GROUP_ID))
+        .await
+        .unwrap();
+    let mut rooms = binding_index.get_rooms(&GROUP_ID).await;
+    rooms.sort();
+    assert_eq!(rooms, vec![other_room.to_owned(), room.to_owned()]);
+
+    // Rebinding moves the room to the other group
+    binding_index
+        .set(room, Some(&OTHER_GROUP_ID))
+        .await
+        .unwrap();
+    assert_eq!(
+        binding_index.get_rooms(&GROUP_ID).await,
+        vec![other_room.to_owned()]
+    );
+    assert_eq!(
+        binding_index.get_group_id(room).await,
+        Some(OTHER_GROUP_ID.to_vec())
+    );
+
+    binding_index.set(other_room, None).await.unwrap();
+    assert!(binding_index.get_rooms(&GROUP_ID).await.is_empty());
+}
+
+#[tokio::test]
+async fn bindings_are_persisted() {
+    let path = temp_dir().join(format!("threematrix_bindings_{}", std::process::id()));
+    let room = room_id!("!room:example.com");
+    {
+        let db = sled::open(&path).unwrap();
+        let binding_index = BindingIndex::open(&db).unwrap();
+        binding_index.set(room, Some(&GROUP_ID)).await.unwrap();
+        db.flush().unwrap();
+    }
+
+    let db = reopen_db(&path);
+    let binding_index = BindingIndex::open(&db).unwrap();
+    let rooms = binding_index.get_rooms(&GROUP_ID).await;
+    drop(binding_index);
+    drop(db);
+    remove_dir_all(&path).unwrap();
+
+    assert_eq!(rooms, vec![room.to_owned()]);
+}
@@ -0,0 +1,134 @@
+use std::sync::Mutex;
+
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+use matrix_sdk::config::SyncSettings;
+use matrix_sdk::ruma::{device_id, user_id};
+use matrix_sdk::{Client, Session};
+use serde_json::{json, Value};
+
+pub const ROOM_ID: &str = "!room:example.com";
+pub const BOT_USER_ID: &str = "@bot:example.com";
+
+/// Homeserver, which has the bot joined to `ROOM_ID` and records the uploaded media and the sent
+/// room events
+#[derive(Default)]
+pub struct MockHomeserver {
+    /// Content type and data of each upload
+    pub uploads: Mutex<Vec<(String, Vec<u8>)>>,
+    /// Type and content of each sent room event
+    pub sent_events: Mutex<Vec<(String, Value)>>,
+}
+
+impl MockHomeserver {
+    pub fn sent_events(&self) -> Vec<(String, Value)> {
+        self.sent_events.lock().unwrap().clone()
+    }
+}
+
+fn sync_response() -> Value {
+    let state_event = |event_type: &str, state_key: &str, content: Value| {
+        json!({
+            "type": event_type,
+            "state_key": state_key,
+            "content": content,
+            "sender": BOT_USER_ID,
+            "event_id": format!("${}", event_type),
+            "origin_server_ts": 1660000000000u64,
+        })
+    };
+    json!({
+        "next_batch": "s1",
+        "rooms": {
+            "join": {
+                ROOM_ID: {
+                    "state": {
+                        "events": [
+                            state_event("m.room.create", "", json!({ "creator": BOT_USER_ID })),
+                            state_event(
+                                "m.room.member",
+                                BOT_USER_ID,
+                                json!({ "membership": "join" })
+                            ),
+                        ]
+                    },
+                    "timeline": { "events": [], "limited": false }
+                }
+            }
+        }
+    })
+}
+
+/// Answers the requests of the Matrix client by the end of their path, so that it does not matter,
+/// which API version the client picks
+async fn handle_request(
+    request: HttpRequest,
+    body: web::Bytes,
+    homeserver: web::Data<MockHomeserver>,
+) -> HttpResponse {
+    let path = request.path();
+    let response = if path == "/_matrix/client/versions" {
+        json!({ "versions": ["r0.6.1", "v1.1", "v1.2"] })
+    } else if path.ends_with("/sync") {
+        sync_response()
+    } else if path.ends_with("/keys/upload") {
+        json!({ "one_time_key_counts": {} })
+    } else if path.ends_with("/keys/query") {
+        json!({ "device_keys": {} })
+    } else if path.contains("/media/") && path.ends_with("/upload") {
+        let content_type = request
+            .headers()
+            .get("content-type")
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_owned();
+        let mut uploads = homeserver.uploads.lock().unwrap();
+        uploads.push((content_type, body.to_vec()));
+        json!({ "content_uri": format!("mxc://example.com/media{}", uploads.len()) })
+    } else if path.contains("/send/") {
+        // .../rooms/{room_id}/send/{event_type}/{txn_id}
+        let segments: Vec<&str> = path.rsplitn(3, '/').collect();
+        let content = serde_json::from_slice(&body).unwrap_or(Value::Null);
+        let mut sent_events = homeserver.sent_events.lock().unwrap();
+        // The client percent-encodes the dots of the event type
+        sent_events.push((segments[1].replace("%2E", "."), content));
+        json!({ "event_id": format!("$event{}", sent_events.len()) })
+    } else {
+        return HttpResponse::NotFound()
+            .json(json!({ "errcode": "M_UNRECOGNIZED", "error": "Unrecognized request" }));
+    };
+    HttpResponse::Ok().json(response)
+}
+
+/// Starts a mock homeserver on a random local port and returns its URL
+pub fn start_mock_homeserver(homeserver: web::Data<MockHomeserver>) -> String {
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(homeserver.clone())
+            .default_service(web::to(handle_request))
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+    let addr = server.addrs()[0];
+    actix_web::rt::spawn(server.run());
+    format!("http://{}", addr)
+}
+
+/// Client of the bot, which has synced the joined room from the mock homeserver
+pub async fn logged_in_client(homeserver_url: &str) -> Client {
+    let client = Client::builder()
+        .homeserver_url(homeserver_url)
+        .build()
+        .await
+        .unwrap();
+    client
+        .restore_login(Session {
+            access_token: "token".to_owned(),
+            user_id: user_id!("@bot:example.com").to_owned(),
+            device_id: device_id!("THREEMATRIX").to_owned(),
+        })
+        .await
+        .unwrap();
+    client.sync_once(SyncSettings::default()).await.unwrap();
+    client
+}
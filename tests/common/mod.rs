@@ -0,0 +1,226 @@
+// Not every test crate uses all helpers
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use actix_web::{web, App, HttpResponse, HttpServer, Responder};
+use data_encoding::HEXLOWER;
+use sodiumoxide::crypto::box_;
+use threema_gateway::IncomingMessage;
+
+use threematrix::threema::group_cache::GroupCache;
+use threematrix::threema::pending_messages::PendingMessageQueue;
+use threematrix::threema::pubkey_cache::PublicKeyCache;
+use threematrix::threema::ThreemaClient;
+
+pub mod homeserver;
+
+pub const GATEWAY_ID: &str = "*TESTGW1";
+pub const SENDER_ID: &str = "SENDER01";
+pub const GROUP_ID: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
+
+pub struct MockGateway {
+    pub pubkeys: HashMap<String, String>,
+    pub blobs: HashMap<String, Vec<u8>>,
+    pub sent_to: Mutex<Vec<String>>,
+    /// Nonce and box of each sent message
+    pub sent_boxes: Mutex<Vec<(Vec<u8>, Vec<u8>)>>,
+    pub blob_uploads: Mutex<usize>,
+    pub pubkey_lookups: Mutex<Vec<String>>,
+}
+
+impl MockGateway {
+    /// Mock gateway, which knows the given hex encoded public keys and no blobs
+    pub fn new(pubkeys: HashMap<String, String>) -> MockGateway {
+        MockGateway {
+            pubkeys,
+            blobs: HashMap::new(),
+            sent_to: Mutex::new(Vec::new()),
+            sent_boxes: Mutex::new(Vec::new()),
+            blob_uploads: Mutex::new(0),
+            pubkey_lookups: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+/// Client talking to a running mock gateway, which knows the public key of `SENDER_ID`
+pub struct TestSetup {
+    pub gateway: web::Data<MockGateway>,
+    pub client: ThreemaClient,
+    pub gateway_pk: box_::PublicKey,
+    pub sender_pk: box_::PublicKey,
+    pub sender_sk: box_::SecretKey,
+}
+
+impl TestSetup {
+    /// Encrypts a message from `SENDER_ID` to the gateway
+    pub fn incoming(&self, plaintext: &[u8]) -> IncomingMessage {
+        encrypt_incoming_message(plaintext, &self.sender_sk, &self.gateway_pk)
+    }
+
+    pub fn sent_to(&self) -> Vec<String> {
+        self.gateway.sent_to.lock().unwrap().clone()
+    }
+
+    /// Decrypts a message, which the gateway sent to `SENDER_ID`, and removes its padding
+    pub fn decrypt_sent(&self, index: usize) -> Vec<u8> {
+        let (nonce, ciphertext) = self.gateway.sent_boxes.lock().unwrap()[index].clone();
+        let nonce = box_::Nonce::from_slice(&nonce).unwrap();
+        let mut plaintext =
+            box_::open(&ciphertext, &nonce, &self.gateway_pk, &self.sender_sk).unwrap();
+        let padding = *plaintext.last().unwrap() as usize;
+        plaintext.truncate(plaintext.len() - padding);
+        plaintext
+    }
+}
+
+/// Sets up a client with an empty temporary store
+pub fn setup_client() -> TestSetup {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    setup_client_with(&[], HashMap::new(), &db)
+}
+
+/// Sets up a client with the given store. Besides `SENDER_ID`, the gateway knows the public keys
+/// of the members and serves the blobs.
+pub fn setup_client_with(
+    members: &[&str],
+    blobs: HashMap<String, Vec<u8>>,
+    db: &sled::Db,
+) -> TestSetup {
+    sodiumoxide::init().unwrap();
+    let (gateway_pk, gateway_sk) = box_::gen_keypair();
+    let (sender_pk, sender_sk) = box_::gen_keypair();
+
+    let mut pubkeys = HashMap::from([(SENDER_ID.to_owned(), HEXLOWER.encode(&sender_pk.0))]);
+    for member in members {
+        pubkeys.insert(
+            member.to_string(),
+            HEXLOWER.encode(&box_::gen_keypair().0 .0),
+        );
+    }
+    let gateway = web::Data::new(MockGateway {
+        blobs,
+        ..MockGateway::new(pubkeys)
+    });
+    let endpoint = start_mock_gateway(gateway.clone());
+    let client = new_client_with_store(&endpoint, &gateway_sk, db);
+
+    TestSetup {
+        gateway,
+        client,
+        gateway_pk,
+        sender_pk,
+        sender_sk,
+    }
+}
+
+async fn pubkey(path: web::Path<String>, gateway: web::Data<MockGateway>) -> impl Responder {
+    gateway
+        .pubkey_lookups
+        .lock()
+        .unwrap()
+        .push(path.to_string());
+    match gateway.pubkeys.get(path.as_str()) {
+        Some(key) => HttpResponse::Ok().body(key.clone()),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+async fn blob(path: web::Path<String>, gateway: web::Data<MockGateway>) -> impl Responder {
+    match gateway.blobs.get(path.as_str()) {
+        Some(blob) => HttpResponse::Ok().body(blob.clone()),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+async fn send_e2e(
+    form: web::Form<HashMap<String, String>>,
+    gateway: web::Data<MockGateway>,
+) -> impl Responder {
+    let to = form.get("to").cloned().unwrap_or_default();
+    gateway.sent_to.lock().unwrap().push(to);
+    let decode = |name: &str| HEXLOWER.decode(form[name].as_bytes()).unwrap();
+    gateway
+        .sent_boxes
+        .lock()
+        .unwrap()
+        .push((decode("nonce"), decode("box")));
+    HttpResponse::Ok().body("0011223344556677")
+}
+
+async fn upload_blob(gateway: web::Data<MockGateway>) -> impl Responder {
+    let mut blob_uploads = gateway.blob_uploads.lock().unwrap();
+    *blob_uploads += 1;
+    HttpResponse::Ok().body(format!("{:032x}", *blob_uploads))
+}
+
+/// Starts a mock Threema Gateway on a random local port and returns its endpoint
+pub fn start_mock_gateway(gateway: web::Data<MockGateway>) -> String {
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(gateway.clone())
+            .route("/pubkeys/{id}", web::get().to(pubkey))
+            .route("/blobs/{id}", web::get().to(blob))
+            .route("/send_e2e", web::post().to(send_e2e))
+            .route("/upload_blob", web::post().to(upload_blob))
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+    let addr = server.addrs()[0];
+    actix_web::rt::spawn(server.run());
+    format!("http://{}", addr)
+}
+
+pub fn encrypt_incoming_message(
+    plaintext: &[u8],
+    sender_sk: &box_::SecretKey,
+    gateway_pk: &box_::PublicKey,
+) -> IncomingMessage {
+    // PKCS#7 style padding, which is stripped again when decrypting
+    let mut padded = plaintext.to_vec();
+    padded.extend([3, 3, 3]);
+    let nonce = box_::gen_nonce();
+    IncomingMessage {
+        from: SENDER_ID.to_owned(),
+        to: GATEWAY_ID.to_owned(),
+        message_id: "0102030405060708".to_owned(),
+        date: 1660000000,
+        nonce: nonce.0.to_vec(),
+        box_data: box_::seal(&padded, &nonce, gateway_pk, sender_sk),
+        nickname: Some("Alice".to_owned()),
+    }
+}
+
+/// Creates a client talking to the mock gateway, using the given store
+pub fn new_client_with_store(
+    endpoint: &str,
+    gateway_sk: &box_::SecretKey,
+    db: &sled::Db,
+) -> ThreemaClient {
+    ThreemaClient::with_custom_endpoint(
+        GATEWAY_ID,
+        "secret",
+        &HEXLOWER.encode(&gateway_sk.0),
+        endpoint,
+        GroupCache::open(db).unwrap(),
+        PublicKeyCache::open(db, Duration::from_secs(60)).unwrap(),
+        PendingMessageQueue::new(Duration::from_secs(60), 2),
+    )
+    .unwrap()
+}
+
+/// Opens the store at the path again. Sled's background flusher may still hold the lock of the
+/// store, which was just dropped, for a moment.
+pub fn reopen_db(path: &Path) -> sled::Db {
+    for _ in 0..50 {
+        match sled::open(path) {
+            Ok(db) => return db,
+            Err(_) => std::thread::sleep(Duration::from_millis(20)),
+        }
+    }
+    sled::open(path).unwrap()
+}
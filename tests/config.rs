@@ -0,0 +1,33 @@
+use std::env::temp_dir;
+use std::fs::{remove_file, write};
+
+use threematrix::ThreematrixConfig;
+
+const MINIMAL_CONFIG: &str = r#"
+[threema]
+secret = "abc123"
+private_key = "abcd1234"
+gateway_own_id = "*CUSTOM1"
+
+[matrix]
+homeserver_url = "https://matrix.myserver.com"
+user = "myuser"
+password = "abc123"
+"#;
+
+#[test]
+fn store_and_audit_log_paths_are_optional() {
+    let path = temp_dir().join(format!("threematrix_cfg_{}.toml", std::process::id()));
+    let config = format!(
+        "{}\n[store]\nmessage_retention = 60\n\n[audit_log]\n",
+        MINIMAL_CONFIG
+    );
+    write(&path, config).unwrap();
+    let config = ThreematrixConfig::new(path.to_str().unwrap());
+    remove_file(&path).unwrap();
+
+    let store = config.store.unwrap();
+    assert_eq!(store.path, None);
+    assert_eq!(store.message_retention, Some(60));
+    assert_eq!(config.audit_log.unwrap().path, None);
+}
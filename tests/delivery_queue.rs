@@ -0,0 +1,204 @@
+use std::env::temp_dir;
+use std::fs::remove_dir_all;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+use matrix_sdk::ruma::room_id;
+
+use threematrix::delivery_queue::{
+    DeliveryJob, DeliveryOutcome, DeliveryQueue, MatrixMessage, ReceivedThreemaMessage,
+    MAX_DELIVERY_ATTEMPTS,
+};
+use threematrix::errors::DeliveryError;
+
+use common::reopen_db;
+
+mod common;
+
+const TREE_NAME: &str = "jobs_test";
+
+fn text_job(text: &str) -> DeliveryJob {
+    DeliveryJob::MatrixToThreema {
+        room_id: room_id!("!room:example.com").to_owned(),
+        event_id: None,
+        group_id: vec![1, 2, 3, 4, 5, 6, 7, 8],
+        group_creator: Some("CREATOR1".to_owned()),
+        message: MatrixMessage::Text(text.to_owned()),
+    }
+}
+
+fn job_text(job: DeliveryJob) -> String {
+    match job {
+        DeliveryJob::MatrixToThreema {
+            message: MatrixMessage::Text(text),
+            ..
+        } => text,
+        _ => panic!("Unexpected job"),
+    }
+}
+
+#[tokio::test]
+async fn jobs_are_returned_in_order() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let queue = DeliveryQueue::open(&db, TREE_NAME).unwrap();
+
+    queue.push(&text_job("first")).await.unwrap();
+    queue.push(&text_job("second")).await.unwrap();
+    assert_eq!(queue.len().await, 2);
+
+    let (job_id, job) = queue.next().await;
+    assert_eq!(job_text(job), "first");
+    queue.complete(job_id).unwrap();
+    let (job_id, job) = queue.next().await;
+    assert_eq!(job_text(job), "second");
+    queue.complete(job_id).unwrap();
+    assert!(queue.is_empty().await);
+}
+
+#[tokio::test]
+async fn waiting_worker_is_woken_up() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let queue = DeliveryQueue::open(&db, TREE_NAME).unwrap();
+
+    let worker_queue = queue.clone();
+    let worker = tokio::spawn(async move { job_text(worker_queue.next().await.1) });
+    queue.push(&text_job("hello")).await.unwrap();
+
+    assert_eq!(worker.await.unwrap(), "hello");
+}
+
+#[tokio::test]
+async fn uncompleted_jobs_survive_a_restart() {
+    let path = temp_dir().join(format!("threematrix_jobs_{}", std::process::id()));
+    {
+        let db = sled::open(&path).unwrap();
+        let queue = DeliveryQueue::open(&db, TREE_NAME).unwrap();
+        queue.push(&text_job("done")).await.unwrap();
+        queue.push(&text_job("interrupted")).await.unwrap();
+        let (job_id, _) = queue.next().await;
+        queue.complete(job_id).unwrap();
+        // The second job is taken, but not completed before the "crash"
+        queue.next().await;
+        db.flush().unwrap();
+    }
+
+    let db = reopen_db(&path);
+    let queue = DeliveryQueue::open(&db, TREE_NAME).unwrap();
+    let remaining = queue.len().await;
+    let (_, job) = queue.next().await;
+    drop(queue);
+    drop(db);
+    remove_dir_all(&path).unwrap();
+
+    assert_eq!(remaining, 1);
+    assert_eq!(job_text(job), "interrupted");
+}
+
+#[tokio::test]
+async fn received_threema_messages_are_stored_compactly() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let queue = DeliveryQueue::open(&db, TREE_NAME).unwrap();
+
+    let received_message = ReceivedThreemaMessage {
+        from: "SENDER01".to_owned(),
+        to: "*TESTGW1".to_owned(),
+        message_id: "0102030405060708".to_owned(),
+        date: 1660000000,
+        nonce: vec![0xab; 24],
+        box_data: vec![0xff; 1000],
+        nickname: Some("Alice".to_owned()),
+    };
+    queue
+        .push(&DeliveryJob::ThreemaToMatrix(received_message))
+        .await
+        .unwrap();
+
+    let stored = db.open_tree(TREE_NAME).unwrap().iter().values().next();
+    let stored = String::from_utf8(stored.unwrap().unwrap().to_vec()).unwrap();
+    assert!(stored.contains(&"ff".repeat(1000)));
+    assert!(stored.len() < 2200);
+
+    match queue.next().await.1 {
+        DeliveryJob::ThreemaToMatrix(message) => {
+            assert_eq!(message.nonce, vec![0xab; 24]);
+            assert_eq!(message.box_data, vec![0xff; 1000]);
+            assert_eq!(message.nickname.as_deref(), Some("Alice"));
+        }
+        _ => panic!("Unexpected job"),
+    }
+}
+
+async fn deliver_with_failures(
+    failures: u32,
+    error: fn(String) -> DeliveryError,
+) -> (Result<(), DeliveryError>, Vec<bool>, usize) {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let mut queue = DeliveryQueue::open(&db, TREE_NAME).unwrap();
+    queue.set_retry_delay(Duration::from_millis(1));
+    queue.push(&text_job("hello")).await.unwrap();
+    let (job_id, _) = queue.next().await;
+
+    let attempts = AtomicU32::new(0);
+    let last_attempt_flags = std::sync::Mutex::new(Vec::new());
+    let result = queue
+        .deliver(job_id, |is_last_attempt| {
+            last_attempt_flags.lock().unwrap().push(is_last_attempt);
+            let attempt = attempts.fetch_add(1, Ordering::SeqCst) + 1;
+            async move {
+                if attempt <= failures {
+                    Err(error(format!("attempt {}", attempt)))
+                } else {
+                    Ok(DeliveryOutcome::Delivered)
+                }
+            }
+        })
+        .await;
+
+    let remaining = db.open_tree(TREE_NAME).unwrap().len();
+    let flags = last_attempt_flags.into_inner().unwrap();
+    (result, flags, remaining)
+}
+
+#[tokio::test]
+async fn temporary_failures_are_retried() {
+    let (result, flags, remaining) = deliver_with_failures(2, DeliveryError::Temporary).await;
+    assert!(result.is_ok());
+    assert_eq!(flags, vec![false, false, false]);
+    assert_eq!(remaining, 0);
+}
+
+#[tokio::test]
+async fn permanent_failures_are_not_retried() {
+    let (result, flags, remaining) = deliver_with_failures(1, DeliveryError::Permanent).await;
+    assert!(matches!(result, Err(DeliveryError::Permanent(e)) if e == "attempt 1"));
+    assert_eq!(flags, vec![false]);
+    assert_eq!(remaining, 0);
+}
+
+#[tokio::test]
+async fn delivery_is_given_up_after_the_last_attempt() {
+    let (result, flags, remaining) =
+        deliver_with_failures(u32::MAX, DeliveryError::Temporary).await;
+    assert!(matches!(result, Err(DeliveryError::Temporary(_))));
+    assert_eq!(flags.len(), MAX_DELIVERY_ATTEMPTS as usize);
+    assert_eq!(flags.iter().filter(|flag| **flag).count(), 1);
+    assert_eq!(flags.last(), Some(&true));
+    assert_eq!(remaining, 0);
+}
+
+#[tokio::test]
+async fn deferred_jobs_are_kept_until_completed() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let queue = DeliveryQueue::open(&db, TREE_NAME).unwrap();
+    queue.push(&text_job("hello")).await.unwrap();
+    let (job_id, _) = queue.next().await;
+
+    let result = queue
+        .deliver(job_id, |_| async { Ok(DeliveryOutcome::Deferred) })
+        .await;
+    assert!(result.is_ok());
+    assert_eq!(db.open_tree(TREE_NAME).unwrap().len(), 1);
+
+    queue.complete(job_id).unwrap();
+    assert_eq!(db.open_tree(TREE_NAME).unwrap().len(), 0);
+}
@@ -0,0 +1,54 @@
+use threematrix::threema::types::{DeliveryReceiptStatus, Message};
+
+use common::{setup_client, SENDER_ID};
+
+mod common;
+
+#[actix_web::test]
+async fn delivery_receipt_is_parsed() {
+    let setup = setup_client();
+
+    let mut plaintext = vec![0x80, 0x02];
+    plaintext.extend([0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77]);
+    plaintext.extend([0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]);
+    let incoming = setup.incoming(&plaintext);
+
+    match setup.client.process_incoming_msg(&incoming).await.unwrap() {
+        Message::DeliveryReceiptMessage(receipt) => {
+            assert_eq!(receipt.status, DeliveryReceiptStatus::Read);
+            assert_eq!(
+                receipt.message_ids,
+                vec!["0011223344556677", "8899aabbccddeeff"]
+            );
+        }
+        _ => panic!("Expected a delivery receipt"),
+    }
+
+    // Message ids have a fixed length
+    let incoming = setup.incoming(&[0x80, 0x01, 0x00, 0x11]);
+    assert!(setup.client.process_incoming_msg(&incoming).await.is_err());
+}
+
+#[actix_web::test]
+async fn delivery_receipt_is_sent() {
+    let setup = setup_client();
+
+    setup
+        .client
+        .send_delivery_receipt(
+            SENDER_ID,
+            DeliveryReceiptStatus::Received,
+            &["0011223344556677".to_owned()],
+        )
+        .await
+        .unwrap();
+    assert_eq!(setup.sent_to(), vec![SENDER_ID]);
+
+    // Invalid message ids are rejected before anything is sent
+    assert!(setup
+        .client
+        .send_delivery_receipt(SENDER_ID, DeliveryReceiptStatus::Read, &["0011".to_owned()])
+        .await
+        .is_err());
+    assert_eq!(setup.sent_to().len(), 1);
+}
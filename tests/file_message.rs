@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+use actix_web::web;
+use matrix_sdk::ruma::RoomId;
+use tokio::sync::Mutex;
+
+use data_encoding::HEXLOWER;
+use sodiumoxide::crypto::box_;
+use threema_gateway::{encrypt_file_data, ApiBuilder, BlobId, FileMessage, Key, RecipientKey};
+
+use threematrix::audit_log::AuditLog;
+use threematrix::delivery_queue::{
+    DeliveryJob, DeliveryQueue, ReceivedThreemaMessage, MATRIX_TO_THREEMA_TREE_NAME,
+    THREEMA_TO_MATRIX_TREE_NAME,
+};
+use threematrix::errors::ProcessIncomingMessageError;
+use threematrix::matrix::binding_index::BindingIndex;
+use threematrix::matrix::pending_bindings::PendingBindings;
+use threematrix::matrix::transaction_log::TransactionLog;
+use threematrix::message_map::MessageMap;
+use threematrix::threema::group_cache::GroupCache;
+use threematrix::threema::serialization::encrypt_group_file_msg;
+use threematrix::threema::types::{
+    Message, MessageGroup, MessageType, OutgoingGroupMessage, ThreemaFile, ThreemaThumbnail,
+};
+
+use threematrix::{run_threema_to_matrix_worker, AppState};
+
+use common::homeserver::{logged_in_client, start_mock_homeserver, MockHomeserver, ROOM_ID};
+use common::{setup_client_with, TestSetup, GATEWAY_ID, GROUP_ID, SENDER_ID};
+
+mod common;
+
+const FILE_BLOB_ID: &str = "0102030405060708090a0b0c0d0e0f10";
+const THUMBNAIL_BLOB_ID: &str = "1112131415161718191a1b1c1d1e1f20";
+
+fn file_message_json(key: &[u8], with_thumbnail: bool) -> String {
+    let thumbnail = if with_thumbnail {
+        format!(r#""t":"{}","p":"image/jpeg","#, THUMBNAIL_BLOB_ID)
+    } else {
+        "".to_owned()
+    };
+    format!(
+        r#"{{"b":"{}",{}"k":"{}","m":"application/pdf","n":"report.pdf","s":11,"d":"Quarterly report","j":0,"i":0}}"#,
+        FILE_BLOB_ID,
+        thumbnail,
+        HEXLOWER.encode(key)
+    )
+}
+
+/// Returns the client and the key of the uploaded file blobs
+fn setup(file_data: &[u8], thumbnail_data: &[u8]) -> (TestSetup, Vec<u8>) {
+    let (encrypted_file, encrypted_thumbnail, key) =
+        encrypt_file_data(file_data, Some(thumbnail_data));
+    let blobs = HashMap::from([
+        (FILE_BLOB_ID.to_owned(), encrypted_file),
+        (THUMBNAIL_BLOB_ID.to_owned(), encrypted_thumbnail.unwrap()),
+    ]);
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    (setup_client_with(&[], blobs, &db), key.0.to_vec())
+}
+
+#[actix_web::test]
+async fn group_file_message_is_downloaded_and_decrypted() {
+    let (setup, key) = setup(b"%PDF-1.4...", b"thumbnail");
+
+    let mut plaintext = vec![0x46];
+    plaintext.extend(SENDER_ID.as_bytes());
+    plaintext.extend(GROUP_ID);
+    plaintext.extend(file_message_json(&key, true).as_bytes());
+    let incoming = setup.incoming(&plaintext);
+
+    let message = setup.client.process_incoming_msg(&incoming).await.unwrap();
+
+    match message {
+        Message::GroupFileMessage(msg) => {
+            assert_eq!(msg.group_creator, SENDER_ID);
+            assert_eq!(msg.group_id, GROUP_ID.to_vec());
+            assert_eq!(msg.base.push_from_name.as_deref(), Some("Alice"));
+            assert_eq!(msg.file.data, b"%PDF-1.4...".to_vec());
+            assert_eq!(msg.file.media_type, "application/pdf");
+            assert_eq!(msg.file.file_name.as_deref(), Some("report.pdf"));
+            assert_eq!(msg.file.caption.as_deref(), Some("Quarterly report"));
+            let thumbnail = msg.file.thumbnail.expect("thumbnail missing");
+            assert_eq!(thumbnail.data, b"thumbnail".to_vec());
+            assert_eq!(thumbnail.media_type, "image/jpeg");
+        }
+        _ => panic!("Expected a group file message"),
+    }
+
+    // The group is unknown to the bridge, so it has to ask the creator for a group sync
+    assert_eq!(setup.sent_to(), vec![SENDER_ID.to_owned()]);
+}
+
+#[actix_web::test]
+async fn group_file_message_is_delivered_to_the_bound_room() {
+    let (setup, key) = setup(b"%PDF-1.4...", b"thumbnail");
+    let homeserver = web::Data::new(MockHomeserver::default());
+    let matrix_client = logged_in_client(&start_mock_homeserver(homeserver.clone())).await;
+
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let room_id = <&RoomId>::try_from(ROOM_ID).unwrap();
+    let binding_index = BindingIndex::open(&db).unwrap();
+    binding_index.set(room_id, Some(&GROUP_ID)).await.unwrap();
+    let app_state = web::Data::new(AppState {
+        threema_client: setup.client.clone(),
+        matrix_client: Mutex::new(matrix_client),
+        appservice: None,
+        pending_bindings: PendingBindings::new(),
+        audit_log: AuditLog::new("threematrix_test_audit.log"),
+        binding_index,
+        threema_to_matrix_queue: DeliveryQueue::open(&db, THREEMA_TO_MATRIX_TREE_NAME).unwrap(),
+        matrix_to_threema_queue: DeliveryQueue::open(&db, MATRIX_TO_THREEMA_TREE_NAME).unwrap(),
+        message_map: MessageMap::open(&db).unwrap(),
+        transaction_log: TransactionLog::open(&db).unwrap(),
+    });
+
+    let mut plaintext = vec![0x46];
+    plaintext.extend(SENDER_ID.as_bytes());
+    plaintext.extend(GROUP_ID);
+    plaintext.extend(file_message_json(&key, true).as_bytes());
+    let incoming = setup.incoming(&plaintext);
+    app_state
+        .threema_to_matrix_queue
+        .push(&DeliveryJob::ThreemaToMatrix(ReceivedThreemaMessage::from(
+            &incoming,
+        )))
+        .await
+        .unwrap();
+    actix_web::rt::spawn(run_threema_to_matrix_worker(app_state.clone()));
+
+    // File and caption
+    for _ in 0..100 {
+        if homeserver.sent_events().len() >= 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    let sent_events = homeserver.sent_events();
+    assert_eq!(sent_events.len(), 2);
+
+    let uploads = homeserver.uploads.lock().unwrap().clone();
+    assert_eq!(
+        uploads,
+        vec![
+            ("image/jpeg".to_owned(), b"thumbnail".to_vec()),
+            ("application/pdf".to_owned(), b"%PDF-1.4...".to_vec()),
+        ]
+    );
+
+    let (event_type, file_event) = &sent_events[0];
+    assert_eq!(event_type, "m.room.message");
+    assert_eq!(file_event["msgtype"], "m.file");
+    assert_eq!(file_event["body"], "Alice: report.pdf");
+    assert_eq!(file_event["url"], "mxc://example.com/media2");
+    assert_eq!(file_event["info"]["mimetype"], "application/pdf");
+    assert_eq!(
+        file_event["info"]["thumbnail_url"],
+        "mxc://example.com/media1"
+    );
+
+    let (_, caption_event) = &sent_events[1];
+    assert_eq!(caption_event["msgtype"], "m.text");
+    assert_eq!(caption_event["body"], "Alice: Quarterly report");
+
+    // The message can be looked up for replies and receipts
+    let bridged = app_state
+        .message_map
+        .get_by_threema_id(&incoming.message_id)
+        .unwrap();
+    assert_eq!(bridged.len(), 1);
+}
+
+#[actix_web::test]
+async fn group_file_message_without_thumbnail_is_downloaded_and_decrypted() {
+    let (setup, key) = setup(b"%PDF-1.4...", b"thumbnail");
+
+    let mut plaintext = vec![0x46];
+    plaintext.extend(SENDER_ID.as_bytes());
+    plaintext.extend(GROUP_ID);
+    plaintext.extend(file_message_json(&key, false).as_bytes());
+    let incoming = setup.incoming(&plaintext);
+
+    match setup.client.process_incoming_msg(&incoming).await.unwrap() {
+        Message::GroupFileMessage(msg) => {
+            assert_eq!(msg.file.data, b"%PDF-1.4...".to_vec());
+            assert_eq!(msg.file.caption.as_deref(), Some("Quarterly report"));
+            assert!(msg.file.thumbnail.is_none());
+        }
+        _ => panic!("Expected a group file message"),
+    }
+}
+
+#[actix_web::test]
+async fn direct_file_and_image_messages_are_not_supported() {
+    let (setup, key) = setup(b"%PDF-1.4...", b"thumbnail");
+
+    let mut plaintext = vec![0x17];
+    plaintext.extend(file_message_json(&key, false).as_bytes());
+    let incoming = setup.incoming(&plaintext);
+    assert!(matches!(
+        setup.client.process_incoming_msg(&incoming).await,
+        Err(ProcessIncomingMessageError::UnsupportedMessageType(
+            MessageType::File
+        ))
+    ));
+
+    let mut plaintext = vec![0x02];
+    plaintext.extend(HEXLOWER.decode(FILE_BLOB_ID.as_bytes()).unwrap());
+    plaintext.extend([0; 28]);
+    let incoming = setup.incoming(&plaintext);
+    assert!(matches!(
+        setup.client.process_incoming_msg(&incoming).await,
+        Err(ProcessIncomingMessageError::UnsupportedMessageType(
+            MessageType::Image
+        ))
+    ));
+}
+
+/// Splits a decrypted group file message into its creator, group id and JSON payload
+fn split_group_file_msg(plaintext: &[u8]) -> (String, Vec<u8>, serde_json::Value) {
+    assert_eq!(plaintext[0], 0x46);
+    let creator = String::from_utf8(plaintext[1..9].to_vec()).unwrap();
+    let payload = serde_json::from_slice(&plaintext[17..]).unwrap();
+    (creator, plaintext[9..17].to_vec(), payload)
+}
+
+#[test]
+fn group_file_message_is_encrypted_for_the_recipient() {
+    sodiumoxide::init().unwrap();
+    let (sender_pk, sender_sk) = box_::gen_keypair();
+    let (recipient_pk, recipient_sk) = box_::gen_keypair();
+    let api = ApiBuilder::new(GATEWAY_ID, "secret")
+        .with_private_key_str(&HEXLOWER.encode(&sender_sk.0))
+        .unwrap()
+        .into_e2e()
+        .unwrap();
+    let file_msg = FileMessage::builder(
+        BlobId::from_str(FILE_BLOB_ID).unwrap(),
+        Key::from_slice(&[7; 32]).unwrap(),
+        mime::APPLICATION_PDF,
+        11,
+    )
+    .file_name("report.pdf")
+    .description("*Bob*: Quarterly report")
+    .build()
+    .unwrap();
+
+    let encrypted = encrypt_group_file_msg(
+        &file_msg,
+        SENDER_ID,
+        &GROUP_ID,
+        &RecipientKey(recipient_pk),
+        &api,
+    )
+    .unwrap();
+    let nonce = box_::Nonce::from_slice(&encrypted.nonce).unwrap();
+    let mut plaintext =
+        box_::open(&encrypted.ciphertext, &nonce, &sender_pk, &recipient_sk).unwrap();
+    let padding = *plaintext.last().unwrap() as usize;
+    plaintext.truncate(plaintext.len() - padding);
+
+    let (creator, group_id, payload) = split_group_file_msg(&plaintext);
+    assert_eq!(creator, SENDER_ID);
+    assert_eq!(group_id, GROUP_ID);
+    assert_eq!(payload["b"], FILE_BLOB_ID);
+    assert_eq!(payload["k"], HEXLOWER.encode(&[7; 32]));
+    assert_eq!(payload["m"], "application/pdf");
+    assert_eq!(payload["n"], "report.pdf");
+    assert_eq!(payload["d"], "*Bob*: Quarterly report");
+}
+
+#[actix_web::test]
+async fn matrix_attachment_is_uploaded_and_sent_to_the_group() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    GroupCache::open(&db)
+        .unwrap()
+        .insert(
+            &GROUP_ID,
+            MessageGroup {
+                members: vec![SENDER_ID.to_owned()],
+                group_creator: GATEWAY_ID.to_owned(),
+                name: "Group".to_owned(),
+            },
+        )
+        .unwrap();
+    let setup = setup_client_with(&[], HashMap::new(), &db);
+
+    let file = ThreemaFile {
+        data: b"PNG image".to_vec(),
+        media_type: "image/png".to_owned(),
+        file_name: Some("cat.png".to_owned()),
+        caption: Some("*Bob*".to_owned()),
+        thumbnail: Some(ThreemaThumbnail {
+            data: b"thumbnail".to_vec(),
+            media_type: "image/jpeg".to_owned(),
+        }),
+    };
+    let report = setup
+        .client
+        .send_or_queue_group_msg(
+            OutgoingGroupMessage::File(file),
+            &GROUP_ID,
+            Some(GATEWAY_ID),
+            1,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(report.sent.len(), 1);
+
+    // File and thumbnail
+    assert_eq!(*setup.gateway.blob_uploads.lock().unwrap(), 2);
+    assert_eq!(setup.sent_to(), vec![SENDER_ID]);
+    let (creator, group_id, payload) = split_group_file_msg(&setup.decrypt_sent(0));
+    assert_eq!(creator, GATEWAY_ID);
+    assert_eq!(group_id, GROUP_ID);
+    assert_eq!(payload["m"], "image/png");
+    assert_eq!(payload["n"], "cat.png");
+    assert_eq!(payload["d"], "*Bob*");
+    assert_eq!(payload["s"], 9);
+    assert_eq!(payload["p"], "image/jpeg");
+    // Shown as media rather than as file
+    assert_eq!(payload["j"], 1);
+}
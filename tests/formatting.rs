@@ -0,0 +1,285 @@
+use matrix_sdk::ruma::events::room::message::{MessageType, RoomMessageEventContent};
+use threema_gateway::IncomingMessage;
+
+use threematrix::formatting::{
+    escape_html, html_to_threema, matrix_attachment_caption, sanitize_nickname,
+    threema_text_to_matrix_content, threema_to_html, unescape_html,
+};
+use threematrix::threema::types::Message;
+
+use common::{setup_client, GROUP_ID, SENDER_ID};
+
+mod common;
+
+/// Returns the plain and the formatted body
+fn text_bodies(content: RoomMessageEventContent) -> (String, String) {
+    match content.msgtype {
+        MessageType::Text(text) => (text.body, text.formatted.unwrap().body),
+        _ => panic!("Expected a text message"),
+    }
+}
+
+#[test]
+fn html_is_escaped() {
+    assert_eq!(
+        escape_html(r#"<a href="x">Tom & 'Jerry'</a>"#),
+        "&lt;a href=&quot;x&quot;&gt;Tom &amp; &#39;Jerry&#39;&lt;/a&gt;"
+    );
+    assert_eq!(
+        unescape_html("&lt;b&gt; &amp;amp; &quot;&#39;&#x41;&#66;&nbsp;&unknown; & x"),
+        "<b> &amp; \"'AB &unknown; & x"
+    );
+}
+
+#[test]
+fn threema_markup_is_converted_to_html() {
+    assert_eq!(threema_to_html("*bold*"), "<strong>bold</strong>");
+    assert_eq!(threema_to_html("_italic_"), "<em>italic</em>");
+    assert_eq!(threema_to_html("~strike~"), "<del>strike</del>");
+    assert_eq!(
+        threema_to_html("This is *very _important_*!"),
+        "This is <strong>very <em>important</em></strong>!"
+    );
+    assert_eq!(
+        threema_to_html("*one* and *two*"),
+        "<strong>one</strong> and <strong>two</strong>"
+    );
+}
+
+#[test]
+fn markers_inside_words_are_kept() {
+    assert_eq!(threema_to_html("snake_case_name"), "snake_case_name");
+    assert_eq!(threema_to_html("2*3*4"), "2*3*4");
+    assert_eq!(threema_to_html("* not bold *"), "* not bold *");
+    assert_eq!(threema_to_html("*unclosed"), "*unclosed");
+    // Markup does not span several lines
+    assert_eq!(threema_to_html("*first\nsecond*"), "*first<br>second*");
+}
+
+#[test]
+fn threema_text_is_escaped() {
+    assert_eq!(
+        threema_to_html("<script>alert(1)</script> & *<b>*"),
+        "&lt;script&gt;alert(1)&lt;/script&gt; &amp; <strong>&lt;b&gt;</strong>"
+    );
+    assert_eq!(threema_to_html("a\nb"), "a<br>b");
+}
+
+#[test]
+fn threema_code_is_converted_to_html() {
+    assert_eq!(
+        threema_to_html("Run `cargo *build*` now"),
+        "Run <code>cargo *build*</code> now"
+    );
+    assert_eq!(
+        threema_to_html("Example:\n```\nfn main() {\n    a < b\n}\n```\nDone"),
+        "Example:<pre><code>fn main() {\n    a &lt; b\n}</code></pre>Done"
+    );
+    assert_eq!(threema_to_html("``` unclosed"), "``` unclosed");
+    assert_eq!(threema_to_html("``"), "``");
+}
+
+#[test]
+fn threema_links_are_converted_to_html() {
+    assert_eq!(
+        threema_to_html("See https://example.com/a_b_c?x=1&y=2."),
+        r#"See <a href="https://example.com/a_b_c?x=1&amp;y=2">https://example.com/a_b_c?x=1&amp;y=2</a>."#
+    );
+    assert_eq!(
+        threema_to_html("(http://example.com) *bold*"),
+        r#"(<a href="http://example.com">http://example.com</a>) <strong>bold</strong>"#
+    );
+    assert_eq!(
+        threema_to_html(r#"https://example.com/"onmouseover="x"#),
+        r#"<a href="https://example.com/&quot;onmouseover=&quot;x">https://example.com/&quot;onmouseover=&quot;x</a>"#
+    );
+    assert_eq!(
+        threema_to_html("nothttps://example.com"),
+        "nothttps://example.com"
+    );
+}
+
+#[test]
+fn html_is_converted_to_threema_markup() {
+    assert_eq!(
+        html_to_threema("<strong>bold</strong>, <b>bold</b>, <em>italic</em>, <i>italic</i>"),
+        "*bold*, *bold*, _italic_, _italic_"
+    );
+    assert_eq!(
+        html_to_threema("<del>a</del> <s>b</s> <strike>c</strike>"),
+        "~a~ ~b~ ~c~"
+    );
+    assert_eq!(html_to_threema("a<br>b<br />c"), "a\nb\nc");
+    assert_eq!(
+        html_to_threema("<p>First</p>\n<p>Second</p>"),
+        "First\n\nSecond"
+    );
+    assert_eq!(
+        html_to_threema("<h1>Title</h1><p>Text</p>"),
+        "*Title*\nText"
+    );
+    assert_eq!(
+        html_to_threema("<span data-mx-color=\"red\">Tom &amp; Jerry &lt;3</span>"),
+        "Tom & Jerry <3"
+    );
+}
+
+#[test]
+fn html_code_is_converted_to_threema_markup() {
+    assert_eq!(
+        html_to_threema("Run <code>cargo build</code>"),
+        "Run `cargo build`"
+    );
+    assert_eq!(
+        html_to_threema(
+            "<p>Example:</p>\n<pre><code class=\"language-rust\">fn main() {\n    a &lt; b\n}\n</code></pre>\n"
+        ),
+        "Example:\n\n```\nfn main() {\n    a < b\n}\n```"
+    );
+}
+
+#[test]
+fn html_links_are_converted_to_threema_text() {
+    assert_eq!(
+        html_to_threema(r#"<a href="https://example.com">https://example.com</a>"#),
+        "https://example.com"
+    );
+    assert_eq!(
+        html_to_threema(r#"See <a href='https://example.com/?a=1&amp;b=2'>the docs</a>"#),
+        "See the docs (https://example.com/?a=1&b=2)"
+    );
+    // Mentions are kept as the display name
+    assert_eq!(
+        html_to_threema(r#"<a href="https://matrix.to/#/@alice:example.com">Alice</a>: hi"#),
+        "Alice: hi"
+    );
+}
+
+#[test]
+fn html_lists_and_quotes_are_converted_to_threema_text() {
+    assert_eq!(
+        html_to_threema("<ul>\n<li>one</li>\n<li>two</li>\n</ul>\n"),
+        "- one\n- two"
+    );
+    assert_eq!(
+        html_to_threema("<ol start=\"3\"><li>three</li><li>four</li></ol>"),
+        "3. three\n4. four"
+    );
+    assert_eq!(
+        html_to_threema("<blockquote>\n<p>Shall we meet?<br>At 8?</p>\n</blockquote>\n<p>Sure</p>"),
+        "> Shall we meet?\n> At 8?\n\nSure"
+    );
+}
+
+#[test]
+fn html_reply_fallback_is_removed() {
+    assert_eq!(
+        html_to_threema(
+            "<mx-reply><blockquote><a href=\"https://matrix.to/#/!room:example.com/$event\">In reply to</a> <a href=\"https://matrix.to/#/@alice:example.com\">@alice:example.com</a><br>Shall we meet?</blockquote></mx-reply>Sounds <em>good</em>"
+        ),
+        "Sounds _good_"
+    );
+}
+
+#[test]
+fn invalid_html_is_kept_as_text() {
+    assert_eq!(html_to_threema("1 < 2 and 3 > 2"), "1 < 2 and 3 > 2");
+    assert_eq!(html_to_threema("a <b"), "a <b");
+    assert_eq!(html_to_threema("</unknown>text<br"), "text<br");
+}
+
+#[test]
+fn malicious_nicknames_are_escaped() {
+    let content = threema_text_to_matrix_content(
+        Some("Mallory</strong>: hi<br><strong>Alice"),
+        "<img src=x onerror=alert(1)>",
+        None,
+    );
+    let (_, html) = text_bodies(content);
+    assert_eq!(
+        html,
+        "<strong>Mallory&lt;/strong&gt;: hi&lt;br&gt;&lt;strong&gt;Alice</strong>: &lt;img src=x onerror=alert(1)&gt;"
+    );
+
+    let content = threema_text_to_matrix_content(
+        Some("<a href=\"https://evil\">"),
+        "*hi*",
+        Some("<b>quoted</b>"),
+    );
+    let (body, html) = text_bodies(content);
+    assert_eq!(body, "> <b>quoted</b>\n\n<a href=\"https://evil\">: *hi*");
+    assert_eq!(
+        html,
+        "<blockquote>&lt;b&gt;quoted&lt;/b&gt;</blockquote><strong>&lt;a href=&quot;https://evil&quot;&gt;</strong>: <strong>hi</strong>"
+    );
+}
+
+#[test]
+fn nicknames_are_sanitized() {
+    assert_eq!(sanitize_nickname("Alice"), Some("Alice".to_owned()));
+    assert_eq!(
+        sanitize_nickname("  Alice \t Smith  "),
+        Some("Alice Smith".to_owned())
+    );
+    // Line breaks could fake further messages in the plain body
+    assert_eq!(
+        sanitize_nickname("Mallory: hi\nAlice"),
+        Some("Mallory: hi Alice".to_owned())
+    );
+    // A right-to-left override could make the name look like another one
+    assert_eq!(
+        sanitize_nickname("Mallory\u{202e}ecilA\u{200b}"),
+        Some("MalloryecilA".to_owned())
+    );
+    assert_eq!(sanitize_nickname("\u{200b}\u{feff} \r\n"), None);
+    assert_eq!(
+        sanitize_nickname(&"A".repeat(100)).map(|nickname| nickname.chars().count()),
+        Some(32)
+    );
+}
+
+#[actix_web::test]
+async fn incoming_nicknames_are_sanitized() {
+    let setup = setup_client();
+
+    let mut plaintext = vec![0x41];
+    plaintext.extend(SENDER_ID.as_bytes());
+    plaintext.extend(GROUP_ID);
+    plaintext.extend("Hello".as_bytes());
+    let incoming = IncomingMessage {
+        nickname: Some("Mallory\n<b>Alice</b>\u{202e}".to_owned()),
+        ..setup.incoming(&plaintext)
+    };
+
+    match setup.client.process_incoming_msg(&incoming).await.unwrap() {
+        Message::GroupTextMessage(message) => {
+            assert_eq!(
+                message.base.push_from_name.as_deref(),
+                Some("Mallory <b>Alice</b>")
+            );
+        }
+        _ => panic!("Expected a group text message"),
+    }
+    assert_eq!(
+        setup.client.get_nickname(SENDER_ID).await.as_deref(),
+        Some("Mallory <b>Alice</b>")
+    );
+}
+
+#[test]
+fn attachment_caption_leaves_out_the_file_name() {
+    assert_eq!(
+        matrix_attachment_caption("Bob", "cat.png", Some("cat.png")),
+        "*Bob*"
+    );
+    // Images don't have a separate file name, so their body is one
+    assert_eq!(matrix_attachment_caption("Bob", "cat.png", None), "*Bob*");
+    assert_eq!(
+        matrix_attachment_caption("Bob", "Look at this", Some("cat.png")),
+        "*Bob*: Look at this"
+    );
+    assert_eq!(
+        matrix_attachment_caption("Bob", " ", Some("cat.png")),
+        "*Bob*"
+    );
+}
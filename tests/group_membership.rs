@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+
+use threematrix::errors::ProcessIncomingMessageError;
+use threematrix::threema::group_cache::GroupCache;
+use threematrix::threema::types::{Message, MessageGroup};
+use threematrix::threema::util::is_valid_threema_id;
+
+use common::{setup_client_with, GATEWAY_ID, GROUP_ID, SENDER_ID};
+
+mod common;
+
+const CREATOR_ID: &str = "CREATOR1";
+const MEMBER_ID: &str = "MEMBER01";
+
+#[actix_web::test]
+async fn group_leave_removes_member() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    GroupCache::open(&db)
+        .unwrap()
+        .insert(
+            &GROUP_ID,
+            MessageGroup {
+                members: vec![
+                    CREATOR_ID.to_owned(),
+                    SENDER_ID.to_owned(),
+                    MEMBER_ID.to_owned(),
+                ],
+                group_creator: CREATOR_ID.to_owned(),
+                name: "Group".to_owned(),
+            },
+        )
+        .unwrap();
+    let setup = setup_client_with(&[], HashMap::new(), &db);
+
+    let mut plaintext = vec![0x4c];
+    plaintext.extend(CREATOR_ID.as_bytes());
+    plaintext.extend(GROUP_ID);
+    let incoming = setup.incoming(&plaintext);
+
+    match setup.client.process_incoming_msg(&incoming).await.unwrap() {
+        Message::GroupLeaveMessage(message) => {
+            assert_eq!(message.base.from_identity, SENDER_ID);
+            assert_eq!(message.group_creator, CREATOR_ID);
+            assert_eq!(message.group_id, GROUP_ID);
+            assert!(message.member_removed);
+        }
+        _ => panic!("Expected a group leave message"),
+    }
+    // Leaving again doesn't change anything
+    match setup.client.process_incoming_msg(&incoming).await.unwrap() {
+        Message::GroupLeaveMessage(message) => assert!(!message.member_removed),
+        _ => panic!("Expected a group leave message"),
+    }
+
+    let group = setup.client.get_group(CREATOR_ID, &GROUP_ID).await.unwrap();
+    assert_eq!(group.members, vec![CREATOR_ID, MEMBER_ID]);
+    assert_eq!(group.name, "Group");
+
+    // The change is persisted
+    drop(setup);
+    let groups = GroupCache::open(&db).unwrap();
+    assert_eq!(
+        groups.get(CREATOR_ID, &GROUP_ID).unwrap().members,
+        vec![CREATOR_ID, MEMBER_ID]
+    );
+}
+
+async fn request_group_sync(group: MessageGroup) -> Vec<String> {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    GroupCache::open(&db)
+        .unwrap()
+        .insert(&GROUP_ID, group)
+        .unwrap();
+    let setup = setup_client_with(&[], HashMap::new(), &db);
+
+    let mut plaintext = vec![0x51];
+    plaintext.extend(GROUP_ID);
+    let incoming = setup.incoming(&plaintext);
+
+    match setup.client.process_incoming_msg(&incoming).await.unwrap() {
+        Message::GroupRequestSyncMessage(message) => assert_eq!(message.group_id, GROUP_ID),
+        _ => panic!("Expected a group sync request"),
+    }
+    // The request is only answered by the worker
+    assert!(setup.sent_to().is_empty());
+
+    if setup
+        .client
+        .accept_group_sync_request(SENDER_ID, &GROUP_ID)
+        .await
+    {
+        setup
+            .client
+            .send_group_sync(&GROUP_ID, SENDER_ID)
+            .await
+            .unwrap();
+    }
+    setup.sent_to()
+}
+
+#[actix_web::test]
+async fn group_sync_is_answered_for_own_groups() {
+    let sent_to = request_group_sync(MessageGroup {
+        members: vec![SENDER_ID.to_owned(), MEMBER_ID.to_owned()],
+        group_creator: GATEWAY_ID.to_owned(),
+        name: "Group".to_owned(),
+    })
+    .await;
+    // Members, name and photo
+    assert_eq!(sent_to, vec![SENDER_ID, SENDER_ID, SENDER_ID]);
+}
+
+#[actix_web::test]
+async fn group_sync_of_non_member_gets_empty_member_list() {
+    let sent_to = request_group_sync(MessageGroup {
+        members: vec![MEMBER_ID.to_owned()],
+        group_creator: GATEWAY_ID.to_owned(),
+        name: "Group".to_owned(),
+    })
+    .await;
+    assert_eq!(sent_to, vec![SENDER_ID]);
+}
+
+#[actix_web::test]
+async fn group_sync_is_ignored_for_foreign_groups() {
+    let sent_to = request_group_sync(MessageGroup {
+        members: vec![SENDER_ID.to_owned(), MEMBER_ID.to_owned()],
+        group_creator: CREATOR_ID.to_owned(),
+        name: "Group".to_owned(),
+    })
+    .await;
+    assert!(sent_to.is_empty());
+}
+
+#[actix_web::test]
+async fn group_sync_requests_are_rate_limited() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    GroupCache::open(&db)
+        .unwrap()
+        .insert(
+            &GROUP_ID,
+            MessageGroup {
+                members: vec![SENDER_ID.to_owned(), MEMBER_ID.to_owned()],
+                group_creator: GATEWAY_ID.to_owned(),
+                name: "Group".to_owned(),
+            },
+        )
+        .unwrap();
+    let setup = setup_client_with(&[], HashMap::new(), &db);
+
+    assert!(
+        setup
+            .client
+            .accept_group_sync_request(SENDER_ID, &GROUP_ID)
+            .await
+    );
+    assert!(
+        !setup
+            .client
+            .accept_group_sync_request(SENDER_ID, &GROUP_ID)
+            .await
+    );
+    // Other requesters are limited on their own
+    assert!(
+        setup
+            .client
+            .accept_group_sync_request(MEMBER_ID, &GROUP_ID)
+            .await
+    );
+}
+
+#[actix_web::test]
+async fn created_group_is_stored_and_set_up() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let setup = setup_client_with(&[MEMBER_ID], HashMap::new(), &db);
+
+    let members = vec![
+        SENDER_ID.to_owned(),
+        MEMBER_ID.to_owned(),
+        SENDER_ID.to_owned(),
+        GATEWAY_ID.to_owned(),
+    ];
+    let group_id = setup.client.create_group("Team", &members).await.unwrap();
+    assert_eq!(group_id.len(), 8);
+
+    let group = setup.client.get_group(GATEWAY_ID, &group_id).await.unwrap();
+    assert_eq!(group.members, vec![SENDER_ID, MEMBER_ID]);
+    assert_eq!(group.group_creator, GATEWAY_ID);
+    assert_eq!(group.name, "Team");
+
+    // Members and name for everyone
+    let report = setup.client.send_group_setup(&group_id).await.unwrap();
+    assert_eq!(report.sent.len(), 2);
+    let mut sent_to = setup.sent_to();
+    sent_to.sort();
+    assert_eq!(sent_to, vec![MEMBER_ID, MEMBER_ID, SENDER_ID, SENDER_ID]);
+}
+
+#[actix_web::test]
+async fn only_the_creator_can_change_a_group() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    GroupCache::open(&db)
+        .unwrap()
+        .insert(
+            &GROUP_ID,
+            MessageGroup {
+                members: vec![SENDER_ID.to_owned(), MEMBER_ID.to_owned()],
+                group_creator: GATEWAY_ID.to_owned(),
+                name: "Group".to_owned(),
+            },
+        )
+        .unwrap();
+    let setup = setup_client_with(&[], HashMap::new(), &db);
+
+    // A member tries to replace the members of a group, which the gateway created
+    let mut plaintext = vec![0x4a];
+    plaintext.extend(GROUP_ID);
+    plaintext.extend(GATEWAY_ID.as_bytes());
+    plaintext.extend(CREATOR_ID.as_bytes());
+    let incoming = setup.incoming(&plaintext);
+    assert!(matches!(
+        setup.client.process_incoming_msg(&incoming).await,
+        Err(ProcessIncomingMessageError::NotGroupCreator(sender)) if sender == SENDER_ID
+    ));
+
+    let mut plaintext = vec![0x4b];
+    plaintext.extend(GROUP_ID);
+    plaintext.extend(b"Taken over");
+    let incoming = setup.incoming(&plaintext);
+    assert!(matches!(
+        setup.client.process_incoming_msg(&incoming).await,
+        Err(ProcessIncomingMessageError::NotGroupCreator(_))
+    ));
+
+    let group = setup.client.get_group(GATEWAY_ID, &GROUP_ID).await.unwrap();
+    assert_eq!(group.members, vec![SENDER_ID, MEMBER_ID]);
+    assert_eq!(group.name, "Group");
+    assert!(setup.client.get_group(SENDER_ID, &GROUP_ID).await.is_none());
+}
+
+#[test]
+fn groups_stored_by_group_id_are_migrated() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let group = MessageGroup {
+        members: vec![MEMBER_ID.to_owned()],
+        group_creator: CREATOR_ID.to_owned(),
+        name: "Group".to_owned(),
+    };
+    db.open_tree("threema_groups")
+        .unwrap()
+        .insert(GROUP_ID, serde_json::to_vec(&group).unwrap())
+        .unwrap();
+
+    let groups = GroupCache::open(&db).unwrap();
+    assert_eq!(groups.get(CREATOR_ID, &GROUP_ID).unwrap().name, "Group");
+    assert_eq!(groups.find_creator(&GROUP_ID), Some(CREATOR_ID));
+    assert!(groups.get(SENDER_ID, &GROUP_ID).is_none());
+
+    drop(groups);
+    let groups = GroupCache::open(&db).unwrap();
+    assert_eq!(
+        groups.get(CREATOR_ID, &GROUP_ID).unwrap().members,
+        vec![MEMBER_ID]
+    );
+}
+
+#[test]
+fn validates_threema_ids() {
+    assert!(is_valid_threema_id("ABCD1234"));
+    assert!(is_valid_threema_id("*GATEWAY"));
+    assert!(!is_valid_threema_id("abcd1234"));
+    assert!(!is_valid_threema_id("ABCD123"));
+    assert!(!is_valid_threema_id("ABCD*234"));
+    assert!(!is_valid_threema_id("ABCD-234"));
+}
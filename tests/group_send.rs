@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+
+use threema_gateway::errors::ApiError;
+
+use threematrix::threema::types::GroupSendReport;
+
+use common::{setup_client_with, GROUP_ID, SENDER_ID};
+
+mod common;
+
+#[actix_web::test]
+async fn message_is_sent_to_every_member() {
+    let members: Vec<String> = (0..10).map(|i| format!("MEMBER{:02}", i)).collect();
+    let receivers: Vec<&str> = members.iter().map(|id| id.as_str()).collect();
+
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let mut setup = setup_client_with(&receivers, HashMap::new(), &db);
+    setup.client.set_send_concurrency(3);
+
+    setup
+        .client
+        .send_group_msg("hello", SENDER_ID, &GROUP_ID, &receivers)
+        .await
+        .unwrap();
+
+    let mut sent_to = setup.sent_to();
+    sent_to.sort();
+    assert_eq!(sent_to, members);
+}
+
+#[test]
+fn report_lists_failed_members() {
+    let report = GroupSendReport::from_results(vec![
+        ("MEMBER01".to_owned(), Ok("0000000000000001".to_owned())),
+        ("MEMBER02".to_owned(), Err(ApiError::IdNotFound)),
+        ("MEMBER03".to_owned(), Ok("0000000000000003".to_owned())),
+    ]);
+
+    assert!(!report.is_complete());
+    assert_eq!(
+        report.sent,
+        vec![
+            ("MEMBER01".to_owned(), "0000000000000001".to_owned()),
+            ("MEMBER03".to_owned(), "0000000000000003".to_owned())
+        ]
+    );
+    assert_eq!(
+        report.to_string(),
+        "Could not send to 1 of 3 group members: MEMBER02 (target ID not found)"
+    );
+}
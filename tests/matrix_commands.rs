@@ -0,0 +1,74 @@
+use threematrix::matrix::commands::{MatrixCommand, MODERATOR_POWER_LEVEL};
+
+#[test]
+fn parses_commands() {
+    assert_eq!(MatrixCommand::parse("hello"), None);
+    assert_eq!(MatrixCommand::parse("!threematrixx status"), None);
+    assert_eq!(
+        MatrixCommand::parse("!threematrix"),
+        Some(MatrixCommand::Help)
+    );
+    assert_eq!(
+        MatrixCommand::parse("!threematrix  status"),
+        Some(MatrixCommand::Status)
+    );
+    assert_eq!(
+        MatrixCommand::parse("!threematrix confirm ABCD1234"),
+        Some(MatrixCommand::Confirm(Some("ABCD1234".to_owned())))
+    );
+    assert_eq!(
+        MatrixCommand::parse("!threematrix bind"),
+        Some(MatrixCommand::Unknown("bind".to_owned()))
+    );
+}
+
+#[test]
+fn parses_create_group_command() {
+    assert_eq!(
+        MatrixCommand::parse("!threematrix create-group Team abcd1234  EFGH5678"),
+        Some(MatrixCommand::CreateGroup {
+            name: Some("Team".to_owned()),
+            members: vec!["ABCD1234".to_owned(), "EFGH5678".to_owned()],
+        })
+    );
+    assert_eq!(
+        MatrixCommand::parse("!threematrix create-group \"Our Team\" ABCD1234"),
+        Some(MatrixCommand::CreateGroup {
+            name: Some("Our Team".to_owned()),
+            members: vec!["ABCD1234".to_owned()],
+        })
+    );
+    assert_eq!(
+        MatrixCommand::parse("!threematrix create-group"),
+        Some(MatrixCommand::CreateGroup {
+            name: None,
+            members: vec![],
+        })
+    );
+    assert_eq!(
+        MatrixCommand::CreateGroup {
+            name: None,
+            members: vec![],
+        }
+        .required_power_level(),
+        MODERATOR_POWER_LEVEL
+    );
+}
+
+#[test]
+fn moderation_commands_need_power_level() {
+    assert_eq!(MatrixCommand::Status.required_power_level(), 0);
+    assert_eq!(MatrixCommand::Help.required_power_level(), 0);
+    assert_eq!(
+        MatrixCommand::Members.required_power_level(),
+        MODERATOR_POWER_LEVEL
+    );
+    assert_eq!(
+        MatrixCommand::Unbind.required_power_level(),
+        MODERATOR_POWER_LEVEL
+    );
+    assert_eq!(
+        MatrixCommand::Confirm(Some("abcd1234".to_owned())).required_power_level(),
+        MODERATOR_POWER_LEVEL
+    );
+}
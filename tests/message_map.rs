@@ -0,0 +1,111 @@
+use std::time::Duration;
+
+use matrix_sdk::ruma::{event_id, room_id, user_id};
+
+use threematrix::message_map::{BridgeDirection, BridgedMessage, MessageMap};
+
+const GROUP_ID: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
+
+#[test]
+fn messages_are_found_from_both_sides() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let message_map = MessageMap::open(&db).unwrap();
+    let room_id = room_id!("!room:example.com");
+    let event_id = event_id!("$event:example.com");
+
+    // A Matrix message is sent to every group member with its own message id
+    let to_alice = BridgedMessage::new(
+        BridgeDirection::MatrixToThreema,
+        "ALICE001",
+        "0011223344556677",
+        &GROUP_ID,
+        room_id.to_owned(),
+        event_id.to_owned(),
+    );
+    let to_bob = BridgedMessage::new(
+        BridgeDirection::MatrixToThreema,
+        "BOB00001",
+        "8899aabbccddeeff",
+        &GROUP_ID,
+        room_id.to_owned(),
+        event_id.to_owned(),
+    );
+    message_map.insert(&to_alice).unwrap();
+    message_map.insert(&to_bob).unwrap();
+
+    assert_eq!(
+        message_map.get_by_threema_id("0011223344556677").unwrap(),
+        vec![to_alice.clone()]
+    );
+    assert_eq!(
+        message_map.get_by_event_id(event_id).unwrap(),
+        vec![to_alice, to_bob]
+    );
+    assert!(message_map
+        .get_by_threema_id("0011223344556600")
+        .unwrap()
+        .is_empty());
+    assert!(message_map
+        .get_by_event_id(event_id!("$other:example.com"))
+        .unwrap()
+        .is_empty());
+
+    // The sender gets only one read receipt
+    assert!(message_map.mark_read("0011223344556677").unwrap());
+    assert!(!message_map.mark_read("0011223344556677").unwrap());
+
+    let bot = user_id!("@bot:example.com");
+    assert!(message_map.add_reaction(event_id, bot, "👀").unwrap());
+    assert!(!message_map.add_reaction(event_id, bot, "👀").unwrap());
+    assert!(message_map.add_reaction(event_id, bot, "👍").unwrap());
+}
+
+#[test]
+fn old_messages_are_pruned() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let message_map = MessageMap::open(&db).unwrap();
+    let room_id = room_id!("!room:example.com");
+    let old_event_id = event_id!("$old:example.com");
+    let new_event_id = event_id!("$new:example.com");
+
+    let mut old_message = BridgedMessage::new(
+        BridgeDirection::ThreemaToMatrix,
+        "ALICE001",
+        "0011223344556677",
+        &GROUP_ID,
+        room_id.to_owned(),
+        old_event_id.to_owned(),
+    );
+    old_message.timestamp -= 2 * 60 * 60;
+    let new_message = BridgedMessage::new(
+        BridgeDirection::ThreemaToMatrix,
+        "ALICE001",
+        "8899aabbccddeeff",
+        &GROUP_ID,
+        room_id.to_owned(),
+        new_event_id.to_owned(),
+    );
+    message_map.insert(&old_message).unwrap();
+    message_map.insert(&new_message).unwrap();
+    message_map.mark_read("0011223344556677").unwrap();
+    let bot = user_id!("@bot:example.com");
+    message_map.add_reaction(old_event_id, bot, "👀").unwrap();
+
+    assert_eq!(message_map.prune(Duration::from_secs(60 * 60)).unwrap(), 1);
+    assert!(message_map
+        .get_by_threema_id("0011223344556677")
+        .unwrap()
+        .is_empty());
+    assert!(message_map
+        .get_by_event_id(old_event_id)
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        message_map.get_by_event_id(new_event_id).unwrap(),
+        vec![new_message]
+    );
+
+    // Read markers and reactions are removed as well
+    assert!(message_map.mark_read("0011223344556677").unwrap());
+    assert!(message_map.add_reaction(old_event_id, bot, "👀").unwrap());
+}
@@ -0,0 +1,72 @@
+use threematrix::errors::ProcessIncomingMessageError;
+use threematrix::threema::types::MessageType;
+
+use common::{setup_client, GROUP_ID};
+
+mod common;
+
+#[test]
+fn message_types_round_trip() {
+    for value in 0..=u8::MAX {
+        if let Ok(message_type) = MessageType::try_from(value) {
+            assert_eq!(u8::from(message_type), value);
+        }
+    }
+    assert_eq!(MessageType::try_from(0x10), Ok(MessageType::Location));
+    assert_eq!(MessageType::try_from(0x4c), Ok(MessageType::GroupLeave));
+    assert_eq!(
+        MessageType::try_from(0x90),
+        Ok(MessageType::TypingIndicator)
+    );
+    assert_eq!(MessageType::try_from(0xff), Err(0xff));
+}
+
+#[actix_web::test]
+async fn unsupported_and_unknown_messages_are_rejected_without_panic() {
+    let setup = setup_client();
+
+    // Typing indicator
+    let incoming = setup.incoming(&[0x90, 0x01]);
+    match setup.client.process_incoming_msg(&incoming).await {
+        Err(ProcessIncomingMessageError::UnsupportedMessageType(message_type)) => {
+            assert_eq!(message_type, MessageType::TypingIndicator)
+        }
+        _ => panic!("Expected an unsupported message type"),
+    }
+
+    // Location
+    let incoming = setup.incoming(b"\x1052.5200,13.4050");
+    match setup.client.process_incoming_msg(&incoming).await {
+        Err(ProcessIncomingMessageError::UnsupportedMessageType(message_type)) => {
+            assert_eq!(message_type, MessageType::Location)
+        }
+        _ => panic!("Expected an unsupported message type"),
+    }
+
+    let incoming = setup.incoming(&[0xfe, 0x00]);
+    match setup.client.process_incoming_msg(&incoming).await {
+        Err(ProcessIncomingMessageError::UnknownMessageType(message_type)) => {
+            assert_eq!(message_type, 0xfe)
+        }
+        _ => panic!("Expected an unknown message type"),
+    }
+
+    // Group create and rename, which are too short to contain a group id
+    for plaintext in [&[0x4a, 0x01, 0x02][..], &[0x4b, 0x01]] {
+        let incoming = setup.incoming(plaintext);
+        assert!(matches!(
+            setup.client.process_incoming_msg(&incoming).await,
+            Err(ProcessIncomingMessageError::InvalidPayloadLength)
+        ));
+    }
+
+    // Group create with a truncated member id
+    let mut plaintext = vec![0x4a];
+    plaintext.extend(GROUP_ID);
+    plaintext.extend(b"MEMBER01MEMB");
+    let incoming = setup.incoming(&plaintext);
+    assert!(matches!(
+        setup.client.process_incoming_msg(&incoming).await,
+        Err(ProcessIncomingMessageError::InvalidPayloadLength)
+    ));
+}
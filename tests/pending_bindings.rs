@@ -0,0 +1,63 @@
+use matrix_sdk::ruma::room_id;
+
+use threematrix::matrix::pending_bindings::PendingBindings;
+
+#[tokio::test]
+async fn binding_is_confirmed_once_with_matching_code() {
+    let pending_bindings = PendingBindings::new();
+    let room_id = room_id!("!abc123:example.com");
+
+    let code = pending_bindings
+        .create(room_id, &[1, 2, 3, 4, 5, 6, 7, 8], "CREATOR1")
+        .await
+        .unwrap();
+
+    assert!(pending_bindings.confirm(room_id, "WRONG").await.is_none());
+    assert!(pending_bindings
+        .confirm(room_id!("!other:example.com"), &code)
+        .await
+        .is_none());
+
+    let binding = pending_bindings
+        .confirm(room_id, &code.to_lowercase())
+        .await
+        .unwrap();
+    assert_eq!(binding.group_id, vec![1, 2, 3, 4, 5, 6, 7, 8]);
+    assert_eq!(binding.group_creator, "CREATOR1");
+
+    // The code is only valid once
+    assert!(pending_bindings.confirm(room_id, &code).await.is_none());
+}
+
+#[tokio::test]
+async fn pending_binding_is_not_replaced_by_another_group() {
+    let pending_bindings = PendingBindings::new();
+    let room_id = room_id!("!abc123:example.com");
+
+    let code = pending_bindings
+        .create(room_id, &[1, 2, 3, 4, 5, 6, 7, 8], "CREATOR1")
+        .await
+        .unwrap();
+    assert!(pending_bindings
+        .create(room_id, &[8, 7, 6, 5, 4, 3, 2, 1], "INTRUDER")
+        .await
+        .is_none());
+    // Other rooms are not affected
+    assert!(pending_bindings
+        .create(
+            room_id!("!other:example.com"),
+            &[8, 7, 6, 5, 4, 3, 2, 1],
+            "INTRUDER"
+        )
+        .await
+        .is_some());
+
+    let binding = pending_bindings.confirm(room_id, &code).await.unwrap();
+    assert_eq!(binding.group_creator, "CREATOR1");
+
+    // After the confirmation a new binding can be requested
+    assert!(pending_bindings
+        .create(room_id, &[8, 7, 6, 5, 4, 3, 2, 1], "CREATOR2")
+        .await
+        .is_some());
+}
@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use threematrix::errors::SendGroupMessageError;
+use threematrix::threema::pending_messages::PendingMessageQueue;
+use threematrix::threema::types::{Message, OutgoingGroupMessage};
+
+use common::{setup_client, setup_client_with, GATEWAY_ID, GROUP_ID, SENDER_ID};
+
+mod common;
+
+const MEMBER_ID: &str = "MEMBER02";
+
+#[actix_web::test]
+async fn queued_messages_are_sent_after_group_sync() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let setup = setup_client_with(&[MEMBER_ID], HashMap::new(), &db);
+
+    for (job_id, text) in [(1, "first"), (2, "second")] {
+        let report = setup
+            .client
+            .send_or_queue_group_msg(
+                OutgoingGroupMessage::Text(text.to_owned()),
+                &GROUP_ID,
+                Some(SENDER_ID),
+                job_id,
+            )
+            .await
+            .unwrap();
+        assert!(report.is_none());
+    }
+    // Only a single group sync request is sent to the creator
+    assert_eq!(setup.sent_to(), vec![SENDER_ID.to_owned()]);
+
+    let overflow = setup
+        .client
+        .send_or_queue_group_msg(
+            OutgoingGroupMessage::Text("third".to_owned()),
+            &GROUP_ID,
+            Some(SENDER_ID),
+            3,
+        )
+        .await;
+    assert!(matches!(
+        overflow,
+        Err(SendGroupMessageError::PendingQueueFull)
+    ));
+
+    let mut plaintext = vec![0x4a];
+    plaintext.extend(GROUP_ID);
+    plaintext.extend(GATEWAY_ID.as_bytes());
+    plaintext.extend(MEMBER_ID.as_bytes());
+    let incoming = setup.incoming(&plaintext);
+    let job_ids = match setup.client.process_incoming_msg(&incoming).await.unwrap() {
+        Message::GroupCreateMessage(msg) => {
+            setup
+                .client
+                .send_pending_group_msgs(&msg.base.from_identity, &msg.group_id)
+                .await
+        }
+        _ => panic!("Expected a group create message"),
+    };
+    assert_eq!(job_ids, vec![1, 2]);
+
+    // Both queued messages went to the creator and the other member
+    let sent_to = setup.sent_to();
+    assert_eq!(sent_to.len(), 5);
+    assert_eq!(sent_to.iter().filter(|id| *id == SENDER_ID).count(), 3);
+    assert_eq!(sent_to.iter().filter(|id| *id == MEMBER_ID).count(), 2);
+}
+
+#[actix_web::test]
+async fn message_for_unknown_group_without_creator_is_rejected() {
+    let setup = setup_client();
+
+    let result = setup
+        .client
+        .send_or_queue_group_msg(
+            OutgoingGroupMessage::Text("hi".to_owned()),
+            &GROUP_ID,
+            None,
+            1,
+        )
+        .await;
+
+    assert!(matches!(
+        result,
+        Err(SendGroupMessageError::GroupNotInCache)
+    ));
+    assert!(setup.sent_to().is_empty());
+}
+
+#[test]
+fn expired_messages_return_their_delivery_jobs() {
+    let mut queue = PendingMessageQueue::new(Duration::ZERO, 10);
+    queue
+        .push(
+            SENDER_ID,
+            &GROUP_ID,
+            7,
+            OutgoingGroupMessage::Text("hi".to_owned()),
+        )
+        .unwrap();
+
+    assert_eq!(queue.take_expired(), vec![7]);
+    assert!(queue.take_expired().is_empty());
+    assert!(queue.take(SENDER_ID, &GROUP_ID).is_empty());
+}
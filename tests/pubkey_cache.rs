@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use sodiumoxide::crypto::box_;
+
+use threematrix::threema::pubkey_cache::PublicKeyCache;
+use threematrix::threema::types::Message;
+
+use common::{setup_client_with, GROUP_ID, SENDER_ID};
+
+mod common;
+
+const MEMBER_ID: &str = "MEMBER02";
+
+#[test]
+fn keys_expire_after_ttl() {
+    sodiumoxide::init().unwrap();
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let (key, _) = box_::gen_keypair();
+    let (new_key, _) = box_::gen_keypair();
+
+    let mut cache = PublicKeyCache::open(&db, Duration::from_secs(60)).unwrap();
+    assert_eq!(cache.insert(MEMBER_ID, &key).unwrap(), None);
+    assert_eq!(cache.get(MEMBER_ID), Some(key));
+    // Looking up the same key again is not a change
+    assert_eq!(cache.insert(MEMBER_ID, &key).unwrap(), None);
+    assert_eq!(cache.insert(MEMBER_ID, &new_key).unwrap(), Some(key));
+
+    let expired_cache = PublicKeyCache::open(&db, Duration::from_secs(0)).unwrap();
+    assert_eq!(expired_cache.get(MEMBER_ID), None);
+}
+
+#[actix_web::test]
+async fn keys_are_looked_up_once() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let setup = setup_client_with(&[MEMBER_ID], HashMap::new(), &db);
+
+    for text in ["first", "second"] {
+        setup
+            .client
+            .send_group_msg(text, SENDER_ID, &GROUP_ID, &[MEMBER_ID])
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(setup.sent_to().len(), 2);
+    assert_eq!(
+        *setup.gateway.pubkey_lookups.lock().unwrap(),
+        vec![MEMBER_ID.to_owned()]
+    );
+}
+
+#[actix_web::test]
+async fn changed_key_is_looked_up_again() {
+    sodiumoxide::init().unwrap();
+    let (old_sender_pk, _) = box_::gen_keypair();
+
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    PublicKeyCache::open(&db, Duration::from_secs(60))
+        .unwrap()
+        .insert(SENDER_ID, &old_sender_pk)
+        .unwrap();
+
+    let setup = setup_client_with(&[], HashMap::new(), &db);
+
+    let incoming = setup.incoming(b"\x01hello");
+    match setup.client.process_incoming_msg(&incoming).await.unwrap() {
+        Message::TextMessage(msg) => assert_eq!(msg.text, "hello"),
+        _ => panic!("Expected a text message"),
+    }
+    assert_eq!(
+        *setup.gateway.pubkey_lookups.lock().unwrap(),
+        vec![SENDER_ID.to_owned()]
+    );
+    assert_eq!(
+        PublicKeyCache::open(&db, Duration::from_secs(60))
+            .unwrap()
+            .get(SENDER_ID),
+        Some(setup.sender_pk)
+    );
+}
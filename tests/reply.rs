@@ -0,0 +1,101 @@
+use threematrix::reply::{
+    format_threema_quote, matches_quoted_text, parse_threema_quote, strip_matrix_reply_fallback,
+    ThreemaQuote,
+};
+
+#[test]
+fn threema_quotes_are_parsed() {
+    assert_eq!(
+        parse_threema_quote("> quote #0011aabbccddeeff\n\nSounds good"),
+        Some((
+            ThreemaQuote::MessageId("0011aabbccddeeff".to_owned()),
+            "Sounds good".to_owned()
+        ))
+    );
+    assert_eq!(
+        parse_threema_quote("> quote #0011aabbccddeeff\r\n\r\nSounds good\nSee you"),
+        Some((
+            ThreemaQuote::MessageId("0011aabbccddeeff".to_owned()),
+            "Sounds good\nSee you".to_owned()
+        ))
+    );
+    // Message ids are lowercase and followed by a blank line
+    assert!(!matches!(
+        parse_threema_quote("> quote #0011AABBCCDDEEFF\n\nSounds good"),
+        Some((ThreemaQuote::MessageId(_), _))
+    ));
+    assert!(!matches!(
+        parse_threema_quote("> quote #0011aabbccddeeff\nSounds good"),
+        Some((ThreemaQuote::MessageId(_), _))
+    ));
+    assert_eq!(
+        parse_threema_quote("> Shall we meet?\n> At 8?\n\nSounds good\n\nSee you"),
+        Some((
+            ThreemaQuote::Text("Shall we meet?\nAt 8?".to_owned()),
+            "Sounds good\n\nSee you".to_owned()
+        ))
+    );
+
+    assert_eq!(parse_threema_quote("Sounds good"), None);
+    // A quote without reply is just a text starting with ">"
+    assert_eq!(parse_threema_quote("> Shall we meet?"), None);
+}
+
+#[test]
+fn threema_quotes_are_formatted() {
+    let quote = ThreemaQuote::MessageId("0011aabbccddeeff".to_owned());
+    assert_eq!(
+        format_threema_quote(&quote, "*Bob*: Sounds good"),
+        "> quote #0011aabbccddeeff\n\n*Bob*: Sounds good"
+    );
+    assert_eq!(
+        parse_threema_quote(&format_threema_quote(&quote, "*Bob*: Sounds good")),
+        Some((quote, "*Bob*: Sounds good".to_owned()))
+    );
+
+    let quote = ThreemaQuote::Text("Shall we meet?\nAt 8?".to_owned());
+    let text = format_threema_quote(&quote, "*Bob*: Sounds good");
+    assert_eq!(text, "> Shall we meet?\n> At 8?\n\n*Bob*: Sounds good");
+    assert_eq!(
+        parse_threema_quote(&text),
+        Some((quote, "*Bob*: Sounds good".to_owned()))
+    );
+}
+
+#[test]
+fn matrix_reply_fallback_is_stripped() {
+    assert_eq!(
+        strip_matrix_reply_fallback(
+            "> <@alice:example.com> Shall we meet?\n> At 8?\n\nSounds good"
+        ),
+        (
+            Some("Shall we meet?\nAt 8?".to_owned()),
+            "Sounds good".to_owned()
+        )
+    );
+    assert_eq!(
+        strip_matrix_reply_fallback("Sounds good"),
+        (None, "Sounds good".to_owned())
+    );
+}
+
+#[test]
+fn quoted_texts_are_matched() {
+    assert!(matches_quoted_text("Shall we meet?", "Shall we meet?"));
+    // Messages from Threema are prefixed with the sender in Matrix and the other way round
+    assert!(matches_quoted_text(
+        "Alice: Shall we meet?",
+        "Shall we meet?"
+    ));
+    assert!(matches_quoted_text(
+        "Shall we meet?",
+        "*Bob*: Shall we meet?"
+    ));
+    assert!(matches_quoted_text(
+        "> <@alice:example.com> Hi\n\nShall we meet?",
+        "Shall we meet?"
+    ));
+
+    assert!(!matches_quoted_text("Shall we meet today?", "meet"));
+    assert!(!matches_quoted_text("", ""));
+}
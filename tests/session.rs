@@ -0,0 +1,68 @@
+use std::env::temp_dir;
+use std::fs::{metadata, remove_file, write};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+use matrix_sdk::ruma::{device_id, user_id};
+use matrix_sdk::Session;
+
+use threematrix::matrix::session::{load_session, save_session};
+
+fn session_path(name: &str) -> String {
+    temp_dir()
+        .join(format!("threematrix_{}_{}.json", name, std::process::id()))
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[test]
+fn saved_session_is_restored() {
+    let path = session_path("session");
+    let session = Session {
+        access_token: "secret_token".to_owned(),
+        user_id: user_id!("@bot:example.com").to_owned(),
+        device_id: device_id!("THREEMATRIX").to_owned(),
+    };
+
+    save_session(&path, &session, Some("s72594_4483_1934")).unwrap();
+    let (restored, sync_token) = load_session(&path).unwrap();
+    remove_file(&path).unwrap();
+
+    assert_eq!(restored.access_token, "secret_token");
+    assert_eq!(restored.user_id, session.user_id);
+    assert_eq!(restored.device_id, session.device_id);
+    assert_eq!(sync_token.as_deref(), Some("s72594_4483_1934"));
+}
+
+#[test]
+fn session_file_is_private_and_only_rewritten_on_changes() {
+    let path = session_path("private_session");
+    let session = Session {
+        access_token: "secret_token".to_owned(),
+        user_id: user_id!("@bot:example.com").to_owned(),
+        device_id: device_id!("THREEMATRIX").to_owned(),
+    };
+
+    save_session(&path, &session, Some("s1")).unwrap();
+    let saved = metadata(&path).unwrap();
+    save_session(&path, &session, Some("s1")).unwrap();
+    let unchanged = metadata(&path).unwrap();
+    save_session(&path, &session, Some("s2")).unwrap();
+    let changed = metadata(&path).unwrap();
+    remove_file(&path).unwrap();
+
+    assert_eq!(saved.permissions().mode() & 0o777, 0o600);
+    // The file is replaced on every write
+    assert_eq!(unchanged.ino(), saved.ino());
+    assert_ne!(changed.ino(), saved.ino());
+    assert_eq!(changed.permissions().mode() & 0o777, 0o600);
+}
+
+#[test]
+fn missing_or_invalid_session_is_ignored() {
+    let path = session_path("invalid_session");
+    assert!(load_session(&path).is_none());
+
+    write(&path, "").unwrap();
+    assert!(load_session(&path).is_none());
+    remove_file(&path).unwrap();
+}
@@ -0,0 +1,31 @@
+use std::time::Duration;
+
+use threematrix::matrix::transaction_log::TransactionLog;
+
+#[test]
+fn processed_transactions_are_remembered() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let transaction_log = TransactionLog::open(&db).unwrap();
+
+    assert!(!transaction_log.contains("txn1").unwrap());
+    transaction_log.insert("txn1").unwrap();
+    assert!(transaction_log.contains("txn1").unwrap());
+    assert!(!transaction_log.contains("txn2").unwrap());
+
+    // Recent transactions are kept when the log is opened again
+    drop(transaction_log);
+    let transaction_log = TransactionLog::open(&db).unwrap();
+    assert!(transaction_log.contains("txn1").unwrap());
+}
+
+#[test]
+fn old_transactions_are_pruned() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let transaction_log = TransactionLog::open(&db).unwrap();
+    transaction_log.insert("txn1").unwrap();
+
+    assert_eq!(transaction_log.prune(Duration::from_secs(60)).unwrap(), 0);
+    std::thread::sleep(Duration::from_millis(1100));
+    assert_eq!(transaction_log.prune(Duration::ZERO).unwrap(), 1);
+    assert!(!transaction_log.contains("txn1").unwrap());
+}